@@ -1,97 +1,519 @@
-use std::net::{UdpSocket, SocketAddr};
+use std::collections::BTreeSet;
+use std::io::{Error, ErrorKind};
+use std::thread;
+use std::time::Duration;
 
 use strum::IntoEnumIterator;
 
 use crate::{
-    cmds::{self, TypeLengthValue, Cmd, CmdAttributes},
-    request::{self, Session},
-    Switch, response::Response,
+    cmds::{Cmd, TLVReadingError, TypeLengthValue},
+    config::{restore_stage, Change, SwitchConfig},
+    models::{readable_cmds, AuthScheme, ModelCapabilities, MAX_VLAN_ID},
+    response::Response,
+    values::{
+        flow_control_tlv, to_hex, CableTestResult, FirmwareBank, FirmwareInfo, IgmpSnooping,
+        Mirror, Port, PortInfo, SpeedSetting,
+    },
+    BlockingClient, Switch,
 };
 
+const CABLE_TEST_POLLS: u32 = 10;
+const CABLE_TEST_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
 pub struct ActionRunner<'a> {
-    socket: &'a UdpSocket,
+    client: &'a BlockingClient,
     switch: &'a Switch,
+    capabilities: Option<&'static ModelCapabilities>, //None for models missing from the table
 }
 
 impl<'a> ActionRunner<'a> {
-    pub fn new(socket: &'a UdpSocket, switch: &'a Switch) -> ActionRunner<'a> {
-        ActionRunner { socket, switch }
+    pub fn new(client: &'a BlockingClient, switch: &'a Switch) -> ActionRunner<'a> {
+        ActionRunner {
+            client,
+            switch,
+            capabilities: ModelCapabilities::lookup(&switch.model),
+        }
     }
 
     pub fn get_all_info(&self, password: &TypeLengthValue) {
-        
-        let resp = self.login(password);
+        let resp = self.transmit(password, Vec::new());
         println!("{:?}", resp);
 
         //Get actual info
 
-        let mut request_builder = request::Request::builder()
-            .ctype(cmds::ProtoConsts::QueryRequest.value().try_into().unwrap())
-            .session(Session::new_random_seq(
-                cmds::ProtoConsts::MACMyPC.value().try_into().unwrap(),
-                self.switch.mac_address,
+        let cmds = readable_cmds(&self.switch.model);
+
+        match self.query(&cmds) {
+            Ok(resp) => {
+                println!("Response:");
+                for tlv in resp.get_cmds() {
+                    println!("{:?}", tlv);
+                }
+            }
+            Err(err) => println!("Error: {}", err),
+        }
+    }
+
+    pub fn get_mirror(&self) -> Result<Mirror, Error> {
+        let resp = self.query(&[Cmd::CMD_Port_Mirroring])?;
+        Mirror::try_from(required_cmd(&resp, &Cmd::CMD_Port_Mirroring)?).map_err(invalid_data)
+    }
+
+    pub fn set_mirror(&self, password: &TypeLengthValue, mirror: &Mirror) -> Result<(), Error> {
+        let port_count = self.port_count()?;
+        mirror
+            .validate(port_count)
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+
+        self.transmit(password, vec![mirror.to_tlv(port_count)])?;
+        Ok(())
+    }
+
+    pub fn get_igmp(&self) -> Result<IgmpSnooping, Error> {
+        let resp = self.query(&IgmpSnooping::CMDS)?;
+        IgmpSnooping::from_tlvs(
+            required_cmd(&resp, &Cmd::CMD_IGMP_Snooping)?,
+            required_cmd(&resp, &Cmd::CMD_IGMP_Validate_IP_Header)?,
+            required_cmd(&resp, &Cmd::CMD_IGMP_Block_Unknown_Multicast)?,
+            required_cmd(&resp, &Cmd::CMD_IGMP_Router_Ports)?,
+        )
+        .map_err(invalid_data)
+    }
+
+    pub fn set_igmp(&self, password: &TypeLengthValue, igmp: &IgmpSnooping) -> Result<(), Error> {
+        let port_count = self.port_count()?;
+        check_ports(port_count, igmp.router_ports.iter())?;
+        //Switches with snooping off may report VLAN 0, which only matters once snooping is turned on
+        if igmp.enabled && !(1..=MAX_VLAN_ID).contains(&igmp.vlan) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("VLAN {} is out of range", igmp.vlan),
+            ));
+        }
+
+        self.transmit(password, igmp.to_tlvs(port_count))?;
+        Ok(())
+    }
+
+    pub fn get_loop_detection(&self) -> Result<bool, Error> {
+        let resp = self.query(&[Cmd::CMD_Loop_Detection])?;
+        bool::try_from(required_cmd(&resp, &Cmd::CMD_Loop_Detection)?).map_err(invalid_data)
+    }
+
+    pub fn set_loop_detection(
+        &self,
+        password: &TypeLengthValue,
+        enabled: bool,
+    ) -> Result<(), Error> {
+        self.transmit(
+            password,
+            vec![TypeLengthValue::from((Cmd::CMD_Loop_Detection, enabled))],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_ports(&self) -> Result<Vec<PortInfo>, Error> {
+        let port_count = self.port_count()?;
+        let resp = self.query(&PortInfo::CMDS)?;
+        PortInfo::from_tlvs(resp.get_cmds(), port_count).map_err(invalid_data)
+    }
+
+    //Settings that are None are left as they are
+    pub fn set_port(
+        &self,
+        password: &TypeLengthValue,
+        port: Port,
+        speed: Option<SpeedSetting>,
+        flow_control: Option<bool>,
+    ) -> Result<(), Error> {
+        check_ports(self.port_count()?, [port].into_iter())?;
+
+        let mut tlvs: Vec<TypeLengthValue> = Vec::new();
+        if let Some(speed) = speed {
+            tlvs.push(speed.to_tlv(port));
+        }
+        if let Some(flow_control) = flow_control {
+            tlvs.push(flow_control_tlv(port, flow_control));
+        }
+        if tlvs.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Nothing to change, pass at least one setting",
             ));
+        }
+
+        self.transmit(password, tlvs)?;
+        Ok(())
+    }
 
-        for cmd in Cmd::iter() {
-            if cmd.is_flag_set(CmdAttributes::READ_ONLY) {
-                request_builder = request_builder.add_cmd(TypeLengthValue::from(cmd));
+    //Starts the cable test and then polls until the switch has a result for the port. A result that can't be
+    //decoded won't get any better by polling again
+    pub fn cable_test(
+        &self,
+        password: &TypeLengthValue,
+        port: Port,
+    ) -> Result<CableTestResult, Error> {
+        check_ports(self.port_count()?, [port].into_iter())?;
+        self.transmit(password, vec![CableTestResult::request_tlv(port)])?;
+
+        for _ in 0..CABLE_TEST_POLLS {
+            thread::sleep(CABLE_TEST_POLL_INTERVAL);
+            let resp = self.query_tlvs(vec![CableTestResult::query_tlv(port)])?;
+            if let Ok(tlv) = resp.get_cmd(&Cmd::CMD_Cable_Test_Result) {
+                return CableTestResult::try_from(tlv).map_err(invalid_data);
             }
         }
 
-        let request = request_builder.build();
-            
+        Err(Error::new(
+            ErrorKind::TimedOut,
+            format!("No cable test result for port {}", port),
+        ))
+    }
+
+    pub fn get_firmware(&self) -> Result<FirmwareInfo, Error> {
+        let resp = self.query(&FirmwareInfo::CMDS)?;
+        FirmwareInfo::from_tlvs(
+            required_cmd(&resp, &Cmd::CMD_FW_Version)?,
+            resp.get_cmd(&Cmd::CMD_FW_Version_2).ok(),
+            resp.get_cmd(&Cmd::CMD_FW_Active).ok(),
+        )
+        .map_err(invalid_data)
+    }
 
-        println!("Request: {:?}", request);
+    //The switch restarts into its bootloader and fetches the new image over TFTP
+    pub fn enter_upgrade_mode(&self, password: &TypeLengthValue) -> Result<(), Error> {
+        self.transmit(
+            password,
+            vec![TypeLengthValue::from((Cmd::CMD_FW_Upgrade, vec![0x01]))],
+        )?;
+        Ok(())
+    }
 
-        let buf = request.format();
+    //Picks the image used from the next boot onwards, only images holding a version are accepted
+    pub fn set_active_firmware(
+        &self,
+        password: &TypeLengthValue,
+        bank: FirmwareBank,
+    ) -> Result<(), Error> {
+        let firmware = self.get_firmware()?;
+        if firmware.version(bank).is_none() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Firmware image {} is empty or not valid", bank),
+            ));
+        }
 
-        
+        self.transmit(password, vec![bank.to_tlv()])?;
+        Ok(())
+    }
 
-        self.socket
-            .send_to(buf.as_slice(), "255.255.255.255:63322")
-            .unwrap();
+    pub fn backup(&self) -> Result<SwitchConfig, Error> {
+        let port_count = self.port_count()?;
+        let resp = self.query(&SwitchConfig::query_cmds())?;
+        SwitchConfig::from_response(&resp, port_count).map_err(invalid_data)
+    }
 
-        let mut buf = [0; 1024];
-        let (usize, _) = self.socket.recv_from(&mut buf).unwrap();
+    //Writes the configuration back one restore stage per transmit request, returns the number of TLVs written
+    pub fn restore(
+        &self,
+        password: &TypeLengthValue,
+        config: &SwitchConfig,
+    ) -> Result<usize, Error> {
+        let port_count = self.port_count()?;
+        //A partial IGMP table still writes whole TLVs, the settings it leaves out are read from the switch.
+        //VLAN memberships replace the ones on the switch, so those are read to delete the VLANs left out
+        let replaces_vlans = config
+            .vlans
+            .as_ref()
+            .is_some_and(|vlans| !vlans.memberships.is_empty());
+        let live = if config.igmp.is_some() || replaces_vlans {
+            Some(self.backup()?)
+        } else {
+            None
+        };
+        let tlvs = config
+            .to_tlvs(live.as_ref(), port_count)
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
 
-        let resp = Response::build(&buf[..usize]).unwrap();
-        
-        println!("Response: {:?}", resp)
+        let after = match &live {
+            Some(live) => live.overlay(config),
+            None => config.clone(),
+        };
+        self.transmit_staged(password, &after, &tlvs)?;
+        Ok(tlvs.len())
+    }
 
+    //Changes apply would make, the settings are checked the same way so a clean plan doesn't fail to apply
+    pub fn plan(&self, desired: &SwitchConfig) -> Result<Vec<Change>, Error> {
+        let port_count = self.port_count()?;
+        let live = self.backup()?;
+        let merged = live.overlay(desired);
+        let changes = live.changes(&merged);
+        if !changes.is_empty() {
+            live.changed_tlvs(&merged, port_count)
+                .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+        }
+        Ok(changes)
     }
 
+    //Brings the switch to the desired configuration, only settings that differ are sent.
+    //Returns the changes that were needed, the switch is read again afterwards to check they stuck
+    pub fn apply(
+        &self,
+        password: &TypeLengthValue,
+        desired: &SwitchConfig,
+    ) -> Result<Vec<Change>, Error> {
+        let port_count = self.port_count()?;
+        let live = self.backup()?;
+        let merged = live.overlay(desired);
+        let changes = live.changes(&merged);
+        if changes.is_empty() {
+            return Ok(changes);
+        }
 
+        let tlvs = live
+            .changed_tlvs(&merged, port_count)
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+        self.transmit_staged(password, &merged, &tlvs)?;
 
-    fn login(&self, password: &TypeLengthValue) -> Result<Response, std::io::Error> {
-        let request = request::Request::builder()
-            .ctype(cmds::ProtoConsts::TransmitRequest.value().try_into().unwrap())
-            .session(Session::new_random_seq(
-                cmds::ProtoConsts::MACMyPC.value().try_into().unwrap(),
-                self.switch.mac_address,
+        let remaining = self.backup()?.changes(&merged);
+        if !remaining.is_empty() {
+            let keys: Vec<String> = remaining.into_iter().map(|change| change.key).collect();
+            return Err(Error::other(format!(
+                "Switch still differs after applying: {}",
+                keys.join(", ")
+            )));
+        }
+        Ok(changes)
+    }
+
+    //Sends TLVs already sorted in restore order, one transmit request per stage. After is the configuration the
+    //switch ends up with
+    fn transmit_staged(
+        &self,
+        password: &TypeLengthValue,
+        after: &SwitchConfig,
+        tlvs: &[(Cmd, TypeLengthValue)],
+    ) -> Result<(), Error> {
+        if tlvs.iter().any(|(cmd, _)| is_vlan_members(cmd)) {
+            self.check_vlans(after)?;
+        }
+        for stage in tlvs.chunk_by(|(a, _), (b, _)| restore_stage(a) == restore_stage(b)) {
+            self.transmit(password, stage.iter().map(|(_, tlv)| tlv.clone()).collect())?;
+        }
+        Ok(())
+    }
+
+    //Both passwords are sent encoded, the switch only takes the new one when the current one is right
+    pub fn set_password(
+        &self,
+        password: &TypeLengthValue,
+        new_password: Vec<u8>,
+    ) -> Result<(), Error> {
+        if new_password.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The new password cannot be empty",
+            ));
+        }
+
+        self.transmit(
+            password,
+            vec![TypeLengthValue::from((Cmd::CMD_New_Password, new_password))],
+        )?;
+        Ok(())
+    }
+
+    pub fn reboot(&self, password: &TypeLengthValue) -> Result<(), Error> {
+        self.transmit_before_restart(
+            password,
+            TypeLengthValue::from((Cmd::CMD_Reboot, vec![0x01])),
+        )
+    }
+
+    pub fn factory_reset(&self, password: &TypeLengthValue) -> Result<(), Error> {
+        self.transmit_before_restart(
+            password,
+            TypeLengthValue::from((Cmd::CMD_Factory_Reset, vec![0x01])),
+        )
+    }
+
+    //Bytes received and sent over all ports. Switches clear their counters when they restart
+    pub fn get_traffic_total(&self) -> Result<u64, Error> {
+        let resp = self.query(&[Cmd::CMD_Port_Statistics])?;
+        Ok(resp
+            .get_cmds()
+            .iter()
+            .filter(|tlv| tlv.cmd_equal_to(&Cmd::CMD_Port_Statistics))
+            .filter_map(|tlv| tlv.value().get(1..17))
+            .flat_map(|counters| counters.chunks_exact(8))
+            .map(|counter| u64::from_be_bytes(counter.try_into().unwrap()))
+            .fold(0, u64::saturating_add))
+    }
+
+    //Some firmwares restart before answering a reboot or factory reset, so a missing reply to those two is
+    //not treated as a failure
+    fn transmit_before_restart(
+        &self,
+        password: &TypeLengthValue,
+        tlv: TypeLengthValue,
+    ) -> Result<(), Error> {
+        let restarts =
+            tlv.cmd_equal_to(&Cmd::CMD_Reboot) || tlv.cmd_equal_to(&Cmd::CMD_Factory_Reset);
+        match self.transmit(password, vec![tlv]) {
+            Ok(_) => Ok(()),
+            Err(err)
+                if restarts
+                    && matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+            {
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    //What the switch reports, the model table only fills in when it doesn't
+    pub fn port_count(&self) -> Result<u8, Error> {
+        let resp = self.query(&[Cmd::CMD_Port_Count])?;
+        resp.get_cmd(&Cmd::CMD_Port_Count)
+            .ok()
+            .and_then(|tlv| tlv.value().first().copied())
+            .or(self.capabilities.map(|capabilities| capabilities.ports))
+            .ok_or(Error::new(
+                ErrorKind::InvalidData,
+                "Switch did not report its port count",
             ))
-            .add_cmd(password.clone())
-            .build();
+    }
 
-        println!("Request: {:?}", request);
+    //Reads the given TLVs from the switch
+    pub fn query(&self, cmds: &[Cmd]) -> Result<Response, Error> {
+        self.query_tlvs(cmds.iter().cloned().map(TypeLengthValue::from).collect())
+    }
 
-        let buf = request.format();
+    //Some TLVs need a value in the query, e.g. the port a result is wanted for.
+    //TLVs the model doesn't support are left out, a query left empty fails
+    pub fn query_tlvs(&self, tlvs: Vec<TypeLengthValue>) -> Result<Response, Error> {
+        let (supported, unsupported): (Vec<TypeLengthValue>, Vec<TypeLengthValue>) =
+            tlvs.into_iter().partition(|tlv| self.supports(tlv.cmd()));
+        if supported.is_empty() && !unsupported.is_empty() {
+            return Err(self.unsupported(&unsupported));
+        }
+        self.client.query_tlvs(self.switch, supported)
+    }
 
-        
+    //Writes the given TLVs to the switch, the password has to lead every transmit request
+    pub fn transmit(
+        &self,
+        password: &TypeLengthValue,
+        tlvs: Vec<TypeLengthValue>,
+    ) -> Result<Response, Error> {
+        if let Some(capabilities) = self.capabilities {
+            if capabilities.auth != AuthScheme::XorPassword {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!(
+                        "{} expects a hashed password, changing its settings is not supported",
+                        capabilities.model
+                    ),
+                ));
+            }
+        }
+        let unsupported: Vec<TypeLengthValue> = tlvs
+            .iter()
+            .filter(|tlv| !self.supports(tlv.cmd()))
+            .cloned()
+            .collect();
+        if !unsupported.is_empty() {
+            return Err(self.unsupported(&unsupported));
+        }
+        self.client.transmit(self.switch, password, tlvs)
+    }
 
-        self.socket
-            .send_to(buf.as_slice(), "255.255.255.255:63322")
-            .unwrap();
+    fn supports(&self, id: [u8; 2]) -> bool {
+        self.capabilities
+            .is_none_or(|capabilities| capabilities.supports_id(id))
+    }
 
-        let mut buf = [0; 128];
-        let resp = self.socket.recv_from(&mut buf);
+    fn unsupported(&self, tlvs: &[TypeLengthValue]) -> Error {
+        let names: Vec<String> = tlvs
+            .iter()
+            .map(|tlv| {
+                Cmd::iter()
+                    .find(|cmd| tlv.cmd_equal_to(cmd))
+                    .map_or(to_hex(&tlv.cmd()), |cmd| cmd.name())
+            })
+            .collect();
+        Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "{} does not support {}",
+                self.switch.model,
+                names.join(", ")
+            ),
+        )
+    }
 
-        match resp {
-            Ok((usize, _)) => {
-                return Ok(Response::build(&buf[..usize]).unwrap())
-            },
-            Err(kind) => {
-                return Err(kind);
-            },
+    //Refuses more VLANs than the model holds, the switch would only take some of them. Counted are the VLANs
+    //the switch ends up with, memberships written replace the ones it held
+    fn check_vlans(&self, after: &SwitchConfig) -> Result<(), Error> {
+        let vlans: BTreeSet<u16> = after
+            .vlans
+            .iter()
+            .flat_map(|vlans| &vlans.memberships)
+            .map(|membership| membership.id)
+            .collect();
+        if let Some(vlan) = vlans.iter().find(|vlan| !(1..=MAX_VLAN_ID).contains(*vlan)) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("VLAN {} is out of range", vlan),
+            ));
         }
+        match self.capabilities {
+            Some(capabilities) if vlans.len() > usize::from(capabilities.max_vlans) => {
+                Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "Switch would hold {} VLANs, {} holds at most {}",
+                        vlans.len(),
+                        capabilities.model,
+                        capabilities.max_vlans
+                    ),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn required_cmd(resp: &Response, cmd: &Cmd) -> Result<TypeLengthValue, Error> {
+    resp.get_cmd(cmd).map_err(|_| {
+        Error::new(
+            ErrorKind::NotFound,
+            format!("Switch did not return {:?}", cmd),
+        )
+    })
+}
+
+fn invalid_data(err: TLVReadingError) -> Error {
+    Error::new(ErrorKind::InvalidData, err.to_string())
+}
+
+fn check_ports(port_count: u8, mut ports: impl Iterator<Item = Port>) -> Result<(), Error> {
+    match ports.find(|port| port.number() > port_count) {
+        Some(port) => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Port {} does not exist, switch has {} ports",
+                port, port_count
+            ),
+        )),
+        None => Ok(()),
     }
 }
+
+fn is_vlan_members(cmd: &Cmd) -> bool {
+    matches!(
+        cmd,
+        Cmd::CMD_VLAN_Port_Members | Cmd::CMD_VLAN_8021Q_Members
+    )
+}
@@ -0,0 +1,97 @@
+use clap::Parser;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::thread;
+
+use pputl::cmds::Cmd;
+use pputl::emulator::{EmulatedSwitch, Emulator};
+use pputl::fixture::Fixture;
+use pputl::record::{load_captures, Replay};
+use pputl::values::format_mac;
+
+#[derive(Parser)]
+#[command(
+    name = "pputl-emulator",
+    about = "Serves emulated NSDP switches from a fixture file or recorded replies, for testing without hardware"
+)]
+struct Cli {
+    /// TOML file listing the switches, see fixtures/fleet.toml
+    #[arg(required_unless_present = "replay", conflicts_with = "replay")]
+    fixture: Option<PathBuf>,
+    /// Answer with the replies recorded by pputl --record in this directory instead
+    #[arg(long, value_name = "DIR")]
+    replay: Option<PathBuf>,
+    /// Address to answer on, 0.0.0.0:63322 also hears broadcast discovery
+    #[arg(long, default_value = "127.0.0.1:63322")]
+    listen: SocketAddr,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let emulator = match (&cli.fixture, &cli.replay) {
+        (Some(fixture), _) => serve_fixture(fixture, cli.listen),
+        (None, Some(dir)) => serve_replay(dir, cli.listen),
+        (None, None) => unreachable!("clap requires one of them"),
+    };
+    let emulator = match emulator {
+        Ok(emulator) => emulator,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    println!("Serving on {}, stop with Ctrl-C", emulator.local_addr());
+
+    //The emulator answers from its own thread until the process is stopped
+    loop {
+        thread::park();
+    }
+}
+
+fn serve_fixture(fixture: &Path, listen: SocketAddr) -> Result<Emulator, io::Error> {
+    let switches = fs::read_to_string(fixture)
+        .map_err(|err| err.to_string())
+        .and_then(|toml| Fixture::from_toml(&toml))
+        .and_then(|fixture| fixture.switches())
+        .map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", fixture.display(), err),
+            )
+        })?;
+
+    for switch in &switches {
+        println!(
+            "{} ({}) {}",
+            string_value(switch, &Cmd::CMD_Name),
+            string_value(switch, &Cmd::CMD_Model),
+            format_mac(&switch.mac())
+        );
+    }
+    println!("{} switches", switches.len());
+    Emulator::bind(listen, switches)
+}
+
+fn serve_replay(dir: &Path, listen: SocketAddr) -> Result<Emulator, io::Error> {
+    let exchanges = load_captures(dir)?;
+    println!(
+        "{} recorded exchanges from {}",
+        exchanges.len(),
+        dir.display()
+    );
+    let replay =
+        Replay::new(&exchanges).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    Emulator::replay(listen, replay)
+}
+
+fn string_value(switch: &EmulatedSwitch, cmd: &Cmd) -> String {
+    switch
+        .get(cmd)
+        .and_then(|values| values.first())
+        .map(|value| String::from_utf8_lossy(value).into_owned())
+        .unwrap_or_default()
+}
@@ -0,0 +1,526 @@
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::runtime::Runtime;
+
+use crate::cmds::{Cmd, ProtoConsts, TypeLengthValue};
+use crate::dispatch::{BindMode, Dispatcher, PortPair};
+use crate::record::Recorder;
+use crate::request::{Request, Session};
+use crate::response::Response;
+use crate::switch::Switch;
+
+//
+//Async NSDP client. Replies are routed by the dispatcher, so any number of requests can be in flight at once
+//from different tasks
+//
+
+pub const REPLY_TIMEOUT: Duration = Duration::from_millis(500);
+pub const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+//A probe is one discovery request, to one host on one port pair. Every probe in flight needs its own sequence number
+pub const MAX_PROBES: usize = 16384;
+
+//Takes the number of hosts rather than a list, so a huge range is refused before it is expanded
+pub fn check_probes(hosts: usize, port_pairs: usize) -> Result<(), Error> {
+    let probes = hosts.saturating_mul(port_pairs);
+    if probes > MAX_PROBES {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Probing {} hosts on {} port pairs is {} probes, more than the {} that fit at once, split the range",
+                hosts, port_pairs, probes, MAX_PROBES
+            ),
+        ));
+    }
+    Ok(())
+}
+
+pub struct Client {
+    dispatcher: Dispatcher,
+    broadcast_address: IpAddr,
+    port_pairs: Vec<PortPair>,
+    probe_hosts: Vec<IpAddr>,
+    unicast: bool,
+    reply_timeout: Duration,
+}
+
+impl Client {
+    //Binds the host side, usually 0.0.0.0:63321 as switches broadcast their replies to that port
+    pub async fn bind(addr: SocketAddr) -> Result<Client, Error> {
+        Client::bind_with(&[addr], BindMode::Exclusive).await
+    }
+
+    pub async fn bind_with(addrs: &[SocketAddr], mode: BindMode) -> Result<Client, Error> {
+        Ok(Client::new(Dispatcher::bind_with(addrs, mode).await?))
+    }
+
+    pub fn new(dispatcher: Dispatcher) -> Client {
+        Client {
+            dispatcher,
+            broadcast_address: IpAddr::V4(Ipv4Addr::BROADCAST),
+            port_pairs: vec![PortPair::V1, PortPair::V2],
+            probe_hosts: Vec::new(),
+            unicast: false,
+            reply_timeout: REPLY_TIMEOUT,
+        }
+    }
+
+    //Where requests are sent, the limited broadcast address by default
+    pub fn with_broadcast_address(mut self, address: IpAddr) -> Client {
+        self.broadcast_address = address;
+        self
+    }
+
+    //Port pairs discovery probes, switches found answer on the first pair they reply to
+    pub fn with_port_pairs(mut self, port_pairs: Vec<PortPair>) -> Client {
+        self.port_pairs = port_pairs;
+        self
+    }
+
+    //Discovery asks each of these hosts directly instead of broadcasting, for switches behind a router
+    pub fn with_probe_hosts(mut self, hosts: Vec<IpAddr>) -> Client {
+        self.probe_hosts = hosts;
+        self
+    }
+
+    //Sends requests to the IP of the switch instead of the broadcast address
+    pub fn with_unicast(mut self, unicast: bool) -> Client {
+        self.unicast = unicast;
+        self
+    }
+
+    pub fn with_reply_timeout(mut self, timeout: Duration) -> Client {
+        self.reply_timeout = timeout;
+        self
+    }
+
+    //Saves every request together with the reply it got
+    pub fn with_recorder(self, recorder: Recorder) -> Client {
+        self.dispatcher.set_recorder(recorder);
+        self
+    }
+
+    pub fn port_pairs(&self) -> &[PortPair] {
+        &self.port_pairs
+    }
+
+    pub fn local_addrs(&self) -> Result<Vec<SocketAddr>, Error> {
+        self.dispatcher.local_addrs()
+    }
+
+    //Asks every switch for its identity on every port pair and collects the replies arriving within the wait.
+    //Switches are asked by broadcast, or one by one when probe hosts are set
+    pub async fn discover(&self, wait: Duration) -> Result<Vec<Switch>, Error> {
+        let hosts = if self.probe_hosts.is_empty() {
+            vec![self.broadcast_address]
+        } else {
+            self.probe_hosts.clone()
+        };
+        check_probes(hosts.len(), self.port_pairs.len())?;
+
+        let mut requests: Vec<(Request, IpAddr, PortPair)> = Vec::new();
+        for host in hosts {
+            for port_pair in &self.port_pairs {
+                let request = Request::builder()
+                    .ctype(ProtoConsts::QueryRequest.value().try_into().unwrap())
+                    .session(
+                        self.new_session(ProtoConsts::MACBroadcast.value().try_into().unwrap()),
+                    )
+                    .add_cmd(TypeLengthValue::from(Cmd::CMD_Name))
+                    .add_cmd(TypeLengthValue::from(Cmd::CMD_Model))
+                    .add_cmd(TypeLengthValue::from(Cmd::CMD_Location))
+                    .add_cmd(TypeLengthValue::from(Cmd::CMD_IPv4))
+                    .build();
+                requests.push((request, host, *port_pair));
+            }
+        }
+
+        let mut replies = self.dispatcher.probe(&requests, wait).await?;
+        //Units speaking both pairs keep the one listed first
+        replies.sort_by_key(|(_, _, port_pair)| {
+            self.port_pairs
+                .iter()
+                .position(|listed| listed == port_pair)
+        });
+
+        let mut switches: Vec<Switch> = Vec::new();
+        for (reply, src_addr, port_pair) in replies {
+            let switch = reply
+                .ok()
+                .and_then(|response| Switch::from_response(&response, src_addr, port_pair));
+            if let Some(switch) = switch {
+                if !switches
+                    .iter()
+                    .any(|found| found.mac_address == switch.mac_address)
+                {
+                    switches.push(switch);
+                }
+            }
+        }
+        Ok(switches)
+    }
+
+    //Reads the given TLVs from the switch
+    pub async fn query(&self, switch: &Switch, cmds: &[Cmd]) -> Result<Response, Error> {
+        self.query_tlvs(
+            switch,
+            cmds.iter().cloned().map(TypeLengthValue::from).collect(),
+        )
+        .await
+    }
+
+    //Some TLVs need a value in the query, e.g. the port a result is wanted for
+    pub async fn query_tlvs(
+        &self,
+        switch: &Switch,
+        tlvs: Vec<TypeLengthValue>,
+    ) -> Result<Response, Error> {
+        let mut request_builder = Request::builder()
+            .ctype(ProtoConsts::QueryRequest.value().try_into().unwrap())
+            .session(self.new_session(switch.mac_address));
+        for tlv in tlvs {
+            request_builder = request_builder.add_cmd(tlv);
+        }
+
+        self.send_request(switch, request_builder.build(), ProtoConsts::QueryResponse)
+            .await
+    }
+
+    //Writes the given TLVs to the switch, the password has to lead every transmit request
+    pub async fn transmit(
+        &self,
+        switch: &Switch,
+        password: &TypeLengthValue,
+        tlvs: Vec<TypeLengthValue>,
+    ) -> Result<Response, Error> {
+        let mut request_builder = Request::builder()
+            .ctype(ProtoConsts::TransmitRequest.value().try_into().unwrap())
+            .session(self.new_session(switch.mac_address))
+            .add_cmd(password.clone());
+        for tlv in tlvs {
+            request_builder = request_builder.add_cmd(tlv);
+        }
+
+        self.send_request(
+            switch,
+            request_builder.build(),
+            ProtoConsts::TransmitResponse,
+        )
+        .await
+    }
+
+    async fn send_request(
+        &self,
+        switch: &Switch,
+        request: Request,
+        expected: ProtoConsts,
+    ) -> Result<Response, Error> {
+        let ip = if self.unicast {
+            switch.ip()
+        } else {
+            self.broadcast_address
+        };
+        let response = self
+            .dispatcher
+            .exchange(&request, ip, switch.port_pair, self.reply_timeout)
+            .await?;
+        check_response(response, expected)
+    }
+
+    fn new_session(&self, switch_mac: [u8; 6]) -> Session {
+        Session::new(
+            ProtoConsts::MACMyPC.value().try_into().unwrap(),
+            switch_mac,
+            self.dispatcher.next_seq(),
+        )
+    }
+}
+
+fn check_response(resp: Response, expected: ProtoConsts) -> Result<Response, Error> {
+    if resp.get_ctype() != expected.value() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Unexpected response type {:02x?}", resp.get_ctype()),
+        ));
+    }
+    if !resp.is_success() {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!(
+                "Switch rejected request (status {:02x?})",
+                resp.get_status()
+            ),
+        ));
+    }
+    Ok(resp)
+}
+
+//Blocking facade over the async client for callers without a runtime, e.g. the command line tool.
+//It is safe to share between threads, every call waits only for its own reply
+pub struct BlockingClient {
+    client: Client,
+    runtime: Runtime,
+}
+
+impl BlockingClient {
+    pub fn bind(addr: SocketAddr) -> Result<BlockingClient, Error> {
+        BlockingClient::bind_with(&[addr], BindMode::Exclusive)
+    }
+
+    pub fn bind_with(addrs: &[SocketAddr], mode: BindMode) -> Result<BlockingClient, Error> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()?;
+        let client = runtime.block_on(Client::bind_with(addrs, mode))?;
+        Ok(BlockingClient { client, runtime })
+    }
+
+    pub fn with_broadcast_address(mut self, address: IpAddr) -> BlockingClient {
+        self.client = self.client.with_broadcast_address(address);
+        self
+    }
+
+    pub fn with_port_pairs(mut self, port_pairs: Vec<PortPair>) -> BlockingClient {
+        self.client = self.client.with_port_pairs(port_pairs);
+        self
+    }
+
+    pub fn with_probe_hosts(mut self, hosts: Vec<IpAddr>) -> BlockingClient {
+        self.client = self.client.with_probe_hosts(hosts);
+        self
+    }
+
+    pub fn with_unicast(mut self, unicast: bool) -> BlockingClient {
+        self.client = self.client.with_unicast(unicast);
+        self
+    }
+
+    pub fn with_reply_timeout(mut self, timeout: Duration) -> BlockingClient {
+        self.client = self.client.with_reply_timeout(timeout);
+        self
+    }
+
+    pub fn with_recorder(mut self, recorder: Recorder) -> BlockingClient {
+        self.client = self.client.with_recorder(recorder);
+        self
+    }
+
+    pub fn port_pairs(&self) -> &[PortPair] {
+        self.client.port_pairs()
+    }
+
+    pub fn local_addrs(&self) -> Result<Vec<SocketAddr>, Error> {
+        self.client.local_addrs()
+    }
+
+    pub fn discover(&self, wait: Duration) -> Result<Vec<Switch>, Error> {
+        self.runtime.block_on(self.client.discover(wait))
+    }
+
+    pub fn query(&self, switch: &Switch, cmds: &[Cmd]) -> Result<Response, Error> {
+        self.runtime.block_on(self.client.query(switch, cmds))
+    }
+
+    pub fn query_tlvs(
+        &self,
+        switch: &Switch,
+        tlvs: Vec<TypeLengthValue>,
+    ) -> Result<Response, Error> {
+        self.runtime.block_on(self.client.query_tlvs(switch, tlvs))
+    }
+
+    pub fn transmit(
+        &self,
+        switch: &Switch,
+        password: &TypeLengthValue,
+        tlvs: Vec<TypeLengthValue>,
+    ) -> Result<Response, Error> {
+        self.runtime
+            .block_on(self.client.transmit(switch, password, tlvs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::values::format_mac;
+    use std::net::UdpSocket as StdUdpSocket;
+    use std::thread;
+
+    const SWITCH_A: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x0a];
+    const SWITCH_B: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x0b];
+
+    //Answers as the switch the request was meant for, or as `mac` for broadcasts, with its MAC as the name
+    fn reply_to(request: &[u8], mac: [u8; 6]) -> Vec<u8> {
+        let mut reply = request[..32].to_vec();
+        reply[0..2].copy_from_slice(ProtoConsts::QueryResponse.value());
+        if reply[14..20] == [0x00; 6] {
+            reply[14..20].copy_from_slice(&mac);
+        }
+        let name = format_mac(&reply[14..20].try_into().unwrap());
+        for tlv in [
+            TypeLengthValue::from((Cmd::CMD_Name, name.as_bytes().to_vec())),
+            TypeLengthValue::from((Cmd::CMD_Model, b"GS105E".to_vec())),
+            TypeLengthValue::from((Cmd::CMD_Location, Vec::new())),
+            TypeLengthValue::from((Cmd::CMD_IPv4, vec![192, 168, 0, mac[5]])),
+        ] {
+            reply.extend(tlv.to_raw());
+        }
+        reply.extend_from_slice(ProtoConsts::EndOfMessage.value());
+        reply
+    }
+
+    //Reads requests `count` at a time and answers each batch in reverse order
+    fn fake_switches(count: usize) -> SocketAddr {
+        let socket = StdUdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || loop {
+            let mut requests = Vec::new();
+            let mut buf = [0; 2048];
+            while requests.len() < count {
+                let (len, src_addr) = socket.recv_from(&mut buf).unwrap();
+                requests.push((buf[..len].to_vec(), src_addr));
+            }
+            for (request, src_addr) in requests.iter().rev() {
+                for mac in [SWITCH_A, SWITCH_B] {
+                    socket.send_to(&reply_to(request, mac), src_addr).unwrap();
+                }
+            }
+        });
+        addr
+    }
+
+    //Requests to the fake switches leave from whichever socket the client has
+    fn port_pair(target: SocketAddr) -> PortPair {
+        PortPair {
+            host: 0,
+            switch: target.port(),
+        }
+    }
+
+    fn blocking_client(port_pairs: Vec<PortPair>) -> BlockingClient {
+        BlockingClient::bind((Ipv4Addr::LOCALHOST, 0).into())
+            .unwrap()
+            .with_broadcast_address(IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .with_port_pairs(port_pairs)
+    }
+
+    fn switch(mac: [u8; 6], target: SocketAddr) -> Switch {
+        Switch {
+            name: format_mac(&mac),
+            model: String::from("GS105E"),
+            location: String::new(),
+            ipv4_address_reported: Ipv4Addr::LOCALHOST,
+            ipv4_address: target.to_string(),
+            mac_address: mac,
+            port_pair: port_pair(target),
+        }
+    }
+
+    fn name(response: &Response) -> String {
+        response
+            .get_cmd(&Cmd::CMD_Name)
+            .unwrap()
+            .try_into()
+            .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn replies_reach_the_request_they_answer() {
+        let target = fake_switches(2);
+        let client = Client::bind((Ipv4Addr::LOCALHOST, 0).into())
+            .await
+            .unwrap()
+            .with_broadcast_address(IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+        let (a, b) = (switch(SWITCH_A, target), switch(SWITCH_B, target));
+        let (a, b) = tokio::join!(
+            client.query(&a, &[Cmd::CMD_Name]),
+            client.query(&b, &[Cmd::CMD_Name])
+        );
+        assert_eq!(name(&a.unwrap()), format_mac(&SWITCH_A));
+        assert_eq!(name(&b.unwrap()), format_mac(&SWITCH_B));
+    }
+
+    #[test]
+    fn discovery_collects_every_switch_once() {
+        let target = fake_switches(1);
+        let client = blocking_client(vec![port_pair(target)]);
+
+        let switches = client.discover(Duration::from_millis(300)).unwrap();
+        let macs: Vec<[u8; 6]> = switches.iter().map(|switch| switch.mac_address).collect();
+        assert_eq!(macs, [SWITCH_A, SWITCH_B]);
+    }
+
+    #[test]
+    fn discovery_keeps_the_port_pair_switches_answer_on() {
+        let silent = StdUdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let target = fake_switches(1);
+        let client = blocking_client(vec![
+            port_pair(silent.local_addr().unwrap()),
+            port_pair(target),
+        ]);
+
+        let switches = client.discover(Duration::from_millis(300)).unwrap();
+        assert_eq!(switches.len(), 2);
+        assert!(switches
+            .iter()
+            .all(|switch| switch.port_pair == port_pair(target)));
+    }
+
+    #[test]
+    fn unanswered_requests_time_out() {
+        let silent = StdUdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let client = blocking_client(Vec::new()).with_reply_timeout(Duration::from_millis(100));
+
+        let err = client
+            .query(
+                &switch(SWITCH_A, silent.local_addr().unwrap()),
+                &[Cmd::CMD_Name],
+            )
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn port_pairs_show_host_and_switch_port() {
+        assert_eq!(PortPair::V1.to_string(), "63321/63322");
+        assert_eq!(PortPair::V2.to_string(), "63323/63324");
+    }
+
+    #[test]
+    fn probes_and_unicast_requests_go_to_the_switch_itself() {
+        let target = fake_switches(1);
+        let client = BlockingClient::bind((Ipv4Addr::LOCALHOST, 0).into())
+            .unwrap()
+            .with_port_pairs(vec![port_pair(target)])
+            .with_probe_hosts(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)])
+            .with_unicast(true);
+
+        let switches = client.discover(Duration::from_millis(300)).unwrap();
+        assert_eq!(switches.len(), 2);
+        assert_eq!(switches[0].ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let response = client.query(&switches[0], &[Cmd::CMD_Name]).unwrap();
+        assert_eq!(name(&response), switches[0].name);
+    }
+
+    #[test]
+    fn oversized_probe_lists_are_refused() {
+        let hosts = vec![IpAddr::V4(Ipv4Addr::LOCALHOST); MAX_PROBES];
+        let client = blocking_client(vec![PortPair::V1, PortPair::V2]).with_probe_hosts(hosts);
+
+        let err = client.discover(Duration::from_millis(10)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn probes_count_every_host_on_every_port_pair() {
+        assert!(check_probes(MAX_PROBES / 2, 2).is_ok());
+        let err = check_probes(MAX_PROBES / 2 + 1, 2).unwrap_err();
+        assert!(err.to_string().contains("16386 probes"), "{}", err);
+        assert!(check_probes(usize::MAX, 2).is_err());
+    }
+}
@@ -1,10 +1,12 @@
-use std::{net::Ipv4Addr, string::FromUtf8Error};
-use strum_macros::EnumIter;
 use bitflags::bitflags;
+use std::{fmt, net::Ipv4Addr, string::FromUtf8Error};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
 
 //Section is for constants used in transmission and testing purposes
 //Flags are not needed because use is fixed and also automation is not needed
 
+#[allow(clippy::upper_case_acronyms)]
 pub enum ProtoConsts {
     QueryRequest,
     TransmitRequest,
@@ -23,7 +25,7 @@ impl ProtoConsts {
             ProtoConsts::QueryRequest => &[0x01, 0x01],
             ProtoConsts::TransmitRequest => &[0x01, 0x03],
             ProtoConsts::QueryResponse => &[0x01, 0x02],
-            ProtoConsts::TransmitResponse => &[0x10, 0x04],
+            ProtoConsts::TransmitResponse => &[0x01, 0x04],
 
             ProtoConsts::NDSP => &[0x4E, 0x53, 0x44, 0x50],
             ProtoConsts::Reserved => &[0x00, 0x00],
@@ -40,70 +42,112 @@ impl ProtoConsts {
 //Stored as U32 allowing for lower 2 bytes being the command and upper 2 bytes being the flags
 //
 
-#[derive(Debug, Clone, EnumIter)]
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, PartialEq, Eq, EnumIter)]
 #[repr(u32)]
 pub enum Cmd {
     CMD_Model = u32([0x00, 0x01]),
     CMD_0002 = u32([0x00, 0x02]),
-    CMD_Name = u32([0x00, 0x03]),
+    CMD_Name = u32([0x00, 0x03]) | CmdAttributes::READ_WRITE.bits(),
     CMD_Switch_MAC = u32([0x00, 0x04]),
-    CMD_Location = u32([0x00, 0x05]),
-    CMD_IPv4 = u32([0x00, 0x06]),
-    CMD_Switch_Netmask = u32([0x00, 0x07]),
-    CMD_Switch_Gateway = u32([0x00, 0x08]),
+    CMD_Location = u32([0x00, 0x05]) | CmdAttributes::READ_WRITE.bits(),
+    CMD_IPv4 = u32([0x00, 0x06]) | CmdAttributes::READ_WRITE.bits(),
+    CMD_Switch_Netmask = u32([0x00, 0x07]) | CmdAttributes::READ_WRITE.bits(),
+    CMD_Switch_Gateway = u32([0x00, 0x08]) | CmdAttributes::READ_WRITE.bits(),
+    CMD_New_Password = u32([0x00, 0x09]) | CmdAttributes::WRITE_ONLY.bits(),
     CMD_Password = u32([0x00, 0x0a]) | CmdAttributes::WRITE_ONLY.bits(),
-    CMD_Switch_DHCP = u32([0x00, 0x0b]),
+    CMD_Switch_DHCP = u32([0x00, 0x0b]) | CmdAttributes::READ_WRITE.bits(),
     CMD_000C = u32([0x00, 0x0c]),
     CMD_FW_Version = u32([0x00, 0x0d]),
     CMD_FW_Version_2 = u32([0x00, 0x0e]),
-    CMD_FW_Active = u32([0x00, 0x0f]),
+    CMD_FW_Active = u32([0x00, 0x0f]) | CmdAttributes::READ_WRITE.bits(),
+    CMD_FW_Upgrade = u32([0x00, 0x10]) | CmdAttributes::WRITE_ONLY.bits(), //01 to enter upgrade mode
+
+    CMD_Reboot = u32([0x00, 0x13]) | CmdAttributes::WRITE_ONLY.bits(), //01 to reboot
 
     CMD_0014 = u32([0x00, 0x14]), //Usually 4 bytes like 00 00 00 01
 
-    CMD_0C00 = u32([0x0C, 0x00]), //Response: n copies of 3 byte TLVs where n is port count
-    CMD_1000 = u32([0x10, 0x00]), //Response: n copies of 49 byte TLVs where n is port count
+    CMD_Factory_Reset = u32([0x04, 0x00]) | CmdAttributes::WRITE_ONLY.bits(), //01 to reset
 
-    CMD_2000 = u32([0x20, 0x00]), //Usually 1 byte like 00
+    CMD_Port_Status = u32([0x0C, 0x00]), //Response: n copies of 3 byte TLVs where n is port count
+    CMD_Port_Statistics = u32([0x10, 0x00]), //Response: n copies of 49 byte TLVs where n is port count
 
-    CMD_3400 = u32([0x34, 0x00]), //Usually 1 byte like 02
+    CMD_Cable_Test_Request = u32([0x18, 0x00]) | CmdAttributes::WRITE_ONLY.bits(), //Port, 01 to start the test
+    CMD_Cable_Test_Result = u32([0x1c, 0x00]), //Queried with the port, response: port, then 4 byte status and 4 byte fault distance per pair
 
-    CMD_4C00 = u32([0x4c, 0x00]), //Response: n copies of 5 byte TLVs where n is port count
+    CMD_VLAN_Mode = u32([0x20, 0x00]) | CmdAttributes::READ_WRITE.bits(), //1 byte, 00 disabled up to 04 advanced 802.1Q
+    CMD_VLAN_Port_Members = u32([0x24, 0x00]) | CmdAttributes::READ_WRITE.bits(), //One TLV per VLAN: 2 byte VLAN ID, member bitmap
+    CMD_VLAN_8021Q_Members = u32([0x28, 0x00]) | CmdAttributes::READ_WRITE.bits(), //One TLV per VLAN: 2 byte VLAN ID, member bitmap, tagged bitmap
+    CMD_VLAN_Delete = u32([0x2c, 0x00]) | CmdAttributes::WRITE_ONLY.bits(), //2 byte VLAN ID to remove
+    CMD_VLAN_PVID = u32([0x30, 0x00]) | CmdAttributes::READ_WRITE.bits(), //n copies of 3 byte TLVs (port, 2 byte VLAN ID)
 
-    CMD_5000 = u32([0x50, 0x00]), //Response: n copies of 5 byte TLVs where n is port count
-    CMD_5400 = u32([0x54, 0x00]), //Usually 1 byte like 00
-    CMD_5C00 = u32([0x5c, 0x00]), //Usually 3 bytes like 00 00 00
+    CMD_QoS_Mode = u32([0x34, 0x00]) | CmdAttributes::READ_WRITE.bits(), //1 byte, 01 port based, 02 802.1p
+    CMD_QoS_Port_Priority = u32([0x38, 0x00]) | CmdAttributes::READ_WRITE.bits(), //n copies of 2 byte TLVs (port, priority)
+
+    CMD_Ingress_Rate_Limit = u32([0x4c, 0x00]) | CmdAttributes::READ_WRITE.bits(), //Response: n copies of 5 byte TLVs where n is port count
+
+    CMD_Egress_Rate_Limit = u32([0x50, 0x00]) | CmdAttributes::READ_WRITE.bits(), //Response: n copies of 5 byte TLVs where n is port count
+    CMD_Storm_Control = u32([0x54, 0x00]) | CmdAttributes::READ_WRITE.bits(),     //1 byte flag
+    CMD_Storm_Control_Rate = u32([0x58, 0x00]) | CmdAttributes::READ_WRITE.bits(), //n copies of 5 byte TLVs where n is port count
+    CMD_Port_Mirroring = u32([0x5c, 0x00]) | CmdAttributes::READ_WRITE.bits(), //Destination port, 00, source port bitmap
 
     CMD_Port_Count = u32([0x60, 0x00]),
 
     CMD_6400 = u32([0x64, 0x00]), //Usually 2 bytes like 00 20
-    CMD_6800 = u32([0x68, 0x00]), //Usually 4 bytes like 00 01 00 01
-    CMD_6C00 = u32([0x6C, 0x00]), //Usually 1 byte like 00
+    CMD_IGMP_Snooping = u32([0x68, 0x00]) | CmdAttributes::READ_WRITE.bits(), //00, enabled flag, 2 byte VLAN ID
+    CMD_IGMP_Block_Unknown_Multicast = u32([0x6C, 0x00]) | CmdAttributes::READ_WRITE.bits(), //1 byte flag
 
-    CMD_7000 = u32([0x70, 0x00]), //Usually 1 byte like 00
+    CMD_IGMP_Validate_IP_Header = u32([0x70, 0x00]) | CmdAttributes::READ_WRITE.bits(), //1 byte flag
     CMD_7400 = u32([0x74, 0x00]), //Usually 8 bytes like 00 00 00 08 7f fc ff ff
     CMD_7800 = u32([0x78, 0x00]), //Usually 21 bytes like 01 30 30 31 31 31 31 31 31 31 31 31 31 31 00 00 1 20 10 00 a2
     CMD_7C00 = u32([0x7c, 0x00]), //Usually 1 byte like 01
 
-    CMD_9000 = u32([0x90, 0x00]), //Usually 1 byte like 00
+    CMD_IGMP_Router_Ports = u32([0x80, 0x00]) | CmdAttributes::READ_WRITE.bits(), //Port bitmap
+
+    CMD_Loop_Detection = u32([0x90, 0x00]) | CmdAttributes::READ_WRITE.bits(), //1 byte flag
+
+    CMD_Port_Speed_Config = u32([0x94, 0x00]) | CmdAttributes::READ_WRITE.bits(), //n copies of 2 byte TLVs (port, speed setting) where n is port count
+    CMD_Port_Flow_Control = u32([0x98, 0x00]) | CmdAttributes::READ_WRITE.bits(), //n copies of 2 byte TLVs (port, flag) where n is port count
 }
 
 impl Cmd {
+    //Attributes live in the upper 2 bytes, a Cmd without any is read only
+    pub fn attributes(&self) -> CmdAttributes {
+        CmdAttributes::from_bits_truncate(self.clone() as u32)
+    }
+
     pub fn is_flag_set(&self, flag: CmdAttributes) -> bool {
-        flag.bits() == (self.clone() as u32 | flag.bits())
+        self.attributes() == flag
+    }
+
+    pub fn is_readable(&self) -> bool {
+        !self.is_flag_set(CmdAttributes::WRITE_ONLY)
+    }
+
+    pub fn is_writable(&self) -> bool {
+        !self.is_flag_set(CmdAttributes::READ_ONLY)
+    }
+
+    //Name as written in the source, used as the key in config files
+    pub fn name(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    pub fn from_name(name: &str) -> Option<Cmd> {
+        Cmd::iter().find(|cmd| cmd.name() == name)
     }
 }
 
-impl Into<[u8; 2]> for Cmd {
-    fn into(self) -> [u8; 2] {
-        let raw = self as u16;
-        (&[(raw >> 8) as u8, raw as u8]).clone()
+impl From<Cmd> for [u8; 2] {
+    fn from(cmd: Cmd) -> Self {
+        let raw = cmd as u16;
+        [(raw >> 8) as u8, raw as u8]
     }
 }
 
-impl Into<[u8; 2]> for &Cmd {
-    fn into(self) -> [u8; 2] {
-        let raw = self.clone() as u16;
-        (&[(raw >> 8) as u8, raw as u8]).clone()
+impl From<&Cmd> for [u8; 2] {
+    fn from(cmd: &Cmd) -> Self {
+        cmd.clone().into()
     }
 }
 
@@ -114,9 +158,9 @@ const fn u32(arr: [u8; 2]) -> u32 {
 bitflags! {
     #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
     pub struct CmdAttributes: u32 {
-        const READ_ONLY = (0b0000000000000000 as u32) << 16 | 0x0000FFFF;
-        const WRITE_ONLY = (0b0000000000000001 as u32) << 16 | 0x0000FFFF;
-        const READ_WRITE = (0b0000000000000010 as u32) << 16 | 0x0000FFFF;
+        const READ_ONLY = 0b0000000000000000_u32 << 16;
+        const WRITE_ONLY = 0b0000000000000001_u32 << 16;
+        const READ_WRITE = 0b0000000000000010_u32 << 16;
     }
 }
 
@@ -132,7 +176,17 @@ pub enum TLVReadingError {
     EndOfMessage,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+impl fmt::Display for TLVReadingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TLVReadingError::ArrTooShort(msg) => write!(f, "Message too short: {}", msg),
+            TLVReadingError::InvalidType(msg) => write!(f, "Invalid value: {}", msg),
+            TLVReadingError::EndOfMessage => write!(f, "End of message"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
 pub struct TypeLengthValue {
     cmd: [u8; 2],
     len: u16,
@@ -153,7 +207,7 @@ impl TypeLengthValue {
 
         let len_ar: [u8; 2] = raw[2..4].try_into().unwrap();
         let len: u16 = u16::from(len_ar[0]) << 8 | (len_ar[1] as u16);
-        let len_index: usize = (len + 4).try_into().unwrap();
+        let len_index: usize = usize::from(len) + 4;
 
         if raw.len() < len_index {
             return Err(TLVReadingError::ArrTooShort(String::from(
@@ -164,7 +218,7 @@ impl TypeLengthValue {
         Ok((
             TypeLengthValue {
                 cmd: raw[0..2].try_into().unwrap(),
-                len: len,
+                len,
                 value: Vec::from(&raw[4..len_index]),
             },
             len_index,
@@ -186,15 +240,13 @@ impl TypeLengthValue {
         }
         false
     }
-}
 
-impl Default for TypeLengthValue {
-    fn default() -> Self {
-        Self {
-            cmd: [0; 2],
-            len: 0,
-            value: Default::default(),
-        }
+    pub fn cmd(&self) -> [u8; 2] {
+        self.cmd
+    }
+
+    pub fn value(&self) -> &[u8] {
+        &self.value
     }
 }
 
@@ -218,7 +270,7 @@ impl From<(Cmd, u16, Vec<u8>)> for TypeLengthValue {
         Self {
             cmd: cmd_enum.into(),
             len,
-            value: value,
+            value,
         }
     }
 }
@@ -228,7 +280,7 @@ impl From<(Cmd, Vec<u8>)> for TypeLengthValue {
         Self {
             cmd: cmd_enum.into(),
             len: value.len() as u16,
-            value: value,
+            value,
         }
     }
 }
@@ -247,7 +299,7 @@ impl From<([u8; 2], u16, Vec<u8>)> for TypeLengthValue {
         Self {
             cmd: cmd_enum,
             len,
-            value: value,
+            value,
         }
     }
 }
@@ -260,6 +312,31 @@ impl TryInto<String> for TypeLengthValue {
     type Error = FromUtf8Error;
 }
 
+impl From<(Cmd, bool)> for TypeLengthValue {
+    fn from((cmd_enum, flag): (Cmd, bool)) -> Self {
+        Self::from((cmd_enum, vec![flag as u8]))
+    }
+}
+
+impl TryFrom<TypeLengthValue> for bool {
+    type Error = TLVReadingError;
+
+    fn try_from(tlv: TypeLengthValue) -> Result<Self, Self::Error> {
+        match tlv.value.first() {
+            Some(flag) => Ok(*flag != 0),
+            None => Err(TLVReadingError::InvalidType(String::from(
+                "Invalid type for flag",
+            ))),
+        }
+    }
+}
+
+impl From<(Cmd, Ipv4Addr)> for TypeLengthValue {
+    fn from((cmd_enum, addr): (Cmd, Ipv4Addr)) -> Self {
+        Self::from((cmd_enum, addr.octets().to_vec()))
+    }
+}
+
 impl TryInto<Ipv4Addr> for TypeLengthValue {
     type Error = TLVReadingError;
 
@@ -277,3 +354,45 @@ impl TryInto<Ipv4Addr> for TypeLengthValue {
         )))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_are_one_byte() {
+        let tlv = TypeLengthValue::from((Cmd::CMD_IGMP_Block_Unknown_Multicast, true));
+        assert_eq!(tlv.value(), &[0x01]);
+        assert!(bool::try_from(tlv).unwrap());
+        //Any non zero byte reads as set
+        let flag = |value: Vec<u8>| {
+            bool::try_from(TypeLengthValue::from((
+                Cmd::CMD_IGMP_Validate_IP_Header,
+                value,
+            )))
+        };
+        assert!(flag(vec![0x02]).unwrap());
+        assert!(!flag(vec![0x00]).unwrap());
+        assert!(flag(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn loop_detection_is_the_9000_flag() {
+        let tlv = TypeLengthValue::from((Cmd::CMD_Loop_Detection, true));
+        assert_eq!(tlv.to_raw(), vec![0x90, 0x00, 0x00, 0x01, 0x01]);
+    }
+
+    #[test]
+    fn restart_commands_are_write_only() {
+        for cmd in [
+            Cmd::CMD_Reboot,
+            Cmd::CMD_Factory_Reset,
+            Cmd::CMD_Cable_Test_Request,
+        ] {
+            assert_eq!(cmd.attributes(), CmdAttributes::WRITE_ONLY);
+            assert!(cmd.is_flag_set(CmdAttributes::WRITE_ONLY));
+        }
+        assert_eq!(Cmd::CMD_Model.attributes(), CmdAttributes::READ_ONLY);
+        assert_eq!(<[u8; 2]>::from(Cmd::CMD_Factory_Reset), [0x04, 0x00]);
+    }
+}
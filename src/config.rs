@@ -0,0 +1,1197 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::net::Ipv4Addr;
+
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+
+use crate::cmds::{Cmd, TLVReadingError, TypeLengthValue};
+use crate::response::Response;
+use crate::values::{
+    flow_control_tlv, format_mac, from_hex, port_records, to_hex, IgmpSnooping, Mirror, Port,
+    PortSet, SpeedSetting, VlanMembership,
+};
+
+//
+//Switch configuration as stored in backup files. Every section is optional so the same layout
+//can describe a partial configuration, settings without a typed section are kept as raw hex TLVs
+//
+
+pub const CONFIG_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct SwitchConfig {
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loop_detection: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub switch: Option<SwitchIdentity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<SystemConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<MirrorConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub igmp: Option<IgmpConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vlans: Option<VlanConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qos: Option<QosConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limits: Option<RateLimitConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<PortConfig>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tlvs: BTreeMap<String, Vec<String>>,
+}
+
+//Which switch the configuration was read from, never written back
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct SwitchIdentity {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firmware: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct SystemConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dhcp: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<Ipv4Addr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub netmask: Option<Ipv4Addr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<Ipv4Addr>,
+}
+
+//Destination 0 turns mirroring off
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct MirrorConfig {
+    pub destination: u8,
+    #[serde(default)]
+    pub sources: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct IgmpConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vlan: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validate_ip_header: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_unknown_multicast: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub router_ports: Option<String>,
+}
+
+//Mode 0 turns VLANs off, 4 is advanced 802.1Q. Memberships given replace the ones on the switch as a whole,
+//VLANs left out are deleted
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct VlanConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub memberships: Vec<VlanMembershipConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<VlanPortConfig>,
+}
+
+//Tagged ports are only given for 802.1Q VLANs, port based VLANs leave them out
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct VlanMembershipConfig {
+    pub id: u16,
+    #[serde(default)]
+    pub members: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tagged: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct VlanPortConfig {
+    pub port: u8,
+    pub pvid: u16,
+}
+
+//Mode 1 is port based, 2 is 802.1p
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct QosConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<QosPortConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct QosPortConfig {
+    pub port: u8,
+    pub priority: u8,
+}
+
+//Rates are the steps the switch offers, 0 means no limit
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storm_control: Option<bool>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<RateLimitPortConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct RateLimitPortConfig {
+    pub port: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ingress: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub egress: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storm: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct PortConfig {
+    pub port: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flow_control: Option<bool>,
+}
+
+//TLVs with a typed section above, everything else readable and writable ends up in the raw tlvs table
+const TYPED_CMDS: [Cmd; 25] = [
+    Cmd::CMD_Name,
+    Cmd::CMD_Location,
+    Cmd::CMD_Switch_DHCP,
+    Cmd::CMD_IPv4,
+    Cmd::CMD_Switch_Netmask,
+    Cmd::CMD_Switch_Gateway,
+    Cmd::CMD_Port_Mirroring,
+    Cmd::CMD_IGMP_Snooping,
+    Cmd::CMD_IGMP_Validate_IP_Header,
+    Cmd::CMD_IGMP_Block_Unknown_Multicast,
+    Cmd::CMD_IGMP_Router_Ports,
+    Cmd::CMD_VLAN_Mode,
+    Cmd::CMD_VLAN_Port_Members,
+    Cmd::CMD_VLAN_8021Q_Members,
+    Cmd::CMD_VLAN_PVID,
+    Cmd::CMD_QoS_Mode,
+    Cmd::CMD_QoS_Port_Priority,
+    Cmd::CMD_Ingress_Rate_Limit,
+    Cmd::CMD_Egress_Rate_Limit,
+    Cmd::CMD_Storm_Control,
+    Cmd::CMD_Storm_Control_Rate,
+    Cmd::CMD_Loop_Detection,
+    Cmd::CMD_Port_Speed_Config,
+    Cmd::CMD_Port_Flow_Control,
+    Cmd::CMD_FW_Active,
+];
+
+//Settings that are read and written back as configuration, the firmware bank is left alone
+pub fn config_cmds() -> Vec<Cmd> {
+    Cmd::iter()
+        .filter(|cmd| cmd.is_readable() && cmd.is_writable())
+        .filter(|cmd| *cmd != Cmd::CMD_FW_Active)
+        .collect()
+}
+
+fn raw_cmds() -> Vec<Cmd> {
+    config_cmds()
+        .into_iter()
+        .filter(|cmd| !TYPED_CMDS.contains(cmd))
+        .collect()
+}
+
+//Order restores go out in, later stages depend on earlier ones (VLAN mode before memberships etc).
+//Network settings go last as they may move the switch to another address
+pub fn restore_stage(cmd: &Cmd) -> u8 {
+    match cmd {
+        Cmd::CMD_Name | Cmd::CMD_Location => 0,
+        Cmd::CMD_VLAN_Mode | Cmd::CMD_VLAN_Delete => 1,
+        Cmd::CMD_VLAN_Port_Members | Cmd::CMD_VLAN_8021Q_Members => 2,
+        Cmd::CMD_VLAN_PVID => 3,
+        Cmd::CMD_QoS_Mode => 4,
+        Cmd::CMD_Switch_DHCP
+        | Cmd::CMD_IPv4
+        | Cmd::CMD_Switch_Netmask
+        | Cmd::CMD_Switch_Gateway => 9,
+        _ => 5,
+    }
+}
+
+//TLVs the switch did not fill in are treated as unsupported
+fn present(resp: &Response, cmd: &Cmd) -> Option<TypeLengthValue> {
+    resp.get_cmd(cmd).ok().filter(|tlv| !tlv.value().is_empty())
+}
+
+fn string_value(resp: &Response, cmd: &Cmd) -> Option<String> {
+    let value: String = present(resp, cmd)?.try_into().ok()?;
+    Some(value.trim_end_matches('\0').to_string())
+}
+
+fn ipv4_value(resp: &Response, cmd: &Cmd) -> Option<Ipv4Addr> {
+    present(resp, cmd)?.try_into().ok()
+}
+
+//Per port records the switch filled in, empty TLVs are left out like unsupported settings
+fn records(
+    resp: &Response,
+    cmd: &Cmd,
+    record_len: usize,
+    port_count: u8,
+) -> Result<Vec<(Port, Vec<u8>)>, TLVReadingError> {
+    let filled: Vec<TypeLengthValue> = resp
+        .get_cmds()
+        .iter()
+        .filter(|tlv| !tlv.value().is_empty())
+        .cloned()
+        .collect();
+    port_records(&filled, cmd, record_len, port_count)
+}
+
+//A VLAN reported as both port based and 802.1Q is taken as 802.1Q
+fn vlans_from_response(
+    resp: &Response,
+    port_count: u8,
+) -> Result<Option<VlanConfig>, TLVReadingError> {
+    let mode = present(resp, &Cmd::CMD_VLAN_Mode).and_then(|tlv| tlv.value().first().copied());
+    let mut memberships: BTreeMap<u16, VlanMembershipConfig> = BTreeMap::new();
+    for cmd in [Cmd::CMD_VLAN_Port_Members, Cmd::CMD_VLAN_8021Q_Members] {
+        for tlv in resp
+            .get_cmds()
+            .iter()
+            .filter(|tlv| tlv.cmd_equal_to(&cmd) && !tlv.value().is_empty())
+        {
+            let membership = VlanMembership::from_tlv(tlv, port_count)?;
+            memberships.insert(
+                membership.id,
+                VlanMembershipConfig {
+                    id: membership.id,
+                    members: membership.members.to_string(),
+                    tagged: membership.tagged.map(|tagged| tagged.to_string()),
+                },
+            );
+        }
+    }
+    let ports: Vec<VlanPortConfig> = records(resp, &Cmd::CMD_VLAN_PVID, 3, port_count)?
+        .into_iter()
+        .map(|(port, record)| VlanPortConfig {
+            port: port.number(),
+            pvid: u16::from_be_bytes([record[1], record[2]]),
+        })
+        .collect();
+
+    if mode.is_none() && memberships.is_empty() && ports.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(VlanConfig {
+        mode,
+        memberships: memberships.into_values().collect(),
+        ports,
+    }))
+}
+
+fn qos_from_response(
+    resp: &Response,
+    port_count: u8,
+) -> Result<Option<QosConfig>, TLVReadingError> {
+    let mode = present(resp, &Cmd::CMD_QoS_Mode).and_then(|tlv| tlv.value().first().copied());
+    let ports: Vec<QosPortConfig> = records(resp, &Cmd::CMD_QoS_Port_Priority, 2, port_count)?
+        .into_iter()
+        .map(|(port, record)| QosPortConfig {
+            port: port.number(),
+            priority: record[1],
+        })
+        .collect();
+
+    if mode.is_none() && ports.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(QosConfig { mode, ports }))
+}
+
+fn rate_limits_from_response(
+    resp: &Response,
+    port_count: u8,
+) -> Result<Option<RateLimitConfig>, TLVReadingError> {
+    let storm_control = present(resp, &Cmd::CMD_Storm_Control)
+        .map(bool::try_from)
+        .transpose()?;
+
+    let mut ports: BTreeMap<Port, RateLimitPortConfig> = BTreeMap::new();
+    for cmd in RATE_LIMIT_CMDS {
+        for (port, record) in records(resp, &cmd, 5, port_count)? {
+            let rate = u32::from_be_bytes([record[1], record[2], record[3], record[4]]);
+            let config = ports.entry(port).or_default();
+            match cmd {
+                Cmd::CMD_Ingress_Rate_Limit => config.ingress = Some(rate),
+                Cmd::CMD_Egress_Rate_Limit => config.egress = Some(rate),
+                _ => config.storm = Some(rate),
+            }
+        }
+    }
+
+    if storm_control.is_none() && ports.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(RateLimitConfig {
+        storm_control,
+        ports: ports
+            .into_iter()
+            .map(|(port, config)| RateLimitPortConfig {
+                port: port.number(),
+                ..config
+            })
+            .collect(),
+    }))
+}
+
+//Per port rates: the port followed by the 4 byte rate
+const RATE_LIMIT_CMDS: [Cmd; 3] = [
+    Cmd::CMD_Ingress_Rate_Limit,
+    Cmd::CMD_Egress_Rate_Limit,
+    Cmd::CMD_Storm_Control_Rate,
+];
+
+fn rate_tlv(cmd: Cmd, port: Port, rate: u32) -> TypeLengthValue {
+    let mut value = vec![port.number()];
+    value.extend(rate.to_be_bytes());
+    TypeLengthValue::from((cmd, value))
+}
+
+//Port numbers in the configuration have to exist on the switch it is written to
+fn config_port(number: u8, port_count: u8) -> Result<Port, String> {
+    Port::new(number)
+        .filter(|port| port.number() <= port_count)
+        .ok_or(format!("Port {} does not exist", number))
+}
+
+fn config_ports(ports: &str, port_count: u8) -> Result<PortSet, String> {
+    let ports: PortSet = ports.parse()?;
+    let missing = ports.iter().find(|port| port.number() > port_count);
+    match missing {
+        Some(port) => Err(format!("Port {} does not exist", port)),
+        None => Ok(ports),
+    }
+}
+
+impl SwitchConfig {
+    pub fn query_cmds() -> Vec<Cmd> {
+        let mut cmds = vec![Cmd::CMD_Model, Cmd::CMD_Switch_MAC, Cmd::CMD_FW_Version];
+        cmds.append(&mut config_cmds());
+        cmds
+    }
+
+    pub fn from_response(resp: &Response, port_count: u8) -> Result<SwitchConfig, TLVReadingError> {
+        let mut config = SwitchConfig {
+            version: CONFIG_VERSION,
+            ..SwitchConfig::default()
+        };
+
+        config.switch = Some(SwitchIdentity {
+            model: string_value(resp, &Cmd::CMD_Model),
+            mac: Some(format_mac(&resp.get_session().get_switch_mac())),
+            firmware: string_value(resp, &Cmd::CMD_FW_Version),
+        });
+
+        config.system = Some(SystemConfig {
+            name: string_value(resp, &Cmd::CMD_Name),
+            location: string_value(resp, &Cmd::CMD_Location),
+            dhcp: present(resp, &Cmd::CMD_Switch_DHCP)
+                .map(bool::try_from)
+                .transpose()?,
+            ip: ipv4_value(resp, &Cmd::CMD_IPv4),
+            netmask: ipv4_value(resp, &Cmd::CMD_Switch_Netmask),
+            gateway: ipv4_value(resp, &Cmd::CMD_Switch_Gateway),
+        });
+
+        if let Some(tlv) = present(resp, &Cmd::CMD_Port_Mirroring) {
+            let mirror = Mirror::try_from(tlv)?;
+            config.mirror = Some(MirrorConfig {
+                destination: mirror.destination.map_or(0, |port| port.number()),
+                sources: mirror.sources.to_string(),
+            });
+        }
+
+        let igmp: Option<Vec<TypeLengthValue>> = IgmpSnooping::CMDS
+            .iter()
+            .map(|cmd| present(resp, cmd))
+            .collect();
+        if let Some([snooping, validate, block, router]) =
+            igmp.and_then(|tlvs| <[TypeLengthValue; 4]>::try_from(tlvs).ok())
+        {
+            let igmp = IgmpSnooping::from_tlvs(snooping, validate, block, router)?;
+            config.igmp = Some(IgmpConfig {
+                enabled: Some(igmp.enabled),
+                vlan: Some(igmp.vlan),
+                validate_ip_header: Some(igmp.validate_ip_header),
+                block_unknown_multicast: Some(igmp.block_unknown_multicast),
+                router_ports: Some(igmp.router_ports.to_string()),
+            });
+        }
+
+        config.loop_detection = present(resp, &Cmd::CMD_Loop_Detection)
+            .map(bool::try_from)
+            .transpose()?;
+
+        config.vlans = vlans_from_response(resp, port_count)?;
+        config.qos = qos_from_response(resp, port_count)?;
+        config.rate_limits = rate_limits_from_response(resp, port_count)?;
+
+        let mut ports: BTreeMap<Port, PortConfig> = BTreeMap::new();
+        for (port, record) in
+            port_records(resp.get_cmds(), &Cmd::CMD_Port_Speed_Config, 2, port_count)?
+        {
+            ports.entry(port).or_default().speed =
+                Some(SpeedSetting::try_from(record[1])?.to_string());
+        }
+        for (port, record) in
+            port_records(resp.get_cmds(), &Cmd::CMD_Port_Flow_Control, 2, port_count)?
+        {
+            ports.entry(port).or_default().flow_control = Some(record[1] != 0);
+        }
+        config.ports = ports
+            .into_iter()
+            .map(|(port, config)| PortConfig {
+                port: port.number(),
+                ..config
+            })
+            .collect();
+
+        for cmd in raw_cmds() {
+            let values: Vec<String> = resp
+                .get_cmds()
+                .iter()
+                .filter(|tlv| tlv.cmd_equal_to(&cmd) && !tlv.value().is_empty())
+                .map(|tlv| to_hex(tlv.value()))
+                .collect();
+            if !values.is_empty() {
+                config.tlvs.insert(cmd.name(), values);
+            }
+        }
+
+        Ok(config)
+    }
+
+    //Every setting in the configuration as TLVs, sorted into restore order. Settings that share a TLV with one
+    //given in the configuration are taken from live when left out
+    pub fn to_tlvs(
+        &self,
+        live: Option<&SwitchConfig>,
+        port_count: u8,
+    ) -> Result<Vec<(Cmd, TypeLengthValue)>, String> {
+        if self.version > CONFIG_VERSION {
+            return Err(format!(
+                "Config version {} is newer than the supported version {}",
+                self.version, CONFIG_VERSION
+            ));
+        }
+
+        let mut tlvs: Vec<(Cmd, TypeLengthValue)> = Vec::new();
+        let mut push = |cmd: Cmd, tlv: TypeLengthValue| tlvs.push((cmd, tlv));
+
+        if let Some(system) = &self.system {
+            if let Some(name) = &system.name {
+                push(
+                    Cmd::CMD_Name,
+                    TypeLengthValue::from((Cmd::CMD_Name, name.as_bytes().to_vec())),
+                );
+            }
+            if let Some(location) = &system.location {
+                push(
+                    Cmd::CMD_Location,
+                    TypeLengthValue::from((Cmd::CMD_Location, location.as_bytes().to_vec())),
+                );
+            }
+            if let Some(dhcp) = system.dhcp {
+                push(
+                    Cmd::CMD_Switch_DHCP,
+                    TypeLengthValue::from((Cmd::CMD_Switch_DHCP, dhcp)),
+                );
+            }
+            //A static address only matters while DHCP is off
+            if system.dhcp != Some(true) {
+                for (cmd, addr) in [
+                    (Cmd::CMD_IPv4, system.ip),
+                    (Cmd::CMD_Switch_Netmask, system.netmask),
+                    (Cmd::CMD_Switch_Gateway, system.gateway),
+                ] {
+                    if let Some(addr) = addr {
+                        push(cmd.clone(), TypeLengthValue::from((cmd, addr)));
+                    }
+                }
+            }
+        }
+
+        if let Some(mirror) = &self.mirror {
+            let mirror = mirror.to_mirror()?;
+            mirror.validate(port_count)?;
+            push(Cmd::CMD_Port_Mirroring, mirror.to_tlv(port_count));
+        }
+
+        if let Some(igmp) = &self.igmp {
+            let igmp = igmp.to_igmp(live.and_then(|live| live.igmp.as_ref()))?;
+            for (cmd, tlv) in IgmpSnooping::CMDS.into_iter().zip(igmp.to_tlvs(port_count)) {
+                push(cmd, tlv);
+            }
+        }
+
+        if let Some(loop_detection) = self.loop_detection {
+            push(
+                Cmd::CMD_Loop_Detection,
+                TypeLengthValue::from((Cmd::CMD_Loop_Detection, loop_detection)),
+            );
+        }
+
+        if let Some(vlans) = &self.vlans {
+            if let Some(mode) = vlans.mode {
+                push(
+                    Cmd::CMD_VLAN_Mode,
+                    TypeLengthValue::from((Cmd::CMD_VLAN_Mode, vec![mode])),
+                );
+            }
+            let mut ids = BTreeSet::new();
+            for membership in &vlans.memberships {
+                if !ids.insert(membership.id) {
+                    return Err(format!("VLAN {} is listed twice", membership.id));
+                }
+                let membership = membership.to_membership(port_count)?;
+                push(membership.cmd(), membership.to_tlv(port_count));
+            }
+            //The memberships replace the ones on the switch, so VLANs left out go
+            if let Some(live) = live.and_then(|live| live.vlans.as_ref()) {
+                if !vlans.memberships.is_empty() {
+                    for id in live
+                        .memberships
+                        .iter()
+                        .map(|membership| membership.id)
+                        .filter(|id| !ids.contains(id))
+                    {
+                        push(Cmd::CMD_VLAN_Delete, VlanMembership::delete_tlv(id));
+                    }
+                }
+            }
+            for port_config in &vlans.ports {
+                let mut value = vec![config_port(port_config.port, port_count)?.number()];
+                value.extend(port_config.pvid.to_be_bytes());
+                push(
+                    Cmd::CMD_VLAN_PVID,
+                    TypeLengthValue::from((Cmd::CMD_VLAN_PVID, value)),
+                );
+            }
+        }
+
+        if let Some(qos) = &self.qos {
+            if let Some(mode) = qos.mode {
+                push(
+                    Cmd::CMD_QoS_Mode,
+                    TypeLengthValue::from((Cmd::CMD_QoS_Mode, vec![mode])),
+                );
+            }
+            for port_config in &qos.ports {
+                let port = config_port(port_config.port, port_count)?;
+                push(
+                    Cmd::CMD_QoS_Port_Priority,
+                    TypeLengthValue::from((
+                        Cmd::CMD_QoS_Port_Priority,
+                        vec![port.number(), port_config.priority],
+                    )),
+                );
+            }
+        }
+
+        if let Some(rate_limits) = &self.rate_limits {
+            if let Some(storm_control) = rate_limits.storm_control {
+                push(
+                    Cmd::CMD_Storm_Control,
+                    TypeLengthValue::from((Cmd::CMD_Storm_Control, storm_control)),
+                );
+            }
+            for port_config in &rate_limits.ports {
+                let port = config_port(port_config.port, port_count)?;
+                for (cmd, rate) in RATE_LIMIT_CMDS.into_iter().zip([
+                    port_config.ingress,
+                    port_config.egress,
+                    port_config.storm,
+                ]) {
+                    if let Some(rate) = rate {
+                        push(cmd.clone(), rate_tlv(cmd, port, rate));
+                    }
+                }
+            }
+        }
+
+        for port_config in &self.ports {
+            let port = config_port(port_config.port, port_count)?;
+            if let Some(speed) = &port_config.speed {
+                push(
+                    Cmd::CMD_Port_Speed_Config,
+                    speed.parse::<SpeedSetting>()?.to_tlv(port),
+                );
+            }
+            if let Some(flow_control) = port_config.flow_control {
+                push(
+                    Cmd::CMD_Port_Flow_Control,
+                    flow_control_tlv(port, flow_control),
+                );
+            }
+        }
+
+        for (name, values) in &self.tlvs {
+            let cmd = Cmd::from_name(name).ok_or(format!("Unknown TLV '{}'", name))?;
+            if !raw_cmds().contains(&cmd) {
+                return Err(format!("{} can not be restored from the tlvs table", name));
+            }
+            for value in values {
+                push(
+                    cmd.clone(),
+                    TypeLengthValue::from((cmd.clone(), from_hex(value)?)),
+                );
+            }
+        }
+
+        tlvs.sort_by_key(|(cmd, _)| restore_stage(cmd));
+        Ok(tlvs)
+    }
+}
+
+//
+//Desired state: a set of partial configurations keyed by an alias, matched to switches by MAC or by the alias itself
+//
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct DesiredState {
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default)]
+    pub switches: BTreeMap<String, DesiredSwitch>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct DesiredSwitch {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>,
+    #[serde(flatten)]
+    pub config: SwitchConfig,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub key: String,
+    pub live: Option<String>,
+    pub desired: Option<String>,
+}
+
+impl SwitchConfig {
+    //Settings given in desired replace the ones in self, everything else is kept
+    pub fn overlay(&self, desired: &SwitchConfig) -> SwitchConfig {
+        let mut merged = self.clone();
+
+        if let Some(system) = &desired.system {
+            let live = merged.system.get_or_insert_with(SystemConfig::default);
+            live.name = system.name.clone().or(live.name.take());
+            live.location = system.location.clone().or(live.location.take());
+            live.dhcp = system.dhcp.or(live.dhcp);
+            live.ip = system.ip.or(live.ip);
+            live.netmask = system.netmask.or(live.netmask);
+            live.gateway = system.gateway.or(live.gateway);
+        }
+        if desired.mirror.is_some() {
+            merged.mirror = desired.mirror.clone();
+        }
+        if let Some(igmp) = &desired.igmp {
+            let live = merged.igmp.get_or_insert_with(IgmpConfig::default);
+            live.enabled = igmp.enabled.or(live.enabled);
+            live.vlan = igmp.vlan.or(live.vlan);
+            live.validate_ip_header = igmp.validate_ip_header.or(live.validate_ip_header);
+            live.block_unknown_multicast = igmp
+                .block_unknown_multicast
+                .or(live.block_unknown_multicast);
+            live.router_ports = igmp.router_ports.clone().or(live.router_ports.take());
+        }
+        if let Some(vlans) = &desired.vlans {
+            let live = merged.vlans.get_or_insert_with(VlanConfig::default);
+            live.mode = vlans.mode.or(live.mode);
+            if !vlans.memberships.is_empty() {
+                live.memberships = vlans.memberships.clone();
+            }
+            for port in &vlans.ports {
+                match live.ports.iter_mut().find(|live| live.port == port.port) {
+                    Some(live) => live.pvid = port.pvid,
+                    None => live.ports.push(port.clone()),
+                }
+            }
+        }
+        if let Some(qos) = &desired.qos {
+            let live = merged.qos.get_or_insert_with(QosConfig::default);
+            live.mode = qos.mode.or(live.mode);
+            for port in &qos.ports {
+                match live.ports.iter_mut().find(|live| live.port == port.port) {
+                    Some(live) => live.priority = port.priority,
+                    None => live.ports.push(port.clone()),
+                }
+            }
+        }
+        if let Some(rate_limits) = &desired.rate_limits {
+            let live = merged
+                .rate_limits
+                .get_or_insert_with(RateLimitConfig::default);
+            live.storm_control = rate_limits.storm_control.or(live.storm_control);
+            for port in &rate_limits.ports {
+                match live.ports.iter_mut().find(|live| live.port == port.port) {
+                    Some(live) => {
+                        live.ingress = port.ingress.or(live.ingress);
+                        live.egress = port.egress.or(live.egress);
+                        live.storm = port.storm.or(live.storm);
+                    }
+                    None => live.ports.push(port.clone()),
+                }
+            }
+        }
+        merged.loop_detection = desired.loop_detection.or(merged.loop_detection);
+        for port in &desired.ports {
+            match merged.ports.iter_mut().find(|live| live.port == port.port) {
+                Some(live) => {
+                    live.speed = port.speed.clone().or(live.speed.take());
+                    live.flow_control = port.flow_control.or(live.flow_control);
+                }
+                None => merged.ports.push(port.clone()),
+            }
+        }
+        for (name, values) in &desired.tlvs {
+            merged.tlvs.insert(name.clone(), values.clone());
+        }
+
+        merged
+    }
+
+    //Settings as flat "section.field" keys, ports and VLANs are keyed by their number rather than position.
+    //Port lists and speeds are parsed and written out again so different spellings of a value compare equal
+    pub fn settings(&self) -> BTreeMap<String, String> {
+        let mut settings = BTreeMap::new();
+        if let Ok(toml::Value::Table(table)) = toml::Value::try_from(self.normalized()) {
+            for (key, value) in table {
+                if key != "version" && key != "switch" {
+                    flatten_setting(&key, &value, &mut settings);
+                }
+            }
+        }
+        settings
+    }
+
+    //Human readable differences between self (live) and the desired configuration. Settings only live holds
+    //are ones desired removes, like the VLANs left out of its memberships
+    pub fn changes(&self, desired: &SwitchConfig) -> Vec<Change> {
+        let live = self.settings();
+        let desired = desired.settings();
+        let keys: BTreeSet<&String> = live.keys().chain(desired.keys()).collect();
+        keys.into_iter()
+            .filter(|key| live.get(*key) != desired.get(*key))
+            .map(|key| Change {
+                key: key.clone(),
+                live: live.get(key).cloned(),
+                desired: desired.get(key).cloned(),
+            })
+            .collect()
+    }
+
+    //TLVs of desired that the live configuration doesn't already hold byte for byte
+    pub fn changed_tlvs(
+        &self,
+        desired: &SwitchConfig,
+        port_count: u8,
+    ) -> Result<Vec<(Cmd, TypeLengthValue)>, String> {
+        let live = self.to_tlvs(None, port_count)?;
+        Ok(desired
+            .to_tlvs(Some(self), port_count)?
+            .into_iter()
+            .filter(|(_, tlv)| !live.iter().any(|(_, live_tlv)| live_tlv == tlv))
+            .collect())
+    }
+
+    //Typed values in their canonical spelling, values that don't parse are left for to_tlvs to report
+    fn normalized(&self) -> SwitchConfig {
+        let mut config = self.clone();
+        if let Some(mirror) = &mut config.mirror {
+            if let Ok(typed) = mirror.to_mirror() {
+                //Sources don't matter while mirroring is off
+                mirror.sources = match typed.destination {
+                    Some(_) => typed.sources.to_string(),
+                    None => PortSet::new().to_string(),
+                };
+            }
+        }
+        if let Some(igmp) = &mut config.igmp {
+            if let Some(Ok(ports)) = igmp.router_ports.as_deref().map(str::parse::<PortSet>) {
+                igmp.router_ports = Some(ports.to_string());
+            }
+        }
+        if let Some(vlans) = &mut config.vlans {
+            for membership in &mut vlans.memberships {
+                if let Ok(typed) = membership.to_membership(u8::MAX) {
+                    membership.members = typed.members.to_string();
+                    membership.tagged = typed.tagged.map(|tagged| tagged.to_string());
+                }
+            }
+        }
+        for port in &mut config.ports {
+            if let Some(Ok(speed)) = port.speed.as_deref().map(str::parse::<SpeedSetting>) {
+                port.speed = Some(speed.to_string());
+            }
+        }
+        config
+    }
+}
+
+fn flatten_setting(key: &str, value: &toml::Value, settings: &mut BTreeMap<String, String>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (field, value) in table {
+                flatten_setting(&format!("{}.{}", key, field), value, settings);
+            }
+        }
+        toml::Value::Array(items) if items.iter().all(|item| item.is_table()) => {
+            for (index, item) in items.iter().enumerate() {
+                let id = item
+                    .get("port")
+                    .or(item.get("id"))
+                    .map_or(index.to_string(), |id| id.to_string());
+                if let toml::Value::Table(table) = item {
+                    for (field, value) in table
+                        .iter()
+                        .filter(|(field, _)| *field != "port" && *field != "id")
+                    {
+                        flatten_setting(&format!("{}.{}.{}", key, id, field), value, settings);
+                    }
+                }
+            }
+        }
+        other => {
+            settings.insert(key.to_string(), other.to_string());
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.live, &self.desired) {
+            (Some(live), Some(desired)) => write!(f, "~ {}: {} -> {}", self.key, live, desired),
+            (Some(live), None) => write!(f, "- {}: {}", self.key, live),
+            (None, desired) => write!(
+                f,
+                "+ {}: {}",
+                self.key,
+                desired.as_deref().unwrap_or_default()
+            ),
+        }
+    }
+}
+
+impl MirrorConfig {
+    pub fn to_mirror(&self) -> Result<Mirror, String> {
+        //Only 0 turns mirroring off, a port number out of range is an error rather than a way to disable it
+        let destination = match self.destination {
+            0 => None,
+            number => Some(Port::new(number).ok_or(format!("Port {} does not exist", number))?),
+        };
+        Ok(Mirror {
+            destination,
+            sources: self.sources.parse::<PortSet>()?,
+        })
+    }
+}
+
+impl VlanMembershipConfig {
+    pub fn to_membership(&self, port_count: u8) -> Result<VlanMembership, String> {
+        Ok(VlanMembership {
+            id: self.id,
+            members: config_ports(&self.members, port_count)?,
+            tagged: self
+                .tagged
+                .as_deref()
+                .map(|tagged| config_ports(tagged, port_count))
+                .transpose()?,
+        })
+    }
+}
+
+impl IgmpConfig {
+    //Settings left out are taken from live, they are switched off when live doesn't hold them either
+    pub fn to_igmp(&self, live: Option<&IgmpConfig>) -> Result<IgmpSnooping, String> {
+        let live = live.cloned().unwrap_or_default();
+        Ok(IgmpSnooping {
+            enabled: self.enabled.or(live.enabled).unwrap_or(false),
+            vlan: self.vlan.or(live.vlan).unwrap_or(1),
+            validate_ip_header: self
+                .validate_ip_header
+                .or(live.validate_ip_header)
+                .unwrap_or(false),
+            block_unknown_multicast: self
+                .block_unknown_multicast
+                .or(live.block_unknown_multicast)
+                .unwrap_or(false),
+            router_ports: match self.router_ports.as_ref().or(live.router_ports.as_ref()) {
+                Some(ports) => ports.parse()?,
+                None => PortSet::new(),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BACKUP: &str = r#"
+version = 1
+loop_detection = true
+
+[system]
+name = "office"
+dhcp = false
+ip = "192.168.0.239"
+
+[mirror]
+destination = 5
+sources = "1-2"
+
+[[ports]]
+port = 3
+flow_control = true
+
+[vlans]
+mode = 4
+
+[[vlans.memberships]]
+id = 1
+members = "1-5"
+tagged = "1"
+
+[[vlans.memberships]]
+id = 2
+members = "4,5"
+tagged = ""
+"#;
+
+    #[test]
+    fn backups_survive_a_toml_round_trip() {
+        let config: SwitchConfig = toml::from_str(BACKUP).unwrap();
+        assert_eq!(config.mirror.as_ref().unwrap().destination, 5);
+        assert_eq!(config.ports[0].flow_control, Some(true));
+        let written = toml::to_string(&config).unwrap();
+        assert_eq!(toml::from_str::<SwitchConfig>(&written).unwrap(), config);
+    }
+
+    #[test]
+    fn restores_go_out_in_dependency_order() {
+        let config: SwitchConfig = toml::from_str(BACKUP).unwrap();
+        let stages: Vec<u8> = config
+            .to_tlvs(None, 5)
+            .unwrap()
+            .iter()
+            .map(|(cmd, _)| restore_stage(cmd))
+            .collect();
+        assert!(stages.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        let cmds: Vec<Cmd> = config
+            .to_tlvs(None, 5)
+            .unwrap()
+            .into_iter()
+            .map(|(cmd, _)| cmd)
+            .collect();
+        assert_eq!(cmds.first(), Some(&Cmd::CMD_Name));
+        assert_eq!(cmds.last(), Some(&Cmd::CMD_IPv4));
+        let mode = cmds.iter().position(|cmd| *cmd == Cmd::CMD_VLAN_Mode);
+        let members = cmds
+            .iter()
+            .position(|cmd| *cmd == Cmd::CMD_VLAN_8021Q_Members);
+        assert!(mode < members);
+    }
+
+    #[test]
+    fn restores_refuse_what_they_can_not_write() {
+        let newer = SwitchConfig {
+            version: CONFIG_VERSION + 1,
+            ..SwitchConfig::default()
+        };
+        assert!(newer.to_tlvs(None, 5).is_err());
+
+        let mut typed = SwitchConfig::default();
+        typed
+            .tlvs
+            .insert(Cmd::CMD_Port_Mirroring.name(), vec![String::from("050000")]);
+        assert!(typed.to_tlvs(None, 5).is_err());
+
+        let missing_port = SwitchConfig {
+            ports: vec![PortConfig {
+                port: 8,
+                flow_control: Some(true),
+                ..PortConfig::default()
+            }],
+            ..SwitchConfig::default()
+        };
+        assert!(missing_port.to_tlvs(None, 5).is_err());
+    }
+
+    fn live() -> SwitchConfig {
+        toml::from_str(BACKUP).unwrap()
+    }
+
+    #[test]
+    fn overlays_replace_only_the_settings_given() {
+        let desired: SwitchConfig = toml::from_str(
+            r#"
+[system]
+name = "lab"
+
+[[ports]]
+port = 3
+speed = "100M-full"
+"#,
+        )
+        .unwrap();
+        let merged = live().overlay(&desired);
+        let system = merged.system.as_ref().unwrap();
+        assert_eq!(system.name.as_deref(), Some("lab"));
+        assert_eq!(system.ip, live().system.unwrap().ip);
+        assert_eq!(merged.ports[0].speed.as_deref(), Some("100M-full"));
+        assert_eq!(merged.ports[0].flow_control, Some(true));
+        assert_eq!(merged.mirror, live().mirror);
+    }
+
+    #[test]
+    fn changes_list_only_what_differs() {
+        let desired: SwitchConfig = toml::from_str(
+            r#"
+loop_detection = true
+
+[system]
+name = "lab"
+location = "rack 2"
+"#,
+        )
+        .unwrap();
+        let changes: Vec<String> = live()
+            .changes(&live().overlay(&desired))
+            .iter()
+            .map(Change::to_string)
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                "+ system.location: \"rack 2\"",
+                "~ system.name: \"office\" -> \"lab\"",
+            ]
+        );
+        assert!(live().changes(&live()).is_empty());
+    }
+
+    #[test]
+    fn changed_tlvs_skip_what_the_switch_already_holds() {
+        let mut desired = live();
+        desired.ports[0].flow_control = Some(false);
+        let changed = live().changed_tlvs(&desired, 5).unwrap();
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0].0, Cmd::CMD_Port_Flow_Control);
+        assert!(live().changed_tlvs(&live(), 5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn restores_delete_the_vlans_left_out() {
+        let config: SwitchConfig = toml::from_str(
+            r#"
+[[vlans.memberships]]
+id = 2
+members = "1-3"
+tagged = ""
+
+[[vlans.memberships]]
+id = 10
+members = "4,5"
+"#,
+        )
+        .unwrap();
+        let tlvs = config.to_tlvs(Some(&live()), 5).unwrap();
+        assert_eq!(
+            tlvs[0],
+            (
+                Cmd::CMD_VLAN_Delete,
+                TypeLengthValue::from((Cmd::CMD_VLAN_Delete, vec![0x00, 0x01]))
+            )
+        );
+        assert_eq!(
+            tlvs[1..],
+            [
+                (
+                    Cmd::CMD_VLAN_8021Q_Members,
+                    TypeLengthValue::from((
+                        Cmd::CMD_VLAN_8021Q_Members,
+                        vec![0x00, 0x02, 0xe0, 0x00]
+                    ))
+                ),
+                (
+                    Cmd::CMD_VLAN_Port_Members,
+                    TypeLengthValue::from((Cmd::CMD_VLAN_Port_Members, vec![0x00, 0x0a, 0x18]))
+                ),
+            ]
+        );
+        //Without the switch to compare against nothing is deleted
+        assert_eq!(config.to_tlvs(None, 5).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn vlans_are_keyed_by_their_id() {
+        let mut desired = live();
+        desired.vlans.as_mut().unwrap().memberships[1].members = String::from("3-5");
+        let changes: Vec<String> = live()
+            .changes(&desired)
+            .iter()
+            .map(Change::to_string)
+            .collect();
+        assert_eq!(
+            changes,
+            ["~ vlans.memberships.2.members: \"4,5\" -> \"3,4,5\""]
+        );
+
+        desired.vlans.as_mut().unwrap().memberships[1].id = 1;
+        assert_eq!(
+            desired.to_tlvs(None, 5).unwrap_err(),
+            "VLAN 1 is listed twice"
+        );
+    }
+
+    #[test]
+    fn vlans_left_out_are_listed_and_deleted() {
+        let mut desired = live();
+        desired.vlans.as_mut().unwrap().memberships.truncate(1);
+        let changes: Vec<String> = live()
+            .changes(&desired)
+            .iter()
+            .map(Change::to_string)
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                "- vlans.memberships.2.members: \"4,5\"",
+                "- vlans.memberships.2.tagged: \"none\"",
+            ]
+        );
+
+        let changed = live().changed_tlvs(&desired, 5).unwrap();
+        assert_eq!(
+            changed,
+            [(
+                Cmd::CMD_VLAN_Delete,
+                TypeLengthValue::from((Cmd::CMD_VLAN_Delete, vec![0x00, 0x02]))
+            )]
+        );
+    }
+}
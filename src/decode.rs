@@ -0,0 +1,186 @@
+use std::io::{self, Write};
+
+use strum::IntoEnumIterator;
+
+use crate::cmds::{Cmd, TypeLengthValue};
+use crate::packet::Packet;
+use crate::values::{
+    format_mac, on_off, to_hex, CableTestResult, FirmwareBank, LinkSpeed, Mirror, PortSet,
+    SpeedSetting,
+};
+
+//
+//Human readable breakdown of NSDP packets for debugging and reverse engineering. TLVs missing from Cmd are
+//flagged with a '?' in front, passwords are never printed
+//
+
+//One line for the header followed by a line per TLV
+pub fn describe_packet(out: &mut dyn Write, datagram: &[u8]) -> Result<(), io::Error> {
+    let packet = match Packet::parse(datagram) {
+        Ok(packet) => packet,
+        Err(err) => return writeln!(out, "  not an NSDP packet: {}", err),
+    };
+    let session = packet.get_session();
+    write!(
+        out,
+        "  {}, seq {}, host {}, switch {}",
+        packet.get_type(),
+        to_hex(&session.get_seq()),
+        format_mac(&session.get_host_mac()),
+        format_mac(&session.get_switch_mac())
+    )?;
+    if !packet.is_success() {
+        write!(
+            out,
+            ", status {} at {}",
+            to_hex(&packet.get_status()),
+            to_hex(&packet.get_failed_cmd())
+        )?;
+    }
+    writeln!(out)?;
+
+    for tlv in packet.get_cmds() {
+        writeln!(out, "    {}", describe_tlv(tlv))?;
+    }
+    Ok(())
+}
+
+//Every header field on a line of its own, for single packets pasted in by hand
+pub fn describe_datagram(out: &mut dyn Write, datagram: &[u8]) -> Result<(), io::Error> {
+    let packet = Packet::parse(datagram)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+    let session = packet.get_session();
+    let packet_type = packet.get_type();
+
+    writeln!(
+        out,
+        "Type:       {} ({})",
+        packet_type,
+        to_hex(&<[u8; 2]>::from(packet_type))
+    )?;
+    if packet.is_success() {
+        writeln!(
+            out,
+            "Status:     {} (success)",
+            to_hex(&packet.get_status())
+        )?;
+    } else {
+        writeln!(
+            out,
+            "Status:     {} at {}",
+            to_hex(&packet.get_status()),
+            to_hex(&packet.get_failed_cmd())
+        )?;
+    }
+    writeln!(out, "Host MAC:   {}", format_mac(&session.get_host_mac()))?;
+    writeln!(out, "Switch MAC: {}", format_mac(&session.get_switch_mac()))?;
+    writeln!(out, "Sequence:   {}", to_hex(&session.get_seq()))?;
+    //Parsing fails on any other signature
+    writeln!(out, "Signature:  NSDP")?;
+    writeln!(out, "TLVs:       {}", packet.get_cmds().len())?;
+    for tlv in packet.get_cmds() {
+        writeln!(out, "    {}", describe_tlv(tlv))?;
+    }
+    Ok(())
+}
+
+//Name, ID, length, raw value and the typed value where the layout is known
+pub fn describe_tlv(tlv: &TypeLengthValue) -> String {
+    let cmd = Cmd::iter().find(|cmd| tlv.cmd_equal_to(cmd));
+    let name = cmd
+        .as_ref()
+        .map_or(String::from("?unknown"), |cmd| format!(" {}", cmd.name()));
+    let head = format!(
+        "{:<34} {} {:>4}",
+        name,
+        to_hex(&tlv.cmd()),
+        tlv.value().len()
+    );
+
+    if matches!(cmd, Some(Cmd::CMD_Password | Cmd::CMD_New_Password)) {
+        return format!("{}  (hidden)", head);
+    }
+    if tlv.value().is_empty() {
+        return head;
+    }
+    match cmd.and_then(|cmd| typed_value(&cmd, tlv)) {
+        Some(typed) => format!("{}  {}  = {}", head, hex_bytes(tlv.value()), typed),
+        None => format!("{}  {}", head, hex_bytes(tlv.value())),
+    }
+}
+
+fn hex_bytes(value: &[u8]) -> String {
+    let bytes: Vec<String> = value.iter().map(|byte| format!("{:02x}", byte)).collect();
+    bytes.join(" ")
+}
+
+fn typed_value(cmd: &Cmd, tlv: &TypeLengthValue) -> Option<String> {
+    let value = tlv.value();
+    let u64_at = |offset: usize| -> Option<u64> {
+        Some(u64::from_be_bytes(
+            value.get(offset..offset + 8)?.try_into().ok()?,
+        ))
+    };
+
+    Some(match cmd {
+        Cmd::CMD_Model
+        | Cmd::CMD_Name
+        | Cmd::CMD_Location
+        | Cmd::CMD_FW_Version
+        | Cmd::CMD_FW_Version_2 => {
+            format!(
+                "\"{}\"",
+                String::from_utf8_lossy(value).trim_end_matches('\0')
+            )
+        }
+        Cmd::CMD_Switch_MAC => format_mac(&value.try_into().ok()?),
+        Cmd::CMD_IPv4 | Cmd::CMD_Switch_Netmask | Cmd::CMD_Switch_Gateway => {
+            let addr: std::net::Ipv4Addr = tlv.clone().try_into().ok()?;
+            addr.to_string()
+        }
+        Cmd::CMD_Switch_DHCP
+        | Cmd::CMD_Loop_Detection
+        | Cmd::CMD_Storm_Control
+        | Cmd::CMD_IGMP_Block_Unknown_Multicast
+        | Cmd::CMD_IGMP_Validate_IP_Header => on_off(value[0] != 0).to_string(),
+        Cmd::CMD_Port_Count => value[0].to_string(),
+        Cmd::CMD_FW_Active => format!("image {}", FirmwareBank::try_from(value[0]).ok()?),
+        Cmd::CMD_Port_Status => format!("port {} {}", value[0], LinkSpeed::from(*value.get(1)?)),
+        Cmd::CMD_Port_Speed_Config => format!(
+            "port {} {}",
+            value[0],
+            SpeedSetting::try_from(*value.get(1)?).ok()?
+        ),
+        Cmd::CMD_Port_Flow_Control => {
+            format!(
+                "port {} flow control {}",
+                value[0],
+                on_off(*value.get(1)? != 0)
+            )
+        }
+        Cmd::CMD_Port_Statistics => format!(
+            "port {} received {} sent {} crc errors {}",
+            value[0],
+            u64_at(1)?,
+            u64_at(9)?,
+            u64_at(17)?
+        ),
+        Cmd::CMD_Port_Mirroring => Mirror::try_from(tlv.clone()).ok()?.to_string(),
+        Cmd::CMD_IGMP_Snooping => format!(
+            "{}, VLAN {}",
+            on_off(*value.get(1)? != 0),
+            u16::from_be_bytes(value.get(2..4)?.try_into().ok()?)
+        ),
+        Cmd::CMD_IGMP_Router_Ports => PortSet::from_bitmap(value).to_string(),
+        Cmd::CMD_Cable_Test_Request => format!("port {}", value[0]),
+        //Queries carry only the port
+        Cmd::CMD_Cable_Test_Result if value.len() == 1 => format!("port {}", value[0]),
+        Cmd::CMD_Cable_Test_Result => CableTestResult::try_from(tlv.clone()).ok()?.to_string(),
+        Cmd::CMD_VLAN_PVID => format!(
+            "port {} VLAN {}",
+            value[0],
+            u16::from_be_bytes(value.get(1..3)?.try_into().ok()?)
+        ),
+        _ => return None,
+    })
+}
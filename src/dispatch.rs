@@ -0,0 +1,505 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Instant};
+
+use crate::record::Recorder;
+use crate::request::Request;
+use crate::response::Response;
+
+//
+//Owns the host sockets and routes every incoming datagram to the session waiting for it, so any number of
+//logical sessions can share port 63321. Datagrams nobody waits for, like late replies, are dropped
+//
+
+type Reply = Result<Response, Error>;
+
+//Exclusive fails when another program already has the port. Shared sets SO_REUSEADDR and SO_REUSEPORT so
+//several programs can listen on 63321 at once, the broadcast replies of switches reach all of them. Unicast
+//replies reach only one of them though, the kernel load-balances them between the sockets sharing the port,
+//so a reply to a unicast request may go to the other program and the request times out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BindMode {
+    #[default]
+    Exclusive,
+    Shared,
+}
+
+//UDP ports a switch talks NSDP on, newer firmwares use the second pair
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PortPair {
+    pub host: u16,
+    pub switch: u16,
+}
+
+impl PortPair {
+    pub const V1: PortPair = PortPair {
+        host: 63321,
+        switch: 63322,
+    };
+    pub const V2: PortPair = PortPair {
+        host: 63323,
+        switch: 63324,
+    };
+}
+
+impl fmt::Display for PortPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.host, self.switch)
+    }
+}
+
+//A reply belongs to the request with the same switch MAC and sequence number. Probes are addressed to any
+//switch, so their replies are matched on the sequence number only. The request is kept for the recorder.
+//Once a socket stops receiving every route fails with its error, and so does every request after it
+#[derive(Default)]
+struct Routes {
+    replies: HashMap<([u8; 6], [u8; 2]), ReplyRoute>,
+    probes: HashMap<[u8; 2], ProbeRoute>,
+    recorder: Option<Arc<Recorder>>,
+    failed: Option<(ErrorKind, String)>,
+}
+
+type ReplyRoute = (Vec<u8>, oneshot::Sender<Reply>);
+type ProbeRoute = (Vec<u8>, PortPair, mpsc::UnboundedSender<ProbeReply>);
+type ProbeReply = (Reply, SocketAddr, PortPair);
+
+//Whoever waits for a reply that just came in
+enum Waiter {
+    Reply(oneshot::Sender<Reply>),
+    Probe(mpsc::UnboundedSender<ProbeReply>, PortPair),
+}
+
+impl Routes {
+    fn check(&self) -> Result<(), Error> {
+        match &self.failed {
+            Some((kind, message)) => Err(Error::new(
+                *kind,
+                format!("Receiving replies failed: {}", message),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    //Waiting probes notice their routes are gone as the senders are dropped
+    fn fail(&mut self, err: &Error) {
+        self.failed = Some((err.kind(), err.to_string()));
+        let senders: Vec<oneshot::Sender<Reply>> = self
+            .replies
+            .drain()
+            .map(|(_, (_, sender))| sender)
+            .collect();
+        for sender in senders {
+            let _ = sender.send(Err(Error::new(
+                err.kind(),
+                format!("Receiving replies failed: {}", err),
+            )));
+        }
+        self.probes.clear();
+    }
+}
+
+//Every socket is kept with the port it was bound to, requests go out on the socket of their host port
+pub struct Dispatcher {
+    sockets: Vec<(u16, Arc<UdpSocket>)>,
+    routes: Arc<Mutex<Routes>>,
+    receivers: Vec<JoinHandle<()>>,
+    next_seq: AtomicU16,
+}
+
+impl Dispatcher {
+    pub async fn bind(addr: SocketAddr) -> Result<Dispatcher, Error> {
+        Dispatcher::bind_with(&[addr], BindMode::Exclusive).await
+    }
+
+    //Binds a socket for every address, usually the host port of each port pair. Binding port 0 picks an
+    //ephemeral port, which serves every port pair but only hears firmwares answering to the source port
+    pub async fn bind_with(addrs: &[SocketAddr], mode: BindMode) -> Result<Dispatcher, Error> {
+        let mut sockets = Vec::new();
+        for addr in addrs {
+            let socket = bind_socket(*addr, mode).map_err(|err| {
+                let hint = match err.kind() {
+                    ErrorKind::AddrInUse => {
+                        ", another pputl or the vendor utility is probably running"
+                    }
+                    ErrorKind::PermissionDenied => ", the port may need more privileges",
+                    _ => "",
+                };
+                Error::new(
+                    err.kind(),
+                    format!("Could not bind {}: {}{}", addr, err, hint),
+                )
+            })?;
+            sockets.push(UdpSocket::from_std(socket)?);
+        }
+        Dispatcher::from_sockets(sockets)
+    }
+
+    //Takes over already bound sockets, has to be called from within a runtime
+    pub fn from_sockets(sockets: Vec<UdpSocket>) -> Result<Dispatcher, Error> {
+        if sockets.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "At least one socket is needed",
+            ));
+        }
+        let routes = Arc::new(Mutex::new(Routes::default()));
+        let mut bound = Vec::new();
+        let mut receivers = Vec::new();
+        for socket in sockets {
+            let socket = Arc::new(socket);
+            receivers.push(tokio::spawn(receive(socket.clone(), routes.clone())));
+            bound.push((socket.local_addr()?.port(), socket));
+        }
+
+        Ok(Dispatcher {
+            sockets: bound,
+            routes,
+            receivers,
+            next_seq: AtomicU16::new(rand::random()),
+        })
+    }
+
+    //Every reply from now on is saved together with its request
+    pub fn set_recorder(&self, recorder: Recorder) {
+        self.lock_routes().recorder = Some(Arc::new(recorder));
+    }
+
+    pub fn local_addrs(&self) -> Result<Vec<SocketAddr>, Error> {
+        self.sockets
+            .iter()
+            .map(|(_, socket)| socket.local_addr())
+            .collect()
+    }
+
+    //Sequence numbers count up so sessions in flight at the same time never share one
+    pub fn next_seq(&self) -> [u8; 2] {
+        self.next_seq.fetch_add(1, Ordering::Relaxed).to_be_bytes()
+    }
+
+    //Sends the request to the switch port of the pair at the given address and waits for the reply from the
+    //switch it is addressed to. The reply is taken whether it comes back unicast or broadcast
+    pub async fn exchange(
+        &self,
+        request: &Request,
+        ip: IpAddr,
+        port_pair: PortPair,
+        timeout: Duration,
+    ) -> Result<Response, Error> {
+        let key = (
+            request.get_session().get_switch_mac(),
+            request.get_session().get_seq(),
+        );
+        let datagram = request.format();
+        let (sender, reply) = oneshot::channel();
+        {
+            let mut routes = self.lock_routes();
+            routes.check()?;
+            routes.replies.insert(key, (datagram.clone(), sender));
+        }
+
+        let target = SocketAddr::new(ip, port_pair.switch);
+        let result = match self.socket_for(port_pair).send_to(&datagram, target).await {
+            Ok(_) => match time::timeout(timeout, reply).await {
+                Ok(Ok(reply)) => reply,
+                _ => Err(Error::new(ErrorKind::TimedOut, "No reply from the switch")),
+            },
+            Err(err) => Err(err),
+        };
+        self.lock_routes().replies.remove(&key);
+        result
+    }
+
+    //Sends requests any switch may answer, each to its own address and port pair, and collects every reply
+    //arriving within the wait. Fails only when none of the requests could be sent
+    pub async fn probe(
+        &self,
+        requests: &[(Request, IpAddr, PortPair)],
+        wait: Duration,
+    ) -> Result<Vec<ProbeReply>, Error> {
+        let (sender, mut replies) = mpsc::unbounded_channel();
+        let seqs: Vec<[u8; 2]> = requests
+            .iter()
+            .map(|(request, _, _)| request.get_session().get_seq())
+            .collect();
+        let datagrams: Vec<Vec<u8>> = requests
+            .iter()
+            .map(|(request, _, _)| request.format())
+            .collect();
+        {
+            let mut routes = self.lock_routes();
+            routes.check()?;
+            for ((seq, datagram), (_, _, port_pair)) in seqs.iter().zip(&datagrams).zip(requests) {
+                routes
+                    .probes
+                    .insert(*seq, (datagram.clone(), *port_pair, sender.clone()));
+            }
+        }
+        drop(sender);
+
+        let mut sent = Ok(());
+        let mut any_sent = false;
+        for (datagram, (_, ip, port_pair)) in datagrams.iter().zip(requests) {
+            let target = SocketAddr::new(*ip, port_pair.switch);
+            match self.socket_for(*port_pair).send_to(datagram, target).await {
+                Ok(_) => any_sent = true,
+                Err(err) => sent = Err(err),
+            }
+        }
+
+        let mut responses = Vec::new();
+        if any_sent {
+            let deadline = Instant::now() + wait;
+            while let Ok(Some(reply)) = time::timeout_at(deadline, replies.recv()).await {
+                responses.push(reply);
+            }
+        }
+
+        {
+            let mut routes = self.lock_routes();
+            routes.check()?;
+            for seq in &seqs {
+                routes.probes.remove(seq);
+            }
+        }
+        if !any_sent {
+            sent?;
+        }
+        Ok(responses)
+    }
+
+    //The socket bound to the host port of the pair, or the first one when there is none like with an
+    //ephemeral port
+    fn socket_for(&self, port_pair: PortPair) -> &UdpSocket {
+        self.sockets
+            .iter()
+            .find(|(port, _)| *port == port_pair.host)
+            .unwrap_or(&self.sockets[0])
+            .1
+            .as_ref()
+    }
+
+    fn lock_routes(&self) -> MutexGuard<'_, Routes> {
+        self.routes.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Drop for Dispatcher {
+    fn drop(&mut self) {
+        for receiver in &self.receivers {
+            receiver.abort();
+        }
+    }
+}
+
+fn bind_socket(addr: SocketAddr, mode: BindMode) -> Result<std::net::UdpSocket, Error> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if mode == BindMode::Shared {
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+    }
+    socket.set_broadcast(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
+
+async fn receive(socket: Arc<UdpSocket>, routes: Arc<Mutex<Routes>>) {
+    let mut buf = [0; 2048];
+    loop {
+        //ICMP port unreachable shows up as one of these on some platforms and only concerns a single datagram,
+        //anything else would fail again on every call
+        let (len, src_addr) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    ErrorKind::ConnectionReset | ErrorKind::ConnectionRefused
+                ) =>
+            {
+                continue
+            }
+            Err(err) => {
+                routes
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .fail(&err);
+                return;
+            }
+        };
+        if len < 32 {
+            continue;
+        }
+        let switch_mac: [u8; 6] = buf[14..20].try_into().unwrap();
+        let seq: [u8; 2] = buf[22..24].try_into().unwrap();
+        let reply = Response::build(&buf[..len])
+            .map_err(|err| Error::new(ErrorKind::InvalidData, err.to_string()));
+
+        let mut routes = routes.lock().unwrap_or_else(PoisonError::into_inner);
+        let recorder = routes.recorder.clone();
+        let (request, waiter) =
+            if let Some((request, sender)) = routes.replies.remove(&(switch_mac, seq)) {
+                (request, Waiter::Reply(sender))
+            } else if let Some((request, port_pair, sender)) = routes.probes.get(&seq) {
+                (request.clone(), Waiter::Probe(sender.clone(), *port_pair))
+            } else {
+                continue;
+            };
+        drop(routes);
+
+        //Written before the reply is handed over so a caller never sees a reply missing from the capture,
+        //a failed write only costs the capture
+        if let Some(recorder) = recorder {
+            let _ = recorder.record(&request, &buf[..len]);
+        }
+        match waiter {
+            Waiter::Reply(sender) => {
+                let _ = sender.send(reply);
+            }
+            Waiter::Probe(sender, port_pair) => {
+                let _ = sender.send((reply, src_addr, port_pair));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::BlockingClient;
+    use crate::cmds::{Cmd, ProtoConsts, TypeLengthValue};
+    use crate::request::Session;
+    use std::net::{Ipv4Addr, UdpSocket as StdUdpSocket};
+
+    fn loopback(port: u16) -> SocketAddr {
+        SocketAddr::from((Ipv4Addr::LOCALHOST, port))
+    }
+
+    #[test]
+    fn a_taken_port_is_explained() {
+        let first = BlockingClient::bind(loopback(0)).unwrap();
+        let taken = first.local_addrs().unwrap()[0];
+
+        let err = BlockingClient::bind(taken).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::AddrInUse);
+        assert!(err.to_string().contains(&taken.to_string()));
+        assert!(err.to_string().contains("another pputl"));
+    }
+
+    #[test]
+    fn shared_ports_take_several_listeners() {
+        let first = BlockingClient::bind_with(&[loopback(0)], BindMode::Shared).unwrap();
+        let port = first.local_addrs().unwrap()[0].port();
+
+        let second = BlockingClient::bind_with(&[loopback(port)], BindMode::Shared).unwrap();
+        assert_eq!(second.local_addrs().unwrap()[0].port(), port);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn requests_leave_from_the_host_port_of_their_pair() {
+        let dispatcher = Dispatcher::bind_with(&[loopback(0), loopback(0)], BindMode::Exclusive)
+            .await
+            .unwrap();
+        let host = dispatcher.local_addrs().unwrap()[1].port();
+
+        //Echoes the request back as the reply and tells which port it came from
+        let switch = StdUdpSocket::bind(loopback(0)).unwrap();
+        let port_pair = PortPair {
+            host,
+            switch: switch.local_addr().unwrap().port(),
+        };
+        let echo = std::thread::spawn(move || {
+            let mut buf = [0; 2048];
+            let (len, src_addr) = switch.recv_from(&mut buf).unwrap();
+            buf[0..2].copy_from_slice(ProtoConsts::QueryResponse.value());
+            switch.send_to(&buf[..len], src_addr).unwrap();
+            src_addr.port()
+        });
+
+        let reply = dispatcher
+            .exchange(
+                &request(&dispatcher),
+                Ipv4Addr::LOCALHOST.into(),
+                port_pair,
+                Duration::from_secs(1),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(echo.join().unwrap(), host);
+        assert_eq!(reply.get_ctype(), ProtoConsts::QueryResponse.value());
+    }
+
+    fn request(dispatcher: &Dispatcher) -> Request {
+        Request::builder()
+            .ctype(ProtoConsts::QueryRequest.value().try_into().unwrap())
+            .session(Session::new(
+                ProtoConsts::MACMyPC.value().try_into().unwrap(),
+                [0x00, 0x11, 0x22, 0x33, 0x44, 0x55],
+                dispatcher.next_seq(),
+            ))
+            .add_cmd(TypeLengthValue::from(Cmd::CMD_Name))
+            .build()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn late_replies_are_not_taken_for_the_next_one() {
+        let dispatcher = Dispatcher::bind(loopback(0)).await.unwrap();
+
+        //Answers the first request only after the second arrived, then the second
+        let switch = StdUdpSocket::bind(loopback(0)).unwrap();
+        let port_pair = PortPair {
+            host: 0,
+            switch: switch.local_addr().unwrap().port(),
+        };
+        std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            let mut buf = [0; 2048];
+            while requests.len() < 2 {
+                let (len, src_addr) = switch.recv_from(&mut buf).unwrap();
+                buf[0..2].copy_from_slice(ProtoConsts::QueryResponse.value());
+                requests.push((buf[..len].to_vec(), src_addr));
+            }
+            for (reply, src_addr) in requests {
+                switch.send_to(&reply, src_addr).unwrap();
+            }
+        });
+
+        let first = request(&dispatcher);
+        let err = dispatcher
+            .exchange(
+                &first,
+                Ipv4Addr::LOCALHOST.into(),
+                port_pair,
+                Duration::from_millis(100),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+
+        let second = request(&dispatcher);
+        let reply = dispatcher
+            .exchange(
+                &second,
+                Ipv4Addr::LOCALHOST.into(),
+                port_pair,
+                Duration::from_secs(1),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            reply.get_session().get_seq(),
+            second.get_session().get_seq()
+        );
+    }
+}
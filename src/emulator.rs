@@ -0,0 +1,564 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Error;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use strum::IntoEnumIterator;
+
+use crate::cmds::{Cmd, ProtoConsts, TypeLengthValue};
+use crate::packet::{Packet, PacketType};
+use crate::record::Replay;
+use crate::request::Session;
+use crate::tftp;
+use crate::values::{bitmap_len, encode_password};
+
+//
+//Emulated switches for testing without hardware. They answer queries from an in-memory TLV store, check the
+//password on every transmit and can be told to reject TLVs or to lose requests. Reboots and firmware upgrades
+//make them go silent for a while like real switches
+//
+
+//Status codes in the reply header, the TLV the switch stumbled over follows the status
+pub const STATUS_READ_ONLY: [u8; 2] = [0x03, 0x00];
+pub const STATUS_INVALID_VALUE: [u8; 2] = [0x05, 0x00];
+pub const STATUS_DENIED: [u8; 2] = [0x07, 0x00];
+
+//How often the serving thread checks whether it should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+//How long a restarting switch doesn't answer unless told otherwise
+const RESTART_TIME: Duration = Duration::from_secs(2);
+//How long the bootloader tries to fetch a firmware image
+const UPGRADE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct EmulatedSwitch {
+    mac: [u8; 6],
+    password: Vec<u8>,
+    tlvs: BTreeMap<[u8; 2], Vec<Vec<u8>>>,
+    failures: HashMap<[u8; 2], [u8; 2]>,
+    loss: f64,
+    writes: Vec<TypeLengthValue>,
+    restart_delay: Duration,
+    restart_time: Duration,
+    offline: Option<(Instant, Instant)>, //Start and end of the current restart
+    upgrade: Option<FirmwareUpgrade>,
+    image: Option<Vec<u8>>,
+}
+
+//Where the bootloader fetches a new image from and the version the switch runs once it has it
+#[derive(Debug, Clone)]
+struct FirmwareUpgrade {
+    server: SocketAddr,
+    version: String,
+    state: Arc<Mutex<UpgradeState>>,
+}
+
+#[derive(Debug, Default)]
+enum UpgradeState {
+    #[default]
+    Idle,
+    Fetching,
+    Fetched(Vec<u8>),
+    Failed,
+}
+
+struct Rejection {
+    status: [u8; 2],
+    cmd: [u8; 2],
+}
+
+impl EmulatedSwitch {
+    //A switch fresh from the factory: password "password", DHCP on and only port 1 linked
+    pub fn new(model: &str, mac: [u8; 6], port_count: u8) -> EmulatedSwitch {
+        let ports = 1..=port_count;
+        let mut switch = EmulatedSwitch {
+            mac,
+            password: encode_password("password"),
+            tlvs: BTreeMap::new(),
+            failures: HashMap::new(),
+            loss: 0.0,
+            writes: Vec::new(),
+            restart_delay: Duration::ZERO,
+            restart_time: RESTART_TIME,
+            offline: None,
+            upgrade: None,
+            image: None,
+        };
+
+        switch.set(Cmd::CMD_Model, vec![model.as_bytes().to_vec()]);
+        switch.set(Cmd::CMD_Name, vec![Vec::new()]);
+        switch.set(Cmd::CMD_Switch_MAC, vec![mac.to_vec()]);
+        switch.set(Cmd::CMD_Location, vec![Vec::new()]);
+        switch.set(Cmd::CMD_IPv4, vec![vec![192, 168, 0, 239]]);
+        switch.set(Cmd::CMD_Switch_Netmask, vec![vec![255, 255, 255, 0]]);
+        switch.set(Cmd::CMD_Switch_Gateway, vec![vec![192, 168, 0, 254]]);
+        switch.set(Cmd::CMD_Switch_DHCP, vec![vec![0x01]]);
+        switch.set(Cmd::CMD_FW_Version, vec![b"1.00.10".to_vec()]);
+        switch.set(Cmd::CMD_FW_Version_2, vec![Vec::new()]);
+        switch.set(Cmd::CMD_FW_Active, vec![vec![0x01]]);
+        switch.set(Cmd::CMD_Port_Count, vec![vec![port_count]]);
+        switch.set(
+            Cmd::CMD_Port_Status,
+            ports
+                .clone()
+                .map(|port| vec![port, if port == 1 { 0x05 } else { 0x00 }, 0x01])
+                .collect(),
+        );
+        switch.set(
+            Cmd::CMD_Port_Speed_Config,
+            ports.clone().map(|port| vec![port, 0x01]).collect(),
+        );
+        switch.set(
+            Cmd::CMD_Port_Flow_Control,
+            ports.clone().map(|port| vec![port, 0x00]).collect(),
+        );
+        //Port followed by six 8 byte counters, starting with bytes received, bytes sent and CRC errors
+        switch.set(
+            Cmd::CMD_Port_Statistics,
+            ports
+                .map(|port| {
+                    let mut record = vec![port];
+                    record.resize(49, 0x00);
+                    record
+                })
+                .collect(),
+        );
+        let mut mirror = vec![0x00, 0x00];
+        mirror.resize(2 + bitmap_len(port_count), 0x00);
+        switch.set(Cmd::CMD_Port_Mirroring, vec![mirror]);
+        switch.set(Cmd::CMD_IGMP_Snooping, vec![vec![0x00, 0x00, 0x00, 0x01]]);
+        switch.set(Cmd::CMD_IGMP_Block_Unknown_Multicast, vec![vec![0x00]]);
+        switch.set(Cmd::CMD_IGMP_Validate_IP_Header, vec![vec![0x00]]);
+        switch.set(
+            Cmd::CMD_IGMP_Router_Ports,
+            vec![vec![0x00; bitmap_len(port_count)]],
+        );
+        switch.set(Cmd::CMD_Loop_Detection, vec![vec![0x00]]);
+        switch
+    }
+
+    pub fn with_name(mut self, name: &str) -> EmulatedSwitch {
+        self.set(Cmd::CMD_Name, vec![name.as_bytes().to_vec()]);
+        self
+    }
+
+    pub fn with_password(mut self, plain: &str) -> EmulatedSwitch {
+        self.password = encode_password(plain);
+        self
+    }
+
+    //Share of requests that go unanswered, from 0.0 for none to 1.0 for all
+    pub fn with_loss(mut self, loss: f64) -> EmulatedSwitch {
+        self.loss = loss;
+        self
+    }
+
+    //Every request carrying the TLV is rejected with the status
+    pub fn with_failure(mut self, cmd: Cmd, status: [u8; 2]) -> EmulatedSwitch {
+        self.failures.insert(cmd.into(), status);
+        self
+    }
+
+    //After a reboot the switch keeps answering for `delay` and is then silent for `time`
+    pub fn with_restart(mut self, delay: Duration, time: Duration) -> EmulatedSwitch {
+        self.restart_delay = delay;
+        self.restart_time = time;
+        self
+    }
+
+    //Entering upgrade mode makes the switch read an image from the TFTP server. Once the whole image arrived
+    //the switch restarts running `version`, a failed transfer leaves the old version running
+    pub fn with_firmware_upgrade(mut self, server: SocketAddr, version: &str) -> EmulatedSwitch {
+        self.upgrade = Some(FirmwareUpgrade {
+            server,
+            version: version.to_string(),
+            state: Arc::default(),
+        });
+        self
+    }
+
+    //The last image fetched in upgrade mode
+    pub fn firmware_image(&self) -> Option<&Vec<u8>> {
+        self.image.as_ref()
+    }
+
+    pub fn mac(&self) -> [u8; 6] {
+        self.mac
+    }
+
+    //Replaces what a query for the TLV returns, per port TLVs hold one value per port
+    pub fn set(&mut self, cmd: Cmd, values: Vec<Vec<u8>>) {
+        self.tlvs.insert(cmd.into(), values);
+    }
+
+    pub fn get(&self, cmd: &Cmd) -> Option<&Vec<Vec<u8>>> {
+        self.tlvs.get(&<[u8; 2]>::from(cmd))
+    }
+
+    //Every TLV accepted by a transmit so far, in order and without the password
+    pub fn writes(&self) -> &[TypeLengthValue] {
+        &self.writes
+    }
+
+    //Builds the reply to a request, None when it is not for this switch or got lost
+    pub fn handle(&mut self, request: &Packet) -> Option<Packet> {
+        let dest_mac = request.get_session().get_switch_mac();
+        let broadcast = dest_mac == ProtoConsts::MACBroadcast.value();
+        if dest_mac != self.mac && !broadcast {
+            return None;
+        }
+        if self.loss > 0.0 && rand::random::<f64>() < self.loss {
+            return None;
+        }
+        self.finish_upgrade();
+        if self.is_restarting() {
+            return None;
+        }
+        self.finish_restart();
+
+        let result = match request.get_type() {
+            PacketType::QueryRequest => self.query(request.get_cmds()),
+            PacketType::TransmitRequest if !broadcast => {
+                self.transmit(request.get_cmds()).map(|_| Vec::new())
+            }
+            _ => return None,
+        };
+
+        let session = request.get_session();
+        let reply = Packet::new(
+            request.get_type().reply_type(),
+            Session::new(session.get_host_mac(), self.mac, session.get_seq()),
+        );
+        Some(match result {
+            Ok(tlvs) => tlvs.into_iter().fold(reply, Packet::with_cmd),
+            Err(rejection) => reply.with_status(rejection.status, rejection.cmd),
+        })
+    }
+
+    //Queries carrying a value only get the records starting with it, e.g. the cable test result of one port
+    fn query(&self, tlvs: &[TypeLengthValue]) -> Result<Vec<TypeLengthValue>, Rejection> {
+        let mut answers = Vec::new();
+        for tlv in tlvs {
+            self.check_failure(tlv)?;
+            for value in self.tlvs.get(&tlv.cmd()).into_iter().flatten() {
+                if value.starts_with(tlv.value()) {
+                    answers.push(TypeLengthValue::from((
+                        tlv.cmd(),
+                        value.len() as u16,
+                        value.clone(),
+                    )));
+                }
+            }
+        }
+        Ok(answers)
+    }
+
+    //Nothing is written unless the password is right and every TLV is accepted
+    fn transmit(&mut self, tlvs: &[TypeLengthValue]) -> Result<(), Rejection> {
+        let password_cmd: [u8; 2] = Cmd::CMD_Password.into();
+        match tlvs.first() {
+            Some(tlv) if tlv.cmd() == password_cmd && tlv.value() == self.password => {}
+            _ => {
+                return Err(Rejection {
+                    status: STATUS_DENIED,
+                    cmd: password_cmd,
+                })
+            }
+        }
+
+        let tlvs = &tlvs[1..];
+        for tlv in tlvs {
+            self.check_failure(tlv)?;
+            if !Cmd::iter().any(|cmd| cmd.is_writable() && tlv.cmd() == <[u8; 2]>::from(cmd)) {
+                return Err(Rejection {
+                    status: STATUS_READ_ONLY,
+                    cmd: tlv.cmd(),
+                });
+            }
+            if tlv.value().is_empty() {
+                return Err(Rejection {
+                    status: STATUS_INVALID_VALUE,
+                    cmd: tlv.cmd(),
+                });
+            }
+        }
+
+        for tlv in tlvs {
+            self.write(tlv);
+            self.writes.push(tlv.clone());
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, tlv: &TypeLengthValue) {
+        let cmd = tlv.cmd();
+        let value = tlv.value().to_vec();
+        if cmd == <[u8; 2]>::from(Cmd::CMD_New_Password) {
+            self.password = value;
+        } else if cmd == <[u8; 2]>::from(Cmd::CMD_Cable_Test_Request) {
+            //The test finds four good pairs unless a result for the port was set up front
+            let results = self
+                .tlvs
+                .entry(Cmd::CMD_Cable_Test_Result.into())
+                .or_default();
+            if !results.iter().any(|result| result.first() == value.first()) {
+                let mut result = vec![value[0]];
+                result.resize(1 + 4 * 8, 0x00);
+                results.push(result);
+            }
+        } else if cmd == <[u8; 2]>::from(Cmd::CMD_VLAN_Delete) {
+            for members in [Cmd::CMD_VLAN_Port_Members, Cmd::CMD_VLAN_8021Q_Members] {
+                if let Some(records) = self.tlvs.get_mut(&<[u8; 2]>::from(members)) {
+                    records.retain(|record| record.get(..2) != value.get(..2));
+                }
+            }
+        } else if [
+            <[u8; 2]>::from(Cmd::CMD_Reboot),
+            Cmd::CMD_Factory_Reset.into(),
+            Cmd::CMD_FW_Upgrade.into(),
+        ]
+        .contains(&cmd)
+        {
+            let start = Instant::now() + self.restart_delay;
+            self.offline = Some((start, start + self.restart_time));
+            if cmd == <[u8; 2]>::from(Cmd::CMD_FW_Upgrade) {
+                self.start_upgrade();
+            }
+        } else {
+            let key_len = record_key_len(cmd);
+            let values = self.tlvs.entry(cmd).or_default();
+            match values
+                .iter_mut()
+                .find(|stored| key_len > 0 && stored.get(..key_len) == value.get(..key_len))
+            {
+                Some(stored) => *stored = value,
+                None if key_len > 0 => {
+                    values.push(value);
+                    values.sort();
+                }
+                None => *values = vec![value],
+            }
+        }
+    }
+
+    fn is_restarting(&self) -> bool {
+        let fetching = self.upgrade.as_ref().is_some_and(|upgrade| {
+            matches!(
+                *upgrade.state.lock().unwrap_or_else(PoisonError::into_inner),
+                UpgradeState::Fetching
+            )
+        });
+        fetching
+            || self
+                .offline
+                .is_some_and(|(start, end)| (start..end).contains(&Instant::now()))
+    }
+
+    //The bootloader asks for an image named after the model, the transfer runs while the switch is away
+    fn start_upgrade(&mut self) {
+        let upgrade = match &self.upgrade {
+            Some(upgrade) => upgrade.clone(),
+            None => return,
+        };
+        let file = format!(
+            "{}.bin",
+            String::from_utf8_lossy(&self.get(&Cmd::CMD_Model).unwrap()[0])
+        );
+        let delay = self.restart_delay;
+        *upgrade.state.lock().unwrap_or_else(PoisonError::into_inner) = UpgradeState::Fetching;
+        thread::spawn(move || {
+            thread::sleep(delay);
+            let state = match tftp::fetch(upgrade.server, &file, UPGRADE_TIMEOUT) {
+                Ok(image) => UpgradeState::Fetched(image),
+                Err(_) => UpgradeState::Failed,
+            };
+            *upgrade.state.lock().unwrap_or_else(PoisonError::into_inner) = state;
+        });
+    }
+
+    //Coming back from a restart clears the traffic counters, keeping the port each record leads with
+    fn finish_restart(&mut self) {
+        if self.offline.is_none_or(|(_, end)| Instant::now() < end) {
+            return;
+        }
+        self.offline = None;
+        if let Some(records) = self
+            .tlvs
+            .get_mut(&<[u8; 2]>::from(Cmd::CMD_Port_Statistics))
+        {
+            for counters in records.iter_mut().filter_map(|record| record.get_mut(1..)) {
+                counters.fill(0x00);
+            }
+        }
+    }
+
+    //Installs a fetched image into the running bank and restarts into it
+    fn finish_upgrade(&mut self) {
+        let upgrade = match &self.upgrade {
+            Some(upgrade) => upgrade.clone(),
+            None => return,
+        };
+        let mut state = upgrade.state.lock().unwrap_or_else(PoisonError::into_inner);
+        match std::mem::take(&mut *state) {
+            UpgradeState::Fetched(image) => {
+                let running = match self.get(&Cmd::CMD_FW_Active).map(|active| active[0][0]) {
+                    Some(0x02) => Cmd::CMD_FW_Version_2,
+                    _ => Cmd::CMD_FW_Version,
+                };
+                self.set(running, vec![upgrade.version.into_bytes()]);
+                self.image = Some(image);
+            }
+            UpgradeState::Failed => {}
+            unfinished => {
+                *state = unfinished;
+                return;
+            }
+        }
+        self.offline = Some((Instant::now(), Instant::now() + self.restart_time));
+    }
+
+    fn check_failure(&self, tlv: &TypeLengthValue) -> Result<(), Rejection> {
+        match self.failures.get(&tlv.cmd()) {
+            Some(status) => Err(Rejection {
+                status: *status,
+                cmd: tlv.cmd(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+//Leading bytes telling the records of a TLV apart: the port for per port settings and the VLAN ID for VLAN
+//members. Writes replace the record with the same key, TLVs without a key are replaced as a whole
+fn record_key_len(cmd: [u8; 2]) -> usize {
+    let per_port = [
+        Cmd::CMD_Port_Status,
+        Cmd::CMD_Port_Statistics,
+        Cmd::CMD_Cable_Test_Result,
+        Cmd::CMD_VLAN_PVID,
+        Cmd::CMD_QoS_Port_Priority,
+        Cmd::CMD_Ingress_Rate_Limit,
+        Cmd::CMD_Egress_Rate_Limit,
+        Cmd::CMD_Storm_Control_Rate,
+        Cmd::CMD_Port_Speed_Config,
+        Cmd::CMD_Port_Flow_Control,
+    ];
+    let per_vlan = [Cmd::CMD_VLAN_Port_Members, Cmd::CMD_VLAN_8021Q_Members];
+    if per_port
+        .iter()
+        .any(|port_cmd| cmd == <[u8; 2]>::from(port_cmd))
+    {
+        1
+    } else if per_vlan
+        .iter()
+        .any(|vlan_cmd| cmd == <[u8; 2]>::from(vlan_cmd))
+    {
+        2
+    } else {
+        0
+    }
+}
+
+//Serves emulated switches or a replay on one UDP port until dropped. Requests are answered by every switch they
+//are addressed to, and replies go straight back to where the request came from
+pub struct Emulator {
+    local_addr: SocketAddr,
+    switches: Arc<Mutex<Vec<EmulatedSwitch>>>,
+    stop: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Emulator {
+    pub fn bind(addr: SocketAddr, switches: Vec<EmulatedSwitch>) -> Result<Emulator, Error> {
+        let switches = Arc::new(Mutex::new(switches));
+        let served = switches.clone();
+        Emulator::serve(addr, switches, move |datagram| {
+            let request = match Packet::parse(datagram) {
+                Ok(request) => request,
+                Err(_) => return Vec::new(),
+            };
+            served
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .iter_mut()
+                .filter_map(|switch| switch.handle(&request))
+                .map(|reply| reply.format())
+                .collect()
+        })
+    }
+
+    //Answers with recorded replies instead of emulated switches, the list of switches stays empty
+    pub fn replay(addr: SocketAddr, mut replay: Replay) -> Result<Emulator, Error> {
+        Emulator::serve(addr, Arc::new(Mutex::new(Vec::new())), move |datagram| {
+            replay.answer(datagram)
+        })
+    }
+
+    fn serve<F>(
+        addr: SocketAddr,
+        switches: Arc<Mutex<Vec<EmulatedSwitch>>>,
+        handler: F,
+    ) -> Result<Emulator, Error>
+    where
+        F: FnMut(&[u8]) -> Vec<Vec<u8>> + Send + 'static,
+    {
+        let socket = UdpSocket::bind(addr).map_err(|err| {
+            Error::new(
+                err.kind(),
+                format!("Could not bind {} for the emulator: {}", addr, err),
+            )
+        })?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let local_addr = socket.local_addr()?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker = {
+            let stop = stop.clone();
+            thread::spawn(move || answer(socket, handler, stop))
+        };
+
+        Ok(Emulator {
+            local_addr,
+            switches,
+            stop,
+            worker: Some(worker),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    //The switches being served, their state can be inspected and changed while the emulator runs
+    pub fn switches(&self) -> MutexGuard<'_, Vec<EmulatedSwitch>> {
+        self.switches.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn answer<F>(socket: UdpSocket, mut handler: F, stop: Arc<AtomicBool>)
+where
+    F: FnMut(&[u8]) -> Vec<Vec<u8>>,
+{
+    let mut buf = [0; 2048];
+    while !stop.load(Ordering::Relaxed) {
+        let (len, src_addr) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(_) => continue,
+        };
+        for reply in handler(&buf[..len]) {
+            let _ = socket.send_to(&reply, src_addr);
+        }
+    }
+}
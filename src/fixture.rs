@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+
+use serde::Deserialize;
+
+use crate::cmds::Cmd;
+use crate::emulator::EmulatedSwitch;
+use crate::values::{from_hex, parse_mac, PortSet, MAX_PORTS};
+
+//
+//Fleets of emulated switches described in TOML. An entry with a count stands for that many switches, their
+//MACs and addresses count up from the ones given and {n} in the name becomes the number of the switch
+//
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+pub struct Fixture {
+    #[serde(default, rename = "switch")]
+    pub switches: Vec<SwitchFixture>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SwitchFixture {
+    pub name: String,
+    pub mac: String,
+    pub model: String,
+    pub ports: u8,
+    #[serde(default = "default_count")]
+    pub count: u32,
+    #[serde(default)]
+    pub location: Option<String>,
+    #[serde(default)]
+    pub ip: Option<Ipv4Addr>,
+    #[serde(default)]
+    pub firmware: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub loss: f64,
+    //Ports with a gigabit link, only port 1 when left out
+    #[serde(default)]
+    pub linked: Option<String>,
+    #[serde(default)]
+    pub vlans: Vec<VlanFixture>,
+    #[serde(default)]
+    pub stats: Vec<StatsFixture>,
+    //Anything else as raw hex values keyed by TLV name, like the tlvs table of backups
+    #[serde(default)]
+    pub tlvs: BTreeMap<String, Vec<String>>,
+}
+
+//802.1Q VLAN, member ports not tagged take the VLAN as their PVID
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct VlanFixture {
+    pub id: u16,
+    pub members: String,
+    #[serde(default)]
+    pub tagged: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StatsFixture {
+    pub port: u8,
+    #[serde(default)]
+    pub received: u64,
+    #[serde(default)]
+    pub sent: u64,
+    #[serde(default)]
+    pub crc_errors: u64,
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+impl Fixture {
+    pub fn from_toml(toml: &str) -> Result<Fixture, String> {
+        toml::from_str(toml).map_err(|err| err.to_string())
+    }
+
+    pub fn switches(&self) -> Result<Vec<EmulatedSwitch>, String> {
+        let mut switches = Vec::new();
+        for fixture in &self.switches {
+            switches.append(&mut fixture.build()?);
+        }
+        Ok(switches)
+    }
+}
+
+impl SwitchFixture {
+    pub fn build(&self) -> Result<Vec<EmulatedSwitch>, String> {
+        if self.ports == 0 || self.ports > MAX_PORTS {
+            return Err(format!(
+                "{}: a switch has 1 to {} ports, not {}",
+                self.name, MAX_PORTS, self.ports
+            ));
+        }
+        let mac =
+            parse_mac(&self.mac).ok_or(format!("{}: invalid MAC '{}'", self.name, self.mac))?;
+        let first_mac = u64::from_be_bytes([0, 0, mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]]);
+
+        (0..self.count)
+            .map(|index| {
+                let mac: [u8; 6] = (first_mac + u64::from(index)).to_be_bytes()[2..]
+                    .try_into()
+                    .unwrap();
+                let name = self.name.replace("{n}", &(index + 1).to_string());
+                let mut switch = EmulatedSwitch::new(&self.model, mac, self.ports)
+                    .with_name(&name)
+                    .with_loss(self.loss);
+                if let Some(password) = &self.password {
+                    switch = switch.with_password(password);
+                }
+                self.fill(&mut switch, index)
+                    .map_err(|err| format!("{}: {}", name, err))?;
+                Ok(switch)
+            })
+            .collect()
+    }
+
+    fn fill(&self, switch: &mut EmulatedSwitch, index: u32) -> Result<(), String> {
+        let ports = 1..=self.ports;
+        if let Some(location) = &self.location {
+            switch.set(Cmd::CMD_Location, vec![location.as_bytes().to_vec()]);
+        }
+        if let Some(ip) = self.ip {
+            let ip = Ipv4Addr::from(u32::from(ip).wrapping_add(index));
+            switch.set(Cmd::CMD_IPv4, vec![ip.octets().to_vec()]);
+            switch.set(Cmd::CMD_Switch_DHCP, vec![vec![0x00]]);
+        }
+        if let Some(firmware) = &self.firmware {
+            switch.set(Cmd::CMD_FW_Version, vec![firmware.as_bytes().to_vec()]);
+        }
+        if let Some(linked) = &self.linked {
+            let linked: PortSet = linked.parse()?;
+            switch.set(
+                Cmd::CMD_Port_Status,
+                ports
+                    .clone()
+                    .map(|port| {
+                        let up = linked.iter().any(|linked| linked.number() == port);
+                        vec![port, if up { 0x05 } else { 0x00 }, 0x01]
+                    })
+                    .collect(),
+            );
+        }
+
+        if !self.vlans.is_empty() {
+            let mut members = Vec::new();
+            let mut pvids: BTreeMap<u8, u16> = BTreeMap::new();
+            for vlan in &self.vlans {
+                let member_ports: PortSet = vlan.members.parse()?;
+                let tagged_ports: PortSet = vlan.tagged.parse()?;
+                let mut record = vlan.id.to_be_bytes().to_vec();
+                record.append(&mut member_ports.to_bitmap(self.ports));
+                record.append(&mut tagged_ports.to_bitmap(self.ports));
+                members.push(record);
+                for port in member_ports
+                    .iter()
+                    .filter(|port| !tagged_ports.contains(*port))
+                {
+                    pvids.entry(port.number()).or_insert(vlan.id);
+                }
+            }
+            switch.set(Cmd::CMD_VLAN_Mode, vec![vec![0x04]]);
+            switch.set(Cmd::CMD_VLAN_8021Q_Members, members);
+            switch.set(
+                Cmd::CMD_VLAN_PVID,
+                ports
+                    .clone()
+                    .map(|port| {
+                        let mut record = vec![port];
+                        record.extend(pvids.get(&port).unwrap_or(&1).to_be_bytes());
+                        record
+                    })
+                    .collect(),
+            );
+        }
+
+        if !self.stats.is_empty() {
+            let mut records = switch
+                .get(&Cmd::CMD_Port_Statistics)
+                .cloned()
+                .unwrap_or_default();
+            for stats in &self.stats {
+                let record = records
+                    .iter_mut()
+                    .find(|record| record.first() == Some(&stats.port))
+                    .ok_or(format!("No port {} for statistics", stats.port))?;
+                record[1..9].copy_from_slice(&stats.received.to_be_bytes());
+                record[9..17].copy_from_slice(&stats.sent.to_be_bytes());
+                record[17..25].copy_from_slice(&stats.crc_errors.to_be_bytes());
+            }
+            switch.set(Cmd::CMD_Port_Statistics, records);
+        }
+
+        for (name, values) in &self.tlvs {
+            let cmd = Cmd::from_name(name).ok_or(format!("Unknown TLV {}", name))?;
+            let values = values
+                .iter()
+                .map(|value| from_hex(value))
+                .collect::<Result<Vec<Vec<u8>>, String>>()?;
+            switch.set(cmd, values);
+        }
+        Ok(())
+    }
+}
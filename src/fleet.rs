@@ -0,0 +1,291 @@
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::values::format_mac;
+use crate::Switch;
+
+//
+//Runs one operation against many switches at once, a bounded number at a time
+//
+
+//Switch property a filter is matched against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKey {
+    Name,
+    Model,
+    Mac,
+    Ip,
+    Location,
+}
+
+//Selects switches by a glob pattern on one of their properties, e.g. model=GS105E*
+#[derive(Debug, Clone, PartialEq)]
+pub struct SwitchFilter {
+    key: FilterKey,
+    pattern: String,
+}
+
+impl SwitchFilter {
+    pub fn matches(&self, switch: &Switch) -> bool {
+        let value = match self.key {
+            FilterKey::Name => switch.name.clone(),
+            FilterKey::Model => switch.model.clone(),
+            FilterKey::Mac => format_mac(&switch.mac_address),
+            FilterKey::Ip => switch.ipv4_address_reported.to_string(),
+            FilterKey::Location => switch.location.clone(),
+        };
+        glob_match(
+            &self.pattern.to_ascii_lowercase(),
+            &value.to_ascii_lowercase(),
+        )
+    }
+}
+
+impl FromStr for SwitchFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, pattern) = s
+            .split_once('=')
+            .ok_or(format!("'{}' is not of the form key=pattern", s))?;
+        let key = match key.trim().to_ascii_lowercase().as_str() {
+            "name" => FilterKey::Name,
+            "model" => FilterKey::Model,
+            "mac" => FilterKey::Mac,
+            "ip" => FilterKey::Ip,
+            "location" => FilterKey::Location,
+            other => {
+                return Err(format!(
+                    "Unknown filter key '{}', use name, model, mac, ip or location",
+                    other
+                ))
+            }
+        };
+        Ok(SwitchFilter {
+            key,
+            pattern: pattern.trim().to_string(),
+        })
+    }
+}
+
+//'*' matches any run of characters and '?' a single one
+fn glob_match(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let value: Vec<char> = value.chars().collect();
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                //Let the last star swallow one more character and try again
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star + 1;
+                    v = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+pub struct Outcome {
+    pub switch: Switch,
+    pub result: Result<Vec<u8>, Error>,
+    pub elapsed: Duration,
+}
+
+//Runs the job for every switch with at most `concurrency` running at once, `done` is called as each finishes.
+//A job taking longer than the timeout is reported as timed out, but a thread can't be stopped from outside so it
+//carries on and may still write to its switch. It keeps its slot until it ends, so timed out jobs never push
+//the number of threads talking to switches past `concurrency`. Jobs still running when the last switch is
+//reported are cut off when the process exits
+pub fn run<F>(
+    switches: Vec<Switch>,
+    concurrency: usize,
+    timeout: Duration,
+    job: F,
+    mut done: impl FnMut(&Outcome),
+) -> Vec<Outcome>
+where
+    F: Fn(&Switch) -> Result<Vec<u8>, Error> + Send + Sync + 'static,
+{
+    let job = Arc::new(job);
+    let (sender, receiver) = mpsc::channel();
+    let mut pending = switches.into_iter().enumerate();
+    let mut running: Vec<(usize, Switch, Instant)> = Vec::new();
+    let mut abandoned = 0;
+    let mut outcomes = Vec::new();
+
+    loop {
+        while running.len() + abandoned < concurrency.max(1) {
+            let (index, switch) = match pending.next() {
+                Some(next) => next,
+                None => break,
+            };
+            let (job, sender, worker_switch) = (job.clone(), sender.clone(), switch.clone());
+            thread::spawn(move || {
+                //Nobody listens anymore when the job already timed out
+                let _ = sender.send((index, job(&worker_switch)));
+            });
+            running.push((index, switch, Instant::now()));
+        }
+
+        let deadline = match running
+            .iter()
+            .map(|(_, _, started)| *started + timeout)
+            .min()
+        {
+            Some(deadline) => deadline,
+            //Every slot is held by a timed out job, wait for one of them to give its slot back
+            None if abandoned > 0 && pending.len() > 0 => {
+                if receiver.recv().is_ok() {
+                    abandoned -= 1;
+                }
+                continue;
+            }
+            None => break,
+        };
+
+        let finished =
+            match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok((index, result)) => {
+                    let position = running
+                        .iter()
+                        .position(|(running_index, _, _)| *running_index == index);
+                    if position.is_none() {
+                        abandoned -= 1;
+                    }
+                    position.map(|position| (position, result, false))
+                }
+                Err(_) => running
+                    .iter()
+                    .position(|(_, _, started)| started.elapsed() >= timeout)
+                    .map(|position| {
+                        (
+                            position,
+                            Err(Error::new(
+                                ErrorKind::TimedOut,
+                                format!("No result within {}s", timeout.as_secs_f32()),
+                            )),
+                            true,
+                        )
+                    }),
+            };
+
+        if let Some((position, result, timed_out)) = finished {
+            if timed_out {
+                abandoned += 1;
+            }
+            let (_, switch, started) = running.swap_remove(position);
+            let outcome = Outcome {
+                switch,
+                result,
+                elapsed: started.elapsed(),
+            };
+            done(&outcome);
+            outcomes.push(outcome);
+        }
+    }
+
+    outcomes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pputl::dispatch::PortPair;
+    use std::net::Ipv4Addr;
+
+    fn switch(name: &str, model: &str, last_octet: u8) -> Switch {
+        Switch {
+            name: name.to_string(),
+            model: model.to_string(),
+            location: String::from("rack 1"),
+            ipv4_address_reported: Ipv4Addr::new(192, 168, 0, last_octet),
+            ipv4_address: format!("192.168.0.{}", last_octet),
+            mac_address: [0x00, 0x11, 0x22, 0x33, 0x44, last_octet],
+            port_pair: PortPair::V1,
+        }
+    }
+
+    #[test]
+    fn globs_match_runs_and_single_characters() {
+        assert!(glob_match("gs105e*", "gs105ev2"));
+        assert!(glob_match("*e*2", "gs105ev2"));
+        assert!(glob_match("gs10?e", "gs108e"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("gs10?e", "gs1008e"));
+        assert!(!glob_match("gs105e", "gs105ev2"));
+    }
+
+    #[test]
+    fn filters_pick_switches_by_property() {
+        let office = switch("Office", "GS105Ev2", 10);
+        let lab = switch("lab", "GS108E", 11);
+
+        let filter: SwitchFilter = "model=GS105E*".parse().unwrap();
+        assert!(filter.matches(&office));
+        assert!(!filter.matches(&lab));
+
+        let filter: SwitchFilter = "mac=*:44:0b".parse().unwrap();
+        assert!(filter.matches(&lab));
+        assert!("NAME = office"
+            .parse::<SwitchFilter>()
+            .unwrap()
+            .matches(&office));
+
+        assert!("model".parse::<SwitchFilter>().is_err());
+        assert!("serial=123".parse::<SwitchFilter>().is_err());
+    }
+
+    #[test]
+    fn runs_every_switch_and_times_out_the_slow_ones() {
+        let switches = vec![
+            switch("a", "GS105E", 1),
+            switch("b", "GS105E", 2),
+            switch("c", "GS105E", 3),
+        ];
+        let mut reported = 0;
+        let outcomes = run(
+            switches,
+            2,
+            Duration::from_millis(300),
+            |switch| {
+                if switch.name == "b" {
+                    thread::sleep(Duration::from_secs(2));
+                }
+                Ok(switch.name.as_bytes().to_vec())
+            },
+            |_| reported += 1,
+        );
+
+        assert_eq!(reported, 3);
+        let mut results: Vec<(String, bool)> = outcomes
+            .iter()
+            .map(|outcome| (outcome.switch.name.clone(), outcome.result.is_ok()))
+            .collect();
+        results.sort();
+        assert_eq!(
+            results,
+            [
+                (String::from("a"), true),
+                (String::from("b"), false),
+                (String::from("c"), true)
+            ]
+        );
+    }
+}
@@ -0,0 +1,20 @@
+//
+//Netgear Switch Discovery Protocol (NSDP) codec and client, the pputl command line tool is built on top
+//
+
+pub mod client;
+pub mod cmds;
+pub mod dispatch;
+pub mod emulator;
+pub mod fixture;
+pub mod models;
+pub mod packet;
+pub mod record;
+pub mod request;
+pub mod response;
+pub mod switch;
+pub mod tftp;
+pub mod values;
+
+pub use client::{BlockingClient, Client};
+pub use switch::Switch;
@@ -1,54 +1,1005 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use dotenv::dotenv;
+use ipnet::Ipv4Net;
+use std::fs;
 use std::io::{self, Write};
-use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
-use std::num::ParseIntError;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::thread;
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::cmds::{Cmd, TypeLengthValue};
-use crate::request::Session;
-use crate::response::Response;
+use pputl::client::{check_probes, DISCOVERY_TIMEOUT};
+use pputl::cmds::{Cmd, TypeLengthValue};
+use pputl::dispatch::{BindMode, PortPair};
+use pputl::record::Recorder;
+use pputl::values::{
+    encode_password, format_mac, from_hex, on_off, parse_mac, parse_on_off, FirmwareBank, Mirror,
+    Port, PortInfo, PortSet, SpeedSetting, PASSWORD_KEY,
+};
+use pputl::{cmds, models, packet, response, tftp, values, BlockingClient, Switch};
+
+use crate::config::{DesiredState, SwitchConfig, CONFIG_VERSION};
+use crate::fleet::SwitchFilter;
 
 mod actions;
-mod cmds;
-mod request;
-mod response;
+mod config;
+mod decode;
+mod fleet;
+mod pcap;
+mod probe;
+
+const REBOOT_TIMEOUT: Duration = Duration::from_secs(180);
+//How long a switch may keep answering after being told to restart, and the pause between discovery rounds
+const REBOOT_GRACE: Duration = Duration::from_secs(15);
+const REBOOT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const FIRMWARE_TRANSFER_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Parser)]
+#[command(
+    name = "pputl",
+    about = "Prosafe plus utility / Netgear Switch Discovery Protocol (NSDP)"
+)]
+struct Cli {
+    /// Run the command on every discovered switch
+    #[arg(long)]
+    all: bool,
+    /// Only run on switches matching KEY=PATTERN (name, model, mac, ip or location), e.g. model=GS105E*
+    #[arg(long, value_name = "KEY=PATTERN")]
+    filter: Vec<SwitchFilter>,
+    /// How many switches are worked on at the same time with --all or --filter
+    #[arg(long, default_value_t = 8)]
+    concurrency: usize,
+    /// Seconds a single switch may take with --all or --filter
+    #[arg(long, default_value_t = 30)]
+    timeout: u64,
+    /// Share ports 63321 and 63323 with other programs like the vendor utility (SO_REUSEADDR/SO_REUSEPORT),
+    /// unicast replies may end up with the other program
+    #[arg(long)]
+    share_port: bool,
+    /// Listen on a random port instead of 63321 and 63323, only switches replying to the source port answer
+    #[arg(long, conflicts_with = "share_port")]
+    ephemeral_port: bool,
+    /// Send requests to the IP of the switch instead of broadcasting them, for switches behind a router
+    #[arg(long)]
+    unicast: bool,
+    /// Find switches by asking these addresses or ranges directly, e.g. 10.1.2.0/24, implies --unicast
+    #[arg(long, value_name = "IP|CIDR", value_parser = parse_probe)]
+    probe: Vec<Ipv4Net>,
+    /// Save every request and reply to a file per switch in this directory, passwords are blanked
+    #[arg(long, value_name = "DIR")]
+    record: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+//Commands act on the switch loaded from the ENV file, running without one starts the interactive menu
+#[derive(Subcommand)]
+enum Command {
+    /// Show the model, firmware and port count
+    Info,
+    /// Show or change port mirroring
+    Mirror {
+        #[command(subcommand)]
+        action: Option<MirrorAction>,
+    },
+    /// Show or change IGMP snooping
+    Igmp {
+        #[command(subcommand)]
+        action: Option<IgmpAction>,
+    },
+    /// Show the link and configuration of every port
+    Ports,
+    /// Change the configuration of a single port
+    Port {
+        #[command(subcommand)]
+        action: PortAction,
+    },
+    /// Run the integrated cable tester
+    CableTest {
+        /// Port to test
+        #[arg(long, required_unless_present = "all", conflicts_with = "all")]
+        port: Option<Port>,
+        /// Test every port one after another
+        #[arg(long)]
+        all: bool,
+    },
+    /// Show or upgrade the firmware
+    Firmware {
+        #[command(subcommand)]
+        action: Option<FirmwareAction>,
+    },
+    /// Save the switch configuration to a file
+    Backup {
+        /// Switch name, MAC or IP address, defaults to the switch in the ENV file
+        switch: Option<String>,
+        /// File to write, e.g. sw1.toml, or a directory to write one file per switch into
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Write a configuration file made by backup to the switch
+    Restore {
+        /// Switch name, MAC or IP address, defaults to the switch in the ENV file
+        switch: Option<String>,
+        /// File to read
+        #[arg(short, long)]
+        input: PathBuf,
+        /// Restore even if the file was taken from a different switch or model
+        #[arg(long)]
+        force: bool,
+    },
+    /// Show how the switches differ from a desired state file, fails when any differ
+    Plan {
+        /// Desired state, switches keyed by alias with an optional mac
+        file: PathBuf,
+    },
+    /// Change the switches to match a desired state file
+    Apply {
+        /// Desired state, switches keyed by alias with an optional mac
+        file: PathBuf,
+    },
+    /// Reboot the switch and wait for it to come back
+    Reboot {
+        /// Switch name, MAC or IP address, defaults to the switch in the ENV file
+        switch: Option<String>,
+    },
+    /// Reset the switch to factory defaults
+    FactoryReset {
+        /// Switch name, MAC or IP address, defaults to the switch in the ENV file
+        switch: Option<String>,
+        /// Confirm that all configuration on the switch should be erased
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Change the admin password to the one in PPUTL_NEW_PASSWORD
+    Password,
+    /// Turn loop detection on or off, or show its status
+    LoopDetection {
+        #[command(subcommand)]
+        action: LoopDetectionAction,
+    },
+    /// Query ranges of TLV IDs to find out what a switch supports, and compare the results
+    Probe {
+        #[command(subcommand)]
+        action: ProbeAction,
+    },
+    /// Print the NSDP packets in a pcap or pcapng capture, e.g. from tcpdump, or a single packet given in hex
+    Decode {
+        /// Capture file
+        #[arg(conflicts_with = "hex")]
+        capture: Option<PathBuf>,
+        /// Packet as hex digits, spaces and colons are ignored. Read from stdin when neither this nor a capture is given
+        #[arg(long)]
+        hex: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ProbeAction {
+    /// Query every TLV ID in the range and save what the switch answers
+    Scan {
+        /// Switch name, MAC or IP address, defaults to the switch in the ENV file
+        switch: Option<String>,
+        /// TLV IDs in hex, e.g. 7400 or 0000-00ff
+        #[arg(long, default_value = "0000-fffe", value_parser = probe::parse_range)]
+        range: RangeInclusive<u16>,
+        /// How many IDs go into one query
+        #[arg(long, default_value_t = 32)]
+        batch: usize,
+        /// File to write, e.g. gs105e.toml, or a directory to write one file per switch into
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Compare the files saved by scan, e.g. from different models or firmware versions
+    Diff {
+        /// Files saved by scan
+        #[arg(num_args = 2.., required = true)]
+        files: Vec<PathBuf>,
+        /// Also list the IDs all switches answer alike
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum MirrorAction {
+    /// Show the current mirroring configuration
+    Show,
+    /// Mirror traffic from the source ports to the destination port
+    Set {
+        /// Port receiving the mirrored traffic
+        #[arg(long)]
+        to: Port,
+        /// Ports to mirror, e.g. 1,2,3 or 1-3
+        #[arg(long)]
+        from: PortSet,
+    },
+    /// Disable port mirroring
+    Off,
+}
+
+#[derive(Subcommand)]
+enum PortAction {
+    /// Change port settings, settings left out are not touched
+    Set {
+        /// Port to change
+        port: Port,
+        /// Speed and duplex: auto, 10h, 10f, 100h, 100f or disable to shut the port
+        #[arg(long)]
+        speed: Option<SpeedSetting>,
+        /// Turn flow control on or off
+        #[arg(long, value_parser = parse_on_off)]
+        flow_control: Option<bool>,
+    },
+}
 
-#[derive(Debug)]
-pub struct Switch {
-    name: String,
-    model: String,
-    location: String,
-    ipv4_address_reported: Ipv4Addr,
+#[derive(Subcommand)]
+enum FirmwareAction {
+    /// Show the firmware images and which one is active
+    Show,
+    /// Put the switch in upgrade mode and serve it the image over TFTP
+    Upgrade {
+        /// Firmware image file
+        image: PathBuf,
+        /// Address the built-in TFTP server listens on
+        #[arg(long, default_value = "0.0.0.0:69")]
+        tftp_listen: SocketAddr,
+    },
+    /// Boot from the given image (1 or 2) from now on and reboot into it
+    Activate {
+        /// Image to boot
+        bank: FirmwareBank,
+        /// Only change the boot image, the switch keeps running the current one
+        #[arg(long)]
+        no_reboot: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum LoopDetectionAction {
+    /// Enable loop detection
+    On,
+    /// Disable loop detection
+    Off,
+    /// Show whether loop detection is enabled
+    Status {
+        /// Check every discovered switch and fail if any has it disabled
+        #[arg(long)]
+        all: bool,
+    },
+}
 
-    ipv4_address: String,
-    mac_address: [u8; 6],
+//Settings left out keep their current value on the switch
+#[derive(Subcommand)]
+enum IgmpAction {
+    /// Show the current IGMP snooping configuration
+    Show,
+    /// Change IGMP snooping settings
+    Set {
+        /// Turn IGMP snooping on or off
+        #[arg(long, value_parser = parse_on_off)]
+        enabled: Option<bool>,
+        /// VLAN to snoop on
+        #[arg(long)]
+        vlan: Option<u16>,
+        /// Validate the IGMPv3 IP header
+        #[arg(long, value_parser = parse_on_off)]
+        validate_ip_header: Option<bool>,
+        /// Block multicast traffic for unknown groups
+        #[arg(long, value_parser = parse_on_off)]
+        block_unknown_multicast: Option<bool>,
+        /// Static router ports, e.g. 1,2 or "" for none
+        #[arg(long)]
+        router_ports: Option<PortSet>,
+    },
 }
 
-fn main() {
+fn main() -> ExitCode {
     dotenv().ok();
 
-    println!("Prosafe plus utility / Netgear Switch Discovery Protocol (NSDP) ");
+    let cli = Cli::parse();
+
+    //Decoding and comparing probes work on files only, no socket is needed
+    let offline = match &cli.command {
+        Some(Command::Decode { capture, hex }) => Some(match capture {
+            Some(capture) => decode_capture(capture),
+            None => decode_hex(hex.as_deref()),
+        }),
+        Some(Command::Probe {
+            action: ProbeAction::Diff { files, all },
+        }) => Some(diff_probes(files, *all)),
+        _ => None,
+    };
+    if let Some(result) = offline {
+        return match result {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let client = match bind_client(&cli) {
+        Ok(client) => Arc::new(client),
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let command = match cli.command {
+        Some(command) => command,
+        None => {
+            println!("Prosafe plus utility / Netgear Switch Discovery Protocol (NSDP) ");
+            while user_input_loop(&client) {}
+            return ExitCode::SUCCESS;
+        }
+    };
+
+    let result = if cli.all || !cli.filter.is_empty() {
+        run_fleet(
+            &client,
+            &cli.filter,
+            cli.concurrency,
+            Duration::from_secs(cli.timeout),
+            command,
+        )
+    } else {
+        run_command(&client, command)
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn bind_client(cli: &Cli) -> Result<BlockingClient, io::Error> {
+    let client = bind_socket(cli)?;
+    //Counted before the hosts are listed, a /8 would list millions of them only for discovery to refuse them
+    check_probes(
+        cli.probe.iter().map(|net| net.hosts().count()).sum(),
+        client.port_pairs().len(),
+    )?;
+    let hosts: Vec<IpAddr> = cli
+        .probe
+        .iter()
+        .flat_map(Ipv4Net::hosts)
+        .map(IpAddr::V4)
+        .collect();
+    let client = client
+        .with_unicast(cli.unicast || !hosts.is_empty())
+        .with_probe_hosts(hosts);
+    match &cli.record {
+        Some(dir) => Ok(client.with_recorder(Recorder::create(dir)?)),
+        None => Ok(client),
+    }
+}
+
+fn bind_socket(cli: &Cli) -> Result<BlockingClient, io::Error> {
+    if cli.ephemeral_port {
+        return BlockingClient::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)));
+    }
+
+    let mode = if cli.share_port {
+        BindMode::Shared
+    } else {
+        BindMode::Exclusive
+    };
+    //One socket per port pair, switches broadcast their replies to the host port of the pair they speak
+    let addrs: Vec<SocketAddr> = [PortPair::V1, PortPair::V2]
+        .iter()
+        .map(|port_pair| SocketAddr::from((Ipv4Addr::UNSPECIFIED, port_pair.host)))
+        .collect();
+    BlockingClient::bind_with(&addrs, mode).map_err(|err| {
+        if err.kind() == io::ErrorKind::AddrInUse && !cli.share_port {
+            io::Error::new(
+                err.kind(),
+                format!(
+                    "{}. Use --share-port to listen alongside it or --ephemeral-port to listen on a random port",
+                    err
+                ),
+            )
+        } else {
+            err
+        }
+    })
+}
+
+//A single address or a CIDR range
+fn parse_probe(value: &str) -> Result<Ipv4Net, String> {
+    value
+        .parse::<Ipv4Net>()
+        .or_else(|_| value.parse::<Ipv4Addr>().map(Ipv4Net::from))
+        .map_err(|_| format!("'{}' is neither an IPv4 address nor a CIDR range", value))
+}
+
+fn run_command(client: &BlockingClient, command: Command) -> Result<(), io::Error> {
+    //Fleet wide commands don't need a particular switch
+    match &command {
+        Command::LoopDetection {
+            action: LoopDetectionAction::Status { all: true },
+        } => return loop_detection_report(client),
+        Command::Plan { file } => return desired_state(client, file, false),
+        Command::Apply { file } => return desired_state(client, file, true),
+        _ => {}
+    }
+
+    let selector = match &command {
+        Command::Reboot { switch }
+        | Command::FactoryReset { switch, .. }
+        | Command::Backup { switch, .. }
+        | Command::Restore { switch, .. }
+        | Command::Probe {
+            action: ProbeAction::Scan { switch, .. },
+        } => switch.clone(),
+        _ => None,
+    };
+    let switch = resolve_switch(client, selector.as_deref())?;
+    run_on_switch(client, &switch, &command, &mut io::stdout())
+}
+
+//Runs a command that is not fleet wide, everything it shows is written to `out`
+fn run_on_switch(
+    client: &BlockingClient,
+    switch: &Switch,
+    command: &Command,
+    out: &mut dyn Write,
+) -> Result<(), io::Error> {
+    let login_tlv = TypeLengthValue::from((Cmd::CMD_Password, password()?));
+    let action = actions::ActionRunner::new(client, switch);
+
+    match command {
+        Command::Info => {
+            let firmware = action.get_firmware()?;
+            writeln!(out, "{}", switch)?;
+            writeln!(
+                out,
+                "Firmware: {}",
+                firmware.active_version().map_or("-", String::as_str)
+            )?;
+            writeln!(out, "Ports: {}", action.port_count()?)
+        }
+        Command::Mirror { action: mirror } => match mirror {
+            None | Some(MirrorAction::Show) => {
+                let mirror = action.get_mirror()?;
+                writeln!(out, "{}", switch)?;
+                writeln!(out, "Mirroring: {}", mirror)
+            }
+            Some(MirrorAction::Set { to, from }) => action.set_mirror(
+                &login_tlv,
+                &Mirror {
+                    destination: Some(*to),
+                    sources: *from,
+                },
+            ),
+            Some(MirrorAction::Off) => action.set_mirror(&login_tlv, &Mirror::default()),
+        },
+        Command::Igmp { action: igmp } => match igmp {
+            None | Some(IgmpAction::Show) => {
+                let igmp = action.get_igmp()?;
+                writeln!(out, "{}", switch)?;
+                writeln!(out, "{}", igmp)
+            }
+            Some(IgmpAction::Set {
+                enabled,
+                vlan,
+                validate_ip_header,
+                block_unknown_multicast,
+                router_ports,
+            }) => action.get_igmp().and_then(|mut igmp| {
+                igmp.enabled = enabled.unwrap_or(igmp.enabled);
+                igmp.vlan = vlan.unwrap_or(igmp.vlan);
+                igmp.validate_ip_header = validate_ip_header.unwrap_or(igmp.validate_ip_header);
+                igmp.block_unknown_multicast =
+                    block_unknown_multicast.unwrap_or(igmp.block_unknown_multicast);
+                igmp.router_ports = router_ports.unwrap_or(igmp.router_ports);
+                action.set_igmp(&login_tlv, &igmp)
+            }),
+        },
+        Command::Ports => {
+            let ports = action.get_ports()?;
+            writeln!(out, "{}", switch)?;
+            print_ports(out, &ports)
+        }
+        Command::Port {
+            action:
+                PortAction::Set {
+                    port,
+                    speed,
+                    flow_control,
+                },
+        } => action.set_port(&login_tlv, *port, *speed, *flow_control),
+        Command::CableTest { port, all } => {
+            let ports: Vec<Port> = match port {
+                Some(port) if !all => vec![*port],
+                _ => (1..=action.port_count()?).filter_map(Port::new).collect(),
+            };
+            writeln!(out, "{}", switch)?;
+            //A port that can't be tested doesn't keep the others from being tested
+            let mut failed = 0;
+            for port in &ports {
+                match action.cable_test(&login_tlv, *port) {
+                    Ok(result) => writeln!(out, "{}", result)?,
+                    Err(err) => {
+                        failed += 1;
+                        writeln!(out, "Port {}: error ({})", port, err)?;
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(io::Error::other(format!(
+                    "Cable test failed on {} of {} ports",
+                    failed,
+                    ports.len()
+                )));
+            }
+            Ok(())
+        }
+        Command::Firmware { action: firmware } => match firmware {
+            None | Some(FirmwareAction::Show) => {
+                let firmware = action.get_firmware()?;
+                writeln!(out, "{}", switch)?;
+                writeln!(out, "{}", firmware)
+            }
+            Some(FirmwareAction::Upgrade { image, tftp_listen }) => upgrade_firmware(
+                client,
+                &action,
+                switch,
+                &login_tlv,
+                image,
+                *tftp_listen,
+                out,
+            ),
+            Some(FirmwareAction::Activate { bank, no_reboot }) => {
+                action.set_active_firmware(&login_tlv, *bank)?;
+                writeln!(out, "{} boots image {} from now on", switch, bank)?;
+                if *no_reboot {
+                    return Ok(());
+                }
+                let traffic = action.get_traffic_total().ok();
+                action.reboot(&login_tlv)?;
+                wait_for_switch(client, &action, switch, traffic, out)?;
+                let firmware = action.get_firmware()?;
+                writeln!(out, "{}", firmware)?;
+                if firmware.active != *bank {
+                    return Err(io::Error::other(format!(
+                        "Switch came back running image {} instead of {}",
+                        firmware.active, bank
+                    )));
+                }
+                Ok(())
+            }
+        },
+        Command::Backup { output, .. } => {
+            let config = action.backup()?;
+            let text = toml::to_string_pretty(&config).map_err(io::Error::other)?;
+            let output = if output.is_dir() {
+                output.join(format!(
+                    "{}.toml",
+                    format_mac(&switch.mac_address).replace(':', "-")
+                ))
+            } else {
+                output.clone()
+            };
+            fs::write(&output, text)?;
+            writeln!(
+                out,
+                "Saved configuration of {} to {}",
+                switch,
+                output.display()
+            )
+        }
+        Command::Restore { input, force, .. } => {
+            let config: SwitchConfig = toml::from_str(&fs::read_to_string(input)?)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            if !force {
+                check_config_identity(&config, switch)?;
+            }
+            let written = action.restore(&login_tlv, &config)?;
+            writeln!(out, "Restored {} settings to {}", written, switch)
+        }
+        Command::Reboot { .. } => {
+            let traffic = action.get_traffic_total().ok();
+            action.reboot(&login_tlv)?;
+            writeln!(out, "Rebooting {}", switch)?;
+            wait_for_switch(client, &action, switch, traffic, out)
+        }
+        Command::FactoryReset { yes, .. } => {
+            if !yes {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Factory reset erases all configuration on {}, pass --yes to confirm",
+                        switch
+                    ),
+                ));
+            }
+            action.factory_reset(&login_tlv)?;
+            writeln!(out, "Factory reset sent to {}", switch)
+        }
+        Command::Password => {
+            action.set_password(&login_tlv, new_password()?)?;
+            writeln!(out, "Changed the password of {}", switch)
+        }
+        Command::LoopDetection {
+            action: loop_detection,
+        } => match loop_detection {
+            LoopDetectionAction::On => action.set_loop_detection(&login_tlv, true),
+            LoopDetectionAction::Off => action.set_loop_detection(&login_tlv, false),
+            LoopDetectionAction::Status { .. } => {
+                let enabled = action.get_loop_detection()?;
+                writeln!(out, "{}", switch)?;
+                writeln!(out, "Loop detection: {}", on_off(enabled))
+            }
+        },
+        Command::Probe {
+            action:
+                ProbeAction::Scan {
+                    range,
+                    batch,
+                    output,
+                    ..
+                },
+        } => {
+            let report = probe::scan(client, switch, range.clone(), *batch)?;
+            let text = toml::to_string_pretty(&report).map_err(io::Error::other)?;
+            let output = if output.is_dir() {
+                output.join(format!(
+                    "{}.toml",
+                    format_mac(&switch.mac_address).replace(':', "-")
+                ))
+            } else {
+                output.clone()
+            };
+            fs::write(&output, text)?;
+            writeln!(out, "{}", switch)?;
+            probe::print_report(out, &report)?;
+            writeln!(out, "Saved to {}", output.display())
+        }
+        Command::Plan { .. } | Command::Apply { .. } => unreachable!("handled as fleet commands"),
+        Command::Decode { .. }
+        | Command::Probe {
+            action: ProbeAction::Diff { .. },
+        } => unreachable!("handled before binding"),
+    }
+}
+
+//Runs the command on every discovered switch passing the filters, and ends with a summary
+fn run_fleet(
+    client: &Arc<BlockingClient>,
+    filters: &[SwitchFilter],
+    concurrency: usize,
+    timeout: Duration,
+    command: Command,
+) -> Result<(), io::Error> {
+    match &command {
+        Command::Info
+        | Command::Mirror { .. }
+        | Command::Igmp { .. }
+        | Command::Ports
+        | Command::Port { .. }
+        | Command::CableTest { .. }
+        | Command::Password
+        | Command::LoopDetection { .. }
+        | Command::Firmware {
+            action: None | Some(FirmwareAction::Show),
+        } => {}
+        Command::Backup {
+            switch: None,
+            output,
+        } if output.is_dir() => {}
+        Command::Backup { switch: None, .. } => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Backing up several switches needs an existing directory as output",
+            ))
+        }
+        Command::Probe {
+            action:
+                ProbeAction::Scan {
+                    switch: None,
+                    output,
+                    ..
+                },
+        } if output.is_dir() => {}
+        Command::Probe {
+            action: ProbeAction::Scan { switch: None, .. },
+        } => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Probing several switches needs an existing directory as output",
+            ))
+        }
+        Command::Plan { .. } | Command::Apply { .. } => return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Plan and apply work on the switches listed in the file, leave out --all and --filter",
+        )),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "This command can only run on a single switch",
+            ))
+        }
+    }
+    //Checked once up front instead of failing on every switch
+    password()?;
+    if matches!(command, Command::Password) {
+        new_password()?;
+    }
+
+    let switches: Vec<Switch> = discover_switches(client)?
+        .into_iter()
+        .filter(|switch| filters.iter().all(|filter| filter.matches(switch)))
+        .collect();
+    if switches.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "No discovered switch matches",
+        ));
+    }
+
+    let total = switches.len();
+    let worker_client = client.clone();
+    let outcomes = fleet::run(
+        switches,
+        concurrency,
+        timeout,
+        move |switch| {
+            let mut out = Vec::new();
+            run_on_switch(&worker_client, switch, &command, &mut out).map(|_| out)
+        },
+        |outcome| match &outcome.result {
+            Ok(out) if out.is_empty() => println!("ok      {}", outcome.switch),
+            Ok(out) => print!("{}", String::from_utf8_lossy(out)),
+            Err(err) => println!("error   {} ({})", outcome.switch, err),
+        },
+    );
+
+    let failed: Vec<&fleet::Outcome> = outcomes
+        .iter()
+        .filter(|outcome| outcome.result.is_err())
+        .collect();
+    println!();
+    println!(
+        "Summary: {} succeeded, {} failed",
+        total - failed.len(),
+        failed.len()
+    );
+    for outcome in &failed {
+        if let Err(err) = &outcome.result {
+            println!(
+                "  {} after {:.1}s: {}",
+                outcome.switch,
+                outcome.elapsed.as_secs_f32(),
+                err
+            );
+        }
+    }
+
+    if !failed.is_empty() {
+        return Err(io::Error::other(format!(
+            "{} of {} switches failed",
+            failed.len(),
+            total
+        )));
+    }
+    Ok(())
+}
+
+//Prints every NSDP packet in the capture, times are relative to the first one
+fn decode_capture(path: &Path) -> Result<(), io::Error> {
+    let datagrams = pcap::read_udp(&fs::read(path)?)?;
+    let nsdp_ports = [
+        PortPair::V1.host,
+        PortPair::V1.switch,
+        PortPair::V2.host,
+        PortPair::V2.switch,
+    ];
+    let packets: Vec<&pcap::UdpDatagram> = datagrams
+        .iter()
+        .filter(|datagram| {
+            nsdp_ports.contains(&datagram.src.port()) || nsdp_ports.contains(&datagram.dst.port())
+        })
+        .collect();
+
+    let start = packets
+        .first()
+        .map(|packet| packet.time)
+        .unwrap_or_default();
+    let mut out = io::stdout().lock();
+    for (index, packet) in packets.iter().enumerate() {
+        writeln!(
+            out,
+            "#{} +{:.6}s {} -> {}",
+            index + 1,
+            packet.time.saturating_sub(start).as_secs_f64(),
+            packet.src,
+            packet.dst
+        )?;
+        decode::describe_packet(&mut out, &packet.payload)?;
+    }
+    writeln!(out)?;
+    writeln!(
+        out,
+        "{} NSDP packets in {} UDP datagrams",
+        packets.len(),
+        datagrams.len()
+    )
+}
+
+//Exits with an error when the probes differ, like plan does
+fn diff_probes(files: &[PathBuf], all: bool) -> Result<(), io::Error> {
+    let reports = files
+        .iter()
+        .map(|file| {
+            toml::from_str(&fs::read_to_string(file)?).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", file.display(), err),
+                )
+            })
+        })
+        .collect::<Result<Vec<probe::ProbeReport>, io::Error>>()?;
+    let differences = probe::diff(&mut io::stdout().lock(), &reports, all)?;
+    if differences > 0 {
+        return Err(io::Error::other(format!("{} TLV IDs differ", differences)));
+    }
+    Ok(())
+}
+
+fn decode_hex(hex: Option<&str>) -> Result<(), io::Error> {
+    let hex = match hex {
+        Some(hex) => hex.to_string(),
+        None => io::read_to_string(io::stdin())?,
+    };
+    let datagram = from_hex(&hex.replace(':', ""))
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    decode::describe_datagram(&mut io::stdout().lock(), &datagram)
+}
+
+fn print_ports(out: &mut dyn Write, ports: &[PortInfo]) -> Result<(), io::Error> {
+    writeln!(
+        out,
+        "{:<6}{:<14}{:<8}{:<14}",
+        "Port", "Link", "Speed", "Flow control"
+    )?;
+    for info in ports {
+        let speed = info
+            .speed
+            .map_or(String::from("-"), |speed| speed.to_string());
+        writeln!(
+            out,
+            "{:<6}{:<14}{:<8}{:<14}",
+            info.port,
+            info.link.to_string(),
+            speed,
+            info.flow_control.map_or("-", on_off)
+        )?;
+    }
+    Ok(())
+}
+
+//Compares every switch in the file with its live configuration, and when applying writes the differences
+fn desired_state(client: &BlockingClient, file: &Path, apply: bool) -> Result<(), io::Error> {
+    let desired: DesiredState = toml::from_str(&fs::read_to_string(file)?)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    if desired.version > CONFIG_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Desired state version {} is not supported", desired.version),
+        ));
+    }
+
+    let login_tlv = TypeLengthValue::from((Cmd::CMD_Password, password()?));
+    let discovered = discover_switches(client)?;
+    let mut drifted = 0;
+    let mut failed = 0;
 
-    let socket = UdpSocket::bind("0.0.0.0:63321").unwrap();
+    for (alias, target) in &desired.switches {
+        let selector = target.mac.as_deref().unwrap_or(alias);
+        let switch = match discovered
+            .iter()
+            .find(|switch| switch_matches(switch, selector))
+        {
+            Some(switch) => switch,
+            None => {
+                failed += 1;
+                println!(
+                    "! {}: no switch matching '{}' was discovered",
+                    alias, selector
+                );
+                continue;
+            }
+        };
 
-    socket
-        .set_broadcast(true)
-        .expect("set_broadcast call failed");
-    let read_timeout_ms = 500;
-    socket
-        .set_read_timeout(Some(Duration::from_millis(read_timeout_ms)))
-        .unwrap();
+        let action = actions::ActionRunner::new(client, switch);
+        let result = if apply {
+            action.apply(&login_tlv, &target.config)
+        } else {
+            action.plan(&target.config)
+        };
 
-    while user_input_loop(&socket) {}
+        match result {
+            Ok(changes) if changes.is_empty() => println!("  {}: in sync", alias),
+            Ok(changes) => {
+                drifted += 1;
+                let verb = if apply { "changed" } else { "differs" };
+                println!("  {}: {} ({})", alias, verb, switch);
+                for change in changes {
+                    println!("    {}", change);
+                }
+            }
+            Err(err) => {
+                failed += 1;
+                println!("! {}: {}", alias, err);
+            }
+        }
+    }
 
-    lifetime_test();
+    if failed > 0 {
+        return Err(io::Error::other(format!(
+            "{} of {} switches failed",
+            failed,
+            desired.switches.len()
+        )));
+    }
+    if drifted > 0 && !apply {
+        return Err(io::Error::other(format!(
+            "{} of {} switches differ from {}",
+            drifted,
+            desired.switches.len(),
+            file.display()
+        )));
+    }
+    Ok(())
 }
 
-fn user_input_loop(socket: &UdpSocket) -> bool {
-    let mut switches: Vec<Switch> = Vec::new();
+fn loop_detection_report(client: &BlockingClient) -> Result<(), io::Error> {
+    let switches = discover_switches(client)?;
+    if switches.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "No switches found to check",
+        ));
+    }
+    let mut disabled = 0;
+    let mut failed = 0;
+
+    for switch in &switches {
+        match actions::ActionRunner::new(client, switch).get_loop_detection() {
+            Ok(true) => println!("on      {}", switch),
+            Ok(false) => {
+                disabled += 1;
+                println!("OFF     {}", switch);
+            }
+            Err(err) => {
+                failed += 1;
+                println!("error   {} ({})", switch, err);
+            }
+        }
+    }
+
+    //A switch that could not be checked may just as well have loop detection off
+    if disabled + failed > 0 {
+        return Err(io::Error::other(format!(
+            "{} of {} switches have loop detection disabled, {} could not be checked",
+            disabled,
+            switches.len(),
+            failed
+        )));
+    }
+    Ok(())
+}
 
+fn user_input_loop(client: &BlockingClient) -> bool {
     println!("Please choose from the following options:");
     println!("0: Exit");
     println!("1: Discover switches");
@@ -64,67 +1015,28 @@ fn user_input_loop(socket: &UdpSocket) -> bool {
 
     match option {
         0 => return false,
-        1 => {
-            let request = request::Request::builder()
-                .ctype(cmds::ProtoConsts::QueryRequest.value().try_into().unwrap())
-                .session(Session::new_random_seq(
-                    cmds::ProtoConsts::MACMyPC.value().try_into().unwrap(),
-                    cmds::ProtoConsts::MACBroadcast.value().try_into().unwrap(),
-                ))
-                .add_cmd(TypeLengthValue::from(Cmd::CMD_Name))
-                .add_cmd(TypeLengthValue::from(Cmd::CMD_Model))
-                .add_cmd(TypeLengthValue::from(Cmd::CMD_Location))
-                .add_cmd(TypeLengthValue::from(Cmd::CMD_IPv4))
-                .build();
-
-            let buf = request.format();
-
-            socket
-                .send_to(buf.as_slice(), "255.255.255.255:63322")
-                .unwrap();
-
-            let discover_count = 6;
-
-            for _ in 0..discover_count {
-                let mut buf = [0; 128];
-
-                if let Ok((number_of_bytes, src_addr)) = socket.recv_from(&mut buf) {
-                    if let Ok(response) = Response::build(&mut buf[..number_of_bytes]) {
-                        switches.push(Switch {
-                            name: response.get_cmd(&Cmd::CMD_Name).unwrap().try_into().unwrap(),
-                            model: response
-                                .get_cmd(&Cmd::CMD_Model)
-                                .unwrap()
-                                .try_into()
-                                .unwrap(),
-                            location: response
-                                .get_cmd(&Cmd::CMD_Location)
-                                .unwrap()
-                                .try_into()
-                                .unwrap(),
-                            ipv4_address_reported: response
-                                .get_cmd(&Cmd::CMD_IPv4)
-                                .unwrap()
-                                .try_into()
-                                .unwrap(),
-
-                            ipv4_address: src_addr.to_string(),
-                            mac_address: response.get_session().get_switch_mac(),
-                        });
-                    }
+        1 => match discover_switches(client) {
+            Ok(switches) => {
+                println!("Discovered {} switches:", switches.len());
+                for switch in &switches {
+                    println!("{}", switch);
                 }
             }
-
-            println!("Final switches array: {:?}", switches);
-        }
+            Err(err) => println!("Discovery failed: {}", err),
+        },
         2 => {
             println!("Using switch loaded in ENV file!");
 
             let switch = load_switch_from_dotenv();
 
-            let login_tlv = TypeLengthValue::from((Cmd::CMD_Password, password()));
-
-            perform_action(&socket, switch, login_tlv);
+            match password() {
+                Ok(password) => perform_action(
+                    client,
+                    switch,
+                    TypeLengthValue::from((Cmd::CMD_Password, password)),
+                ),
+                Err(err) => eprintln!("Error: {}", err),
+            }
         }
         3 => {
             println!("Debug test message");
@@ -137,33 +1049,255 @@ fn user_input_loop(socket: &UdpSocket) -> bool {
     true
 }
 
-fn perform_action(socket: &UdpSocket, switch: Switch, login_tlv: TypeLengthValue) {
-    let action = actions::ActionRunner::new(socket, &switch);
+fn discover_switches(client: &BlockingClient) -> Result<Vec<Switch>, io::Error> {
+    client.discover(DISCOVERY_TIMEOUT)
+}
+
+//Picks a discovered switch by name, MAC or IP, without a selector the switch from the ENV file is used
+fn resolve_switch(client: &BlockingClient, selector: Option<&str>) -> Result<Switch, io::Error> {
+    let selector = match selector {
+        Some(selector) => selector,
+        None => return Ok(load_switch_from_dotenv()),
+    };
+
+    let mut matches: Vec<Switch> = discover_switches(client)?
+        .into_iter()
+        .filter(|switch| switch_matches(switch, selector))
+        .collect();
+
+    match matches.len() {
+        0 => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No switch matching '{}' was discovered", selector),
+        )),
+        1 => Ok(matches.remove(0)),
+        count => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "'{}' matches {} switches, use the MAC instead",
+                selector, count
+            ),
+        )),
+    }
+}
+
+fn switch_matches(switch: &Switch, selector: &str) -> bool {
+    if let Some(mac) = parse_mac(selector) {
+        return switch.mac_address == mac;
+    }
+    if let Ok(ip) = selector.parse::<Ipv4Addr>() {
+        return switch.ipv4_address_reported == ip;
+    }
+    switch.name.eq_ignore_ascii_case(selector)
+}
+
+//Refuses configuration taken from another switch or model unless forced
+fn check_config_identity(config: &SwitchConfig, switch: &Switch) -> Result<(), io::Error> {
+    let identity = match &config.switch {
+        Some(identity) => identity,
+        None => return Ok(()),
+    };
+    if let Some(mac) = identity.mac.as_deref().and_then(parse_mac) {
+        if mac != switch.mac_address {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Configuration was taken from {}, not {}, pass --force to restore anyway",
+                    format_mac(&mac),
+                    format_mac(&switch.mac_address)
+                ),
+            ));
+        }
+    }
+    if let Some(model) = &identity.model {
+        if *model != switch.model {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Configuration is for a {}, not a {}, pass --force to restore anyway",
+                    model, switch.model
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn upgrade_firmware(
+    client: &BlockingClient,
+    action: &actions::ActionRunner,
+    switch: &Switch,
+    login_tlv: &TypeLengthValue,
+    image: &Path,
+    tftp_listen: SocketAddr,
+    out: &mut dyn Write,
+) -> Result<(), io::Error> {
+    let image = fs::read(image)?;
+    let before = action.get_firmware()?;
+    let before_version = before.active_version().cloned().unwrap_or_default();
+
+    //Listen before the switch goes into upgrade mode so its first request isn't missed
+    let server = tftp::TftpServer::bind(tftp_listen)?;
+    let traffic = action.get_traffic_total().ok();
+    action.enter_upgrade_mode(login_tlv)?;
+    writeln!(
+        out,
+        "{} is in upgrade mode, serving {} bytes on {}",
+        switch,
+        image.len(),
+        server.local_addr()?
+    )?;
+
+    let (tftp_client, file) = server.serve_once(&image, FIRMWARE_TRANSFER_TIMEOUT)?;
+    writeln!(
+        out,
+        "Image sent to {}, which asked for '{}', waiting for the switch to restart",
+        tftp_client, file
+    )?;
+    wait_for_switch(client, action, switch, traffic, out)?;
+
+    let after = action.get_firmware()?;
+    writeln!(out, "{}", after)?;
+    match after.active_version() {
+        Some(version) if *version != before_version => {
+            writeln!(out, "Upgraded from {} to {}", before_version, version)?;
+            Ok(())
+        }
+        _ => Err(io::Error::other(format!(
+            "Switch is still running {} after the upgrade",
+            before_version
+        ))),
+    }
+}
+
+//Waits for the switch to go away and answer discovery again. Some switches take a while to start rebooting,
+//answering until then must not count as being back. A quick restart can fall between two polls, so a switch
+//that keeps answering counts as restarted once its traffic counters drop below the ones read before
+fn wait_for_switch(
+    client: &BlockingClient,
+    action: &actions::ActionRunner,
+    switch: &Switch,
+    traffic_before: Option<u64>,
+    out: &mut dyn Write,
+) -> Result<(), io::Error> {
+    let started = Instant::now();
+    let discovered = || -> Result<bool, io::Error> {
+        Ok(discover_switches(client)?
+            .iter()
+            .any(|found| found.mac_address == switch.mac_address))
+    };
+    let counters_reset = || match (traffic_before, action.get_traffic_total()) {
+        (Some(before), Ok(now)) => now < before,
+        _ => false,
+    };
+
+    let mut went_away = false;
+    while started.elapsed() < REBOOT_GRACE {
+        if !discovered()? {
+            went_away = true;
+            break;
+        }
+        if counters_reset() {
+            writeln!(
+                out,
+                "{} restarted between polls, back after {}s",
+                switch.name,
+                started.elapsed().as_secs()
+            )?;
+            return Ok(());
+        }
+        thread::sleep(REBOOT_POLL_INTERVAL);
+    }
+
+    while started.elapsed() < REBOOT_TIMEOUT {
+        if discovered()? {
+            break;
+        }
+        thread::sleep(REBOOT_POLL_INTERVAL);
+    }
+    if started.elapsed() >= REBOOT_TIMEOUT {
+        return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!(
+                "{} did not reappear within {}s",
+                switch.name,
+                REBOOT_TIMEOUT.as_secs()
+            ),
+        ));
+    }
+
+    //Answering all along only counts as a restart when the counters show one, idle counters can't tell
+    if !went_away && !counters_reset() {
+        let evidence = if traffic_before.unwrap_or(0) > 0 {
+            "its traffic counters did not reset"
+        } else {
+            "it has no traffic counters to check"
+        };
+        return Err(io::Error::other(format!(
+            "{} kept answering for {}s and {}, could not verify that it restarted",
+            switch.name,
+            REBOOT_GRACE.as_secs(),
+            evidence
+        )));
+    }
+    writeln!(
+        out,
+        "{} is back after {}s",
+        switch.name,
+        started.elapsed().as_secs()
+    )?;
+    Ok(())
+}
+
+fn perform_action(client: &BlockingClient, switch: Switch, login_tlv: TypeLengthValue) {
+    let action = actions::ActionRunner::new(client, &switch);
 
     action.get_all_info(&login_tlv);
 }
 
-fn password() -> Vec<u8> {
-    let plainpass = "password".as_bytes();
-    let hashkey = "NtgrSmartSwitchRock".as_bytes();
+//The password comes from PPUTL_PASSWORD, switches ship with "password"
+fn password() -> Result<Vec<u8>, io::Error> {
+    let plain = std::env::var("PPUTL_PASSWORD").unwrap_or_else(|_| String::from("password"));
+    checked_password("PPUTL_PASSWORD", &plain)
+}
 
-    let mut password: Vec<u8> = Vec::<u8>::new();
+fn new_password() -> Result<Vec<u8>, io::Error> {
+    match std::env::var("PPUTL_NEW_PASSWORD") {
+        Ok(plain) if !plain.is_empty() => checked_password("PPUTL_NEW_PASSWORD", &plain),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Set PPUTL_NEW_PASSWORD to the new password",
+        )),
+    }
+}
 
-    for (plain_char, hash_char) in plainpass.iter().zip(hashkey) {
-        let xor = plain_char ^ hash_char;
-        password.push(xor);
+//Encoding drops what is past the end of the key, so a longer password would silently become another one
+fn checked_password(var: &str, plain: &str) -> Result<Vec<u8>, io::Error> {
+    if plain.len() > PASSWORD_KEY.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} is longer than the {} characters a switch password can have",
+                var,
+                PASSWORD_KEY.len()
+            ),
+        ));
     }
-    password
+    Ok(encode_password(plain))
 }
 
 fn password_test() {
-    let password: Vec<u8> = password();
-    print!("Password xor: ");
-    print_hex(&password);
-    println!();
+    match password() {
+        Ok(password) => {
+            print!("Password xor: ");
+            print_hex(&password);
+            println!();
+        }
+        Err(err) => eprintln!("Error: {}", err),
+    }
 }
 
-fn print_hex(value: &Vec<u8>) {
+fn print_hex(value: &[u8]) {
     for byte in value {
         print!("{:02x} ", byte);
     }
@@ -182,6 +1316,11 @@ fn load_switch_from_dotenv() -> Switch {
         .unwrap(),
     );
     let ipv4_address = std::env::var("TESTSWITCH_IPV4").expect("TESTSWITCH_IPV4 not set");
+    //Replies come from the switch port, so it tells which pair the switch speaks
+    let port_pair = match ipv4_address.parse::<SocketAddr>() {
+        Ok(addr) if addr.port() == PortPair::V2.switch => PortPair::V2,
+        _ => PortPair::V1,
+    };
     let mac_address: [u8; 6] = csv_to_byte_array(
         std::env::var("TESTSWITCH_MAC")
             .expect("TESTSWITCH_MAC not set")
@@ -197,11 +1336,12 @@ fn load_switch_from_dotenv() -> Switch {
         ipv4_address_reported,
         ipv4_address,
         mac_address,
+        port_pair,
     }
 }
 
 fn csv_to_byte_array(str: &str) -> Vec<u8> {
-    let comma_ascii: char = char::from_u32(",".as_bytes()[0] as u32).unwrap();
+    let comma_ascii: char = char::from(",".as_bytes()[0]);
     let mut bytes: Vec<u8> = Vec::new();
     let mut start = 0;
     let mut end = 0;
@@ -215,24 +1355,131 @@ fn csv_to_byte_array(str: &str) -> Vec<u8> {
     }
     let num: u8 = str[start..end].parse().unwrap();
     bytes.push(num);
-    bytes.try_into().unwrap()
+    bytes
 }
 
-fn lifetime_test() {
-    let str1 = String::from("Longer string");
-    let str2 = String::from("Short");
-    let longer;
-    {
-        longer = test_2(&str1, &str2);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn cli_definition_is_consistent() {
+        Cli::command().debug_assert();
     }
 
-    println!("Invalid! {}", longer);
-}
+    #[test]
+    fn loop_detection_takes_on_off_or_status() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(["pputl", "loop-detection"].iter().chain(args))
+                .map(|cli| cli.command)
+        };
+        assert!(matches!(
+            parse(&["on"]),
+            Ok(Some(Command::LoopDetection {
+                action: LoopDetectionAction::On
+            }))
+        ));
+        assert!(matches!(
+            parse(&["status", "--all"]),
+            Ok(Some(Command::LoopDetection {
+                action: LoopDetectionAction::Status { all: true }
+            }))
+        ));
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["maybe"]).is_err());
+    }
 
-fn test_2<'a>(st: &'a str, sl: &'a str) -> &'a str {
-    if st.len() > sl.len() {
-        st
-    } else {
-        sl
+    #[test]
+    fn port_set_takes_any_of_its_settings() {
+        let cli =
+            Cli::try_parse_from(["pputl", "port", "set", "2", "--flow-control", "off"]).unwrap();
+        match cli.command {
+            Some(Command::Port {
+                action:
+                    PortAction::Set {
+                        port,
+                        speed,
+                        flow_control,
+                    },
+            }) => {
+                assert_eq!(port.number(), 2);
+                assert_eq!(speed, None);
+                assert_eq!(flow_control, Some(false));
+            }
+            _ => panic!("Expected port set"),
+        }
+        assert!(
+            Cli::try_parse_from(["pputl", "port", "set", "2", "--flow-control", "maybe"]).is_err()
+        );
+    }
+
+    fn switch() -> Switch {
+        Switch {
+            name: String::from("Desk"),
+            model: String::from("GS105E"),
+            location: String::new(),
+            ipv4_address_reported: Ipv4Addr::new(192, 168, 0, 239),
+            ipv4_address: String::from("192.168.0.239:63322"),
+            mac_address: [0x02, 0x00, 0x00, 0x00, 0x01, 0x01],
+            port_pair: PortPair::V1,
+        }
+    }
+
+    #[test]
+    fn switches_are_picked_by_mac_ip_or_name() {
+        let switch = switch();
+        assert!(switch_matches(&switch, "02:00:00:00:01:01"));
+        assert!(switch_matches(&switch, "02-00-00-00-01-01"));
+        assert!(switch_matches(&switch, "192.168.0.239"));
+        assert!(switch_matches(&switch, "desk"));
+        assert!(!switch_matches(&switch, "02:00:00:00:01:02"));
+        assert!(!switch_matches(&switch, "rack"));
+    }
+
+    #[test]
+    fn macs_need_six_hex_bytes() {
+        assert_eq!(
+            parse_mac("aa:BB:cc:dd:ee:ff"),
+            Some([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff])
+        );
+        assert_eq!(parse_mac("aa:bb:cc:dd:ee"), None);
+        assert_eq!(parse_mac("aa:bb:cc:dd:ee:gg"), None);
+    }
+
+    #[test]
+    fn factory_reset_is_only_confirmed_by_yes() {
+        let cli = Cli::try_parse_from(["pputl", "factory-reset", "desk"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::FactoryReset { yes: false, .. })
+        ));
+        let cli = Cli::try_parse_from(["pputl", "factory-reset", "--yes"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::FactoryReset {
+                switch: None,
+                yes: true
+            })
+        ));
+    }
+
+    #[test]
+    fn port_sharing_and_ephemeral_ports_exclude_each_other() {
+        assert!(Cli::try_parse_from(["pputl", "--share-port", "info"]).is_ok());
+        assert!(
+            Cli::try_parse_from(["pputl", "--share-port", "--ephemeral-port", "info"]).is_err()
+        );
+    }
+
+    #[test]
+    fn probes_take_an_address_or_a_range() {
+        assert_eq!(
+            parse_probe("10.1.2.3").unwrap().hosts().collect::<Vec<_>>(),
+            [Ipv4Addr::new(10, 1, 2, 3)]
+        );
+        assert_eq!(parse_probe("10.1.2.0/24").unwrap().hosts().count(), 254);
+        assert!(parse_probe("10.1.2.0/33").is_err());
+        assert!(parse_probe("switch.local").is_err());
     }
 }
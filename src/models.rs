@@ -0,0 +1,172 @@
+use strum::IntoEnumIterator;
+
+use crate::cmds::Cmd;
+
+//
+//What each switch model understands, keyed by the model string it reports in CMD_Model. Some switches reject
+//a query holding a TLV they don't know or cut the reply short, so queries only ask for what the model supports.
+//Models missing from the table are asked for everything
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthScheme {
+    //Password XORed with a fixed key in front of every transmit request
+    XorPassword,
+    //Password hashed together with a nonce the switch hands out, pputl can only read these switches
+    HashedPassword,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ModelCapabilities {
+    pub model: &'static str,
+    pub ports: u8,
+    //802.1Q VLANs the switch holds at once, IDs go up to MAX_VLAN_ID
+    pub max_vlans: u16,
+    pub auth: AuthScheme,
+    tlvs: &'static [&'static [Cmd]],
+}
+
+pub const MAX_VLAN_ID: u16 = 4093;
+
+//Identity, network settings, port status and statistics, VLANs, QoS, mirroring, IGMP and loop detection
+const COMMON: &[Cmd] = &[
+    Cmd::CMD_Model,
+    Cmd::CMD_Name,
+    Cmd::CMD_Switch_MAC,
+    Cmd::CMD_Location,
+    Cmd::CMD_IPv4,
+    Cmd::CMD_Switch_Netmask,
+    Cmd::CMD_Switch_Gateway,
+    Cmd::CMD_New_Password,
+    Cmd::CMD_Password,
+    Cmd::CMD_Switch_DHCP,
+    Cmd::CMD_FW_Version,
+    Cmd::CMD_FW_Upgrade,
+    Cmd::CMD_Reboot,
+    Cmd::CMD_Factory_Reset,
+    Cmd::CMD_Port_Status,
+    Cmd::CMD_Port_Statistics,
+    Cmd::CMD_Cable_Test_Request,
+    Cmd::CMD_Cable_Test_Result,
+    Cmd::CMD_VLAN_Mode,
+    Cmd::CMD_VLAN_Port_Members,
+    Cmd::CMD_VLAN_8021Q_Members,
+    Cmd::CMD_VLAN_Delete,
+    Cmd::CMD_VLAN_PVID,
+    Cmd::CMD_QoS_Mode,
+    Cmd::CMD_QoS_Port_Priority,
+    Cmd::CMD_Ingress_Rate_Limit,
+    Cmd::CMD_Egress_Rate_Limit,
+    Cmd::CMD_Storm_Control,
+    Cmd::CMD_Storm_Control_Rate,
+    Cmd::CMD_Port_Mirroring,
+    Cmd::CMD_Port_Count,
+    Cmd::CMD_IGMP_Snooping,
+    Cmd::CMD_IGMP_Block_Unknown_Multicast,
+    Cmd::CMD_IGMP_Validate_IP_Header,
+    Cmd::CMD_IGMP_Router_Ports,
+    Cmd::CMD_Loop_Detection,
+    Cmd::CMD_Port_Speed_Config,
+    Cmd::CMD_Port_Flow_Control,
+];
+
+//Two firmware images, one of them picked for the next boot
+const DUAL_IMAGE: &[Cmd] = &[Cmd::CMD_FW_Version_2, Cmd::CMD_FW_Active];
+
+//Seen on the later hardware revisions, meaning still unknown
+const UNKNOWN: &[Cmd] = &[
+    Cmd::CMD_0014,
+    Cmd::CMD_6400,
+    Cmd::CMD_7400,
+    Cmd::CMD_7800,
+    Cmd::CMD_7C00,
+];
+
+pub const MODELS: &[ModelCapabilities] = &[
+    ModelCapabilities {
+        model: "GS105E",
+        ports: 5,
+        max_vlans: 32,
+        auth: AuthScheme::XorPassword,
+        tlvs: &[COMMON],
+    },
+    ModelCapabilities {
+        model: "GS105Ev2",
+        ports: 5,
+        max_vlans: 32,
+        auth: AuthScheme::XorPassword,
+        tlvs: &[COMMON, DUAL_IMAGE, UNKNOWN],
+    },
+    ModelCapabilities {
+        model: "GS108E",
+        ports: 8,
+        max_vlans: 32,
+        auth: AuthScheme::XorPassword,
+        tlvs: &[COMMON],
+    },
+    ModelCapabilities {
+        model: "GS108Ev3",
+        ports: 8,
+        max_vlans: 32,
+        auth: AuthScheme::XorPassword,
+        tlvs: &[COMMON, DUAL_IMAGE, UNKNOWN],
+    },
+    ModelCapabilities {
+        model: "GS116Ev2",
+        ports: 16,
+        max_vlans: 64,
+        auth: AuthScheme::XorPassword,
+        tlvs: &[COMMON, DUAL_IMAGE, UNKNOWN],
+    },
+    ModelCapabilities {
+        model: "GS308E",
+        ports: 8,
+        max_vlans: 32,
+        auth: AuthScheme::HashedPassword,
+        tlvs: &[COMMON, DUAL_IMAGE],
+    },
+    ModelCapabilities {
+        model: "JGS516PE",
+        ports: 16,
+        max_vlans: 64,
+        auth: AuthScheme::XorPassword,
+        tlvs: &[COMMON, DUAL_IMAGE],
+    },
+    ModelCapabilities {
+        model: "JGS524PE",
+        ports: 24,
+        max_vlans: 64,
+        auth: AuthScheme::XorPassword,
+        tlvs: &[COMMON, DUAL_IMAGE],
+    },
+];
+
+impl ModelCapabilities {
+    //Models are matched exactly, a later hardware revision may support a different set
+    pub fn lookup(model: &str) -> Option<&'static ModelCapabilities> {
+        let model = model.trim_end_matches('\0').trim();
+        MODELS
+            .iter()
+            .find(|capabilities| capabilities.model.eq_ignore_ascii_case(model))
+    }
+
+    pub fn supports(&self, cmd: &Cmd) -> bool {
+        self.tlvs.iter().any(|group| group.contains(cmd))
+    }
+
+    pub fn supports_id(&self, id: [u8; 2]) -> bool {
+        self.tlvs
+            .iter()
+            .flat_map(|group| group.iter())
+            .any(|cmd| <[u8; 2]>::from(cmd) == id)
+    }
+}
+
+//Everything worth reading from a switch of the model, every readable TLV for unknown models
+pub fn readable_cmds(model: &str) -> Vec<Cmd> {
+    let capabilities = ModelCapabilities::lookup(model);
+    Cmd::iter()
+        .filter(Cmd::is_readable)
+        .filter(|cmd| capabilities.is_none_or(|capabilities| capabilities.supports(cmd)))
+        .collect()
+}
@@ -0,0 +1,197 @@
+use std::fmt;
+
+use crate::cmds::{ProtoConsts, TLVReadingError, TypeLengthValue};
+use crate::request::Session;
+use crate::values::to_hex;
+
+//
+//Any NSDP datagram, sent by the host or by a switch. Unlike Response::build the type and signature are checked,
+//so requests and replies can be told apart when both sides of a conversation are parsed
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketType {
+    QueryRequest,
+    QueryResponse,
+    TransmitRequest,
+    TransmitResponse,
+}
+
+impl PacketType {
+    pub fn is_request(&self) -> bool {
+        matches!(self, PacketType::QueryRequest | PacketType::TransmitRequest)
+    }
+
+    //The type a switch answers a request with, responses are their own answer
+    pub fn reply_type(&self) -> PacketType {
+        match self {
+            PacketType::QueryRequest => PacketType::QueryResponse,
+            PacketType::TransmitRequest => PacketType::TransmitResponse,
+            response => *response,
+        }
+    }
+
+    fn proto_const(&self) -> ProtoConsts {
+        match self {
+            PacketType::QueryRequest => ProtoConsts::QueryRequest,
+            PacketType::QueryResponse => ProtoConsts::QueryResponse,
+            PacketType::TransmitRequest => ProtoConsts::TransmitRequest,
+            PacketType::TransmitResponse => ProtoConsts::TransmitResponse,
+        }
+    }
+}
+
+impl fmt::Display for PacketType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PacketType::QueryRequest => "query request",
+            PacketType::QueryResponse => "query response",
+            PacketType::TransmitRequest => "transmit request",
+            PacketType::TransmitResponse => "transmit response",
+        };
+        f.write_str(name)
+    }
+}
+
+impl From<PacketType> for [u8; 2] {
+    fn from(packet_type: PacketType) -> Self {
+        packet_type.proto_const().value().try_into().unwrap()
+    }
+}
+
+impl TryFrom<[u8; 2]> for PacketType {
+    type Error = TLVReadingError;
+
+    fn try_from(ctype: [u8; 2]) -> Result<Self, Self::Error> {
+        [
+            PacketType::QueryRequest,
+            PacketType::QueryResponse,
+            PacketType::TransmitRequest,
+            PacketType::TransmitResponse,
+        ]
+        .into_iter()
+        .find(|packet_type| <[u8; 2]>::from(*packet_type) == ctype)
+        .ok_or(TLVReadingError::InvalidType(format!(
+            "Unknown packet type {}",
+            to_hex(&ctype)
+        )))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    packet_type: PacketType,
+    status: [u8; 2],
+    failed_cmd: [u8; 2], //TLV the switch rejected, follows the status
+    session: Session,
+    cmds: Vec<TypeLengthValue>,
+}
+
+impl Packet {
+    pub fn new(packet_type: PacketType, session: Session) -> Packet {
+        Packet {
+            packet_type,
+            status: [0x00, 0x00],
+            failed_cmd: [0x00, 0x00],
+            session,
+            cmds: Vec::new(),
+        }
+    }
+
+    pub fn with_status(mut self, status: [u8; 2], failed_cmd: [u8; 2]) -> Packet {
+        self.status = status;
+        self.failed_cmd = failed_cmd;
+        self
+    }
+
+    pub fn with_cmd(mut self, cmd: TypeLengthValue) -> Packet {
+        self.cmds.push(cmd);
+        self
+    }
+
+    pub fn parse(datagram: &[u8]) -> Result<Packet, TLVReadingError> {
+        if datagram.len() < 32 {
+            return Err(TLVReadingError::ArrTooShort(String::from(
+                "Raw array too short for header",
+            )));
+        }
+        let packet_type = PacketType::try_from(<[u8; 2]>::try_from(&datagram[0..2]).unwrap())?;
+        if &datagram[24..28] != ProtoConsts::NDSP.value() {
+            return Err(TLVReadingError::InvalidType(format!(
+                "Signature {} instead of NSDP",
+                to_hex(&datagram[24..28])
+            )));
+        }
+
+        Ok(Packet {
+            packet_type,
+            status: datagram[2..4].try_into().unwrap(),
+            failed_cmd: datagram[4..6].try_into().unwrap(),
+            session: Session::new(
+                datagram[8..14].try_into().unwrap(),
+                datagram[14..20].try_into().unwrap(),
+                datagram[22..24].try_into().unwrap(),
+            ),
+            cmds: read_tlvs(&datagram[32..])?,
+        })
+    }
+
+    pub fn format(&self) -> Vec<u8> {
+        let mut datagram = vec![0; 32];
+        datagram[0..2].copy_from_slice(&<[u8; 2]>::from(self.packet_type));
+        datagram[2..4].copy_from_slice(&self.status);
+        datagram[4..6].copy_from_slice(&self.failed_cmd);
+        datagram[8..14].copy_from_slice(&self.session.get_host_mac());
+        datagram[14..20].copy_from_slice(&self.session.get_switch_mac());
+        datagram[22..24].copy_from_slice(&self.session.get_seq());
+        datagram[24..28].copy_from_slice(ProtoConsts::NDSP.value());
+        for cmd in &self.cmds {
+            datagram.append(&mut cmd.to_raw());
+        }
+        datagram.extend_from_slice(ProtoConsts::EndOfMessage.value());
+        datagram
+    }
+
+    pub fn get_type(&self) -> PacketType {
+        self.packet_type
+    }
+
+    pub fn get_status(&self) -> [u8; 2] {
+        self.status
+    }
+
+    pub fn get_failed_cmd(&self) -> [u8; 2] {
+        self.failed_cmd
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status == [0x00, 0x00]
+    }
+
+    pub fn get_session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn get_cmds(&self) -> &[TypeLengthValue] {
+        &self.cmds
+    }
+
+    pub fn into_cmds(self) -> Vec<TypeLengthValue> {
+        self.cmds
+    }
+}
+
+//TLVs up to the end of message marker, which has to be there
+pub(crate) fn read_tlvs(mut raw: &[u8]) -> Result<Vec<TypeLengthValue>, TLVReadingError> {
+    let mut cmds = Vec::new();
+    loop {
+        match TypeLengthValue::from_raw(raw) {
+            Ok((tlv, len)) => {
+                cmds.push(tlv);
+                raw = &raw[len..];
+            }
+            Err(TLVReadingError::EndOfMessage) => return Ok(cmds),
+            Err(err) => return Err(err),
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use std::io::{Error, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+//
+//Minimal reader for pcap and pcapng captures, e.g. from tcpdump or Wireshark. Only IPv4 UDP datagrams are
+//picked out, over Ethernet, Linux cooked captures, BSD loopback or raw IP links
+//
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b23c4d;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_OPTION_TSRESOL: u16 = 9;
+
+const LINKTYPE_NULL: u16 = 0;
+const LINKTYPE_ETHERNET: u16 = 1;
+const LINKTYPE_RAW: u16 = 101;
+const LINKTYPE_LINUX_SLL: u16 = 113;
+const LINKTYPE_LINUX_SLL2: u16 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const IP_PROTOCOL_UDP: u8 = 17;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UdpDatagram {
+    //Since the Unix epoch, as stamped by the capturing machine
+    pub time: Duration,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub payload: Vec<u8>,
+}
+
+//A captured frame and the link layer it was captured on
+struct Frame<'a> {
+    time: Duration,
+    link_type: u16,
+    data: &'a [u8],
+}
+
+//Every UDP datagram in the capture, in capture order
+pub fn read_udp(capture: &[u8]) -> Result<Vec<UdpDatagram>, Error> {
+    let magic = capture
+        .get(0..4)
+        .ok_or(invalid("File too short for a capture"))?;
+    let frames = if magic == PCAPNG_SECTION_HEADER.to_le_bytes() {
+        pcapng_frames(capture)?
+    } else {
+        pcap_frames(capture)?
+    };
+    Ok(frames.iter().filter_map(udp_datagram).collect())
+}
+
+fn pcap_frames(capture: &[u8]) -> Result<Vec<Frame<'_>>, Error> {
+    let magic = u32::from_le_bytes(capture[0..4].try_into().unwrap());
+    let (little_endian, nanos) = match magic {
+        PCAP_MAGIC_MICROS => (true, false),
+        PCAP_MAGIC_NANOS => (true, true),
+        _ => match magic.swap_bytes() {
+            PCAP_MAGIC_MICROS => (false, false),
+            PCAP_MAGIC_NANOS => (false, true),
+            _ => return Err(invalid("Not a pcap or pcapng file")),
+        },
+    };
+    let header = capture
+        .get(0..24)
+        .ok_or(invalid("File too short for a pcap header"))?;
+    let read = Reader { little_endian };
+    let link_type = read.u32(header, 20) as u16;
+
+    let mut frames = Vec::new();
+    let mut offset = 24;
+    while offset + 16 <= capture.len() {
+        let seconds = read.u32(capture, offset);
+        let fraction = read.u32(capture, offset + 4);
+        let len = read.u32(capture, offset + 8) as usize;
+        let data = capture
+            .get(offset + 16..offset + 16 + len)
+            .ok_or(invalid("Capture ends in the middle of a packet"))?;
+        let fraction = if nanos {
+            Duration::from_nanos(fraction.into())
+        } else {
+            Duration::from_micros(fraction.into())
+        };
+        frames.push(Frame {
+            time: Duration::from_secs(seconds.into()) + fraction,
+            link_type,
+            data,
+        });
+        offset += 16 + len;
+    }
+    Ok(frames)
+}
+
+fn pcapng_frames(capture: &[u8]) -> Result<Vec<Frame<'_>>, Error> {
+    //Link type and timestamp units per second of every interface in the current section
+    let mut interfaces: Vec<(u16, u64)> = Vec::new();
+    let mut read = Reader {
+        little_endian: true,
+    };
+    let mut frames = Vec::new();
+    let mut offset = 0;
+
+    while offset + 12 <= capture.len() {
+        let block_type = read.u32(capture, offset);
+        if block_type == PCAPNG_SECTION_HEADER {
+            let order = capture
+                .get(offset + 8..offset + 12)
+                .ok_or(invalid("Section header too short"))?;
+            read.little_endian = match u32::from_le_bytes(order.try_into().unwrap()) {
+                PCAPNG_BYTE_ORDER_MAGIC => true,
+                magic if magic.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC => false,
+                _ => return Err(invalid("Unknown pcapng byte order")),
+            };
+            interfaces.clear();
+        }
+        let len = read.u32(capture, offset + 4) as usize;
+        if len < 12 || offset + len > capture.len() {
+            return Err(invalid("Capture ends in the middle of a block"));
+        }
+        let body = &capture[offset + 8..offset + len - 4];
+
+        match block_type {
+            PCAPNG_INTERFACE_DESCRIPTION if body.len() >= 8 => {
+                interfaces.push((read.u16(body, 0), interface_resolution(&read, &body[8..])));
+            }
+            PCAPNG_ENHANCED_PACKET if body.len() >= 20 => {
+                let (link_type, resolution) = *interfaces
+                    .get(read.u32(body, 0) as usize)
+                    .ok_or(invalid("Packet for an undescribed interface"))?;
+                let timestamp = (u64::from(read.u32(body, 4)) << 32) | u64::from(read.u32(body, 8));
+                let captured = read.u32(body, 12) as usize;
+                frames.push(Frame {
+                    time: Duration::from_secs(timestamp / resolution)
+                        + Duration::from_nanos(
+                            ((timestamp % resolution) as u128 * 1_000_000_000 / resolution as u128)
+                                as u64,
+                        ),
+                    link_type,
+                    data: body
+                        .get(20..20 + captured)
+                        .ok_or(invalid("Packet longer than its block"))?,
+                });
+            }
+            //Simple packets carry no timestamp and belong to the first interface
+            PCAPNG_SIMPLE_PACKET if body.len() >= 4 => {
+                let (link_type, _) = *interfaces
+                    .first()
+                    .ok_or(invalid("Packet for an undescribed interface"))?;
+                let len = (read.u32(body, 0) as usize).min(body.len() - 4);
+                frames.push(Frame {
+                    time: Duration::ZERO,
+                    link_type,
+                    data: &body[4..4 + len],
+                });
+            }
+            _ => {}
+        }
+        offset += len;
+    }
+    Ok(frames)
+}
+
+//Units per second from the if_tsresol option, microseconds when it is missing
+fn interface_resolution(read: &Reader, mut options: &[u8]) -> u64 {
+    while options.len() >= 4 {
+        let code = read.u16(options, 0);
+        let len = read.u16(options, 2) as usize;
+        if code == 0 {
+            break;
+        }
+        if code == PCAPNG_OPTION_TSRESOL && len >= 1 && options.len() > 4 {
+            let resolution = options[4];
+            let exponent = u32::from(resolution & 0x7f);
+            //The top bit picks a power of two instead of ten
+            let base: u64 = if resolution & 0x80 != 0 { 2 } else { 10 };
+            return base.checked_pow(exponent).unwrap_or(1_000_000);
+        }
+        options = options.get(4 + len.div_ceil(4) * 4..).unwrap_or_default();
+    }
+    1_000_000
+}
+
+fn udp_datagram(frame: &Frame) -> Option<UdpDatagram> {
+    let data = frame.data;
+    let ip = match frame.link_type {
+        LINKTYPE_ETHERNET => {
+            let mut ethertype = u16::from_be_bytes(data.get(12..14)?.try_into().ok()?);
+            let mut offset = 14;
+            while ethertype == ETHERTYPE_VLAN {
+                ethertype = u16::from_be_bytes(data.get(offset + 2..offset + 4)?.try_into().ok()?);
+                offset += 4;
+            }
+            (ethertype == ETHERTYPE_IPV4).then_some(data.get(offset..)?)?
+        }
+        LINKTYPE_LINUX_SLL => {
+            let protocol = u16::from_be_bytes(data.get(14..16)?.try_into().ok()?);
+            (protocol == ETHERTYPE_IPV4).then_some(data.get(16..)?)?
+        }
+        LINKTYPE_LINUX_SLL2 => {
+            let protocol = u16::from_be_bytes(data.get(0..2)?.try_into().ok()?);
+            (protocol == ETHERTYPE_IPV4).then_some(data.get(20..)?)?
+        }
+        //The address family is in the byte order of the capturing machine, 2 is IPv4 everywhere
+        LINKTYPE_NULL => {
+            let family = data.get(0..4)?;
+            (family == [2, 0, 0, 0] || family == [0, 0, 0, 2]).then_some(data.get(4..)?)?
+        }
+        LINKTYPE_RAW => data,
+        _ => return None,
+    };
+
+    if ip.first()? >> 4 != 4 || *ip.get(9)? != IP_PROTOCOL_UDP {
+        return None;
+    }
+    //Fragments other than a complete datagram can't be decoded on their own
+    let fragment = u16::from_be_bytes(ip.get(6..8)?.try_into().ok()?);
+    if fragment & 0x3fff != 0 {
+        return None;
+    }
+    let header_len = usize::from(ip[0] & 0x0f) * 4;
+    let total_len = usize::from(u16::from_be_bytes(ip.get(2..4)?.try_into().ok()?));
+    let src_ip = Ipv4Addr::from(<[u8; 4]>::try_from(ip.get(12..16)?).ok()?);
+    let dst_ip = Ipv4Addr::from(<[u8; 4]>::try_from(ip.get(16..20)?).ok()?);
+
+    let udp = ip.get(header_len..total_len.min(ip.len()))?;
+    let src_port = u16::from_be_bytes(udp.get(0..2)?.try_into().ok()?);
+    let dst_port = u16::from_be_bytes(udp.get(2..4)?.try_into().ok()?);
+    let udp_len = usize::from(u16::from_be_bytes(udp.get(4..6)?.try_into().ok()?));
+    Some(UdpDatagram {
+        time: frame.time,
+        src: SocketAddr::from((src_ip, src_port)),
+        dst: SocketAddr::from((dst_ip, dst_port)),
+        payload: udp.get(8..udp_len.min(udp.len()))?.to_vec(),
+    })
+}
+
+struct Reader {
+    little_endian: bool,
+}
+
+impl Reader {
+    fn u16(&self, bytes: &[u8], offset: usize) -> u16 {
+        let raw: [u8; 2] = bytes[offset..offset + 2].try_into().unwrap();
+        if self.little_endian {
+            u16::from_le_bytes(raw)
+        } else {
+            u16::from_be_bytes(raw)
+        }
+    }
+
+    fn u32(&self, bytes: &[u8], offset: usize) -> u32 {
+        let raw: [u8; 4] = bytes[offset..offset + 4].try_into().unwrap();
+        if self.little_endian {
+            u32::from_le_bytes(raw)
+        } else {
+            u32::from_be_bytes(raw)
+        }
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,251 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Error, ErrorKind, Write};
+use std::ops::RangeInclusive;
+
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+
+use crate::{
+    cmds::{Cmd, TypeLengthValue},
+    values::{format_mac, from_hex, to_hex},
+    BlockingClient, Switch,
+};
+
+//
+//Reverse engineering aid: every TLV ID in a range is queried and whatever the switch answers is saved, so the
+//results of different models and firmware versions can be compared to fill in the Cmd placeholders
+//
+
+//0xffff would read as the end of message marker
+pub const LAST_ID: u16 = 0xfffe;
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ProbeReport {
+    pub model: String,
+    pub firmware: String,
+    pub mac: String,
+    pub range: String,
+    //IDs the switch refused with an error status
+    #[serde(default)]
+    pub rejected: Vec<String>,
+    //IDs nothing came back for, not even an error
+    #[serde(default)]
+    pub silent: Vec<String>,
+    //Answered IDs with their raw values, keyed by the ID in hex
+    #[serde(default)]
+    pub tlvs: BTreeMap<String, Vec<String>>,
+}
+
+impl ProbeReport {
+    pub fn label(&self) -> String {
+        if self.firmware.is_empty() {
+            self.model.clone()
+        } else {
+            format!("{} {}", self.model, self.firmware)
+        }
+    }
+
+    //Byte lengths of the values answered for an ID, None when it was not answered
+    fn lengths(&self, id: &str) -> Option<Vec<usize>> {
+        let values = self.tlvs.get(id)?;
+        Some(values.iter().map(|value| value.len() / 2).collect())
+    }
+
+    //Short description of what came back for an ID, e.g. "49 x8" for eight records of 49 bytes
+    fn cell(&self, id: &str) -> String {
+        if self.rejected.iter().any(|rejected| rejected == id) {
+            return String::from("rejected");
+        }
+        if self.silent.iter().any(|silent| silent == id) {
+            return String::from("silent");
+        }
+        match self.lengths(id) {
+            None => String::from("-"),
+            Some(lengths) if lengths.len() > 1 && lengths.iter().all(|len| *len == lengths[0]) => {
+                format!("{} x{}", lengths[0], lengths.len())
+            }
+            Some(lengths) => {
+                let lengths: Vec<String> = lengths.iter().map(usize::to_string).collect();
+                lengths.join(",")
+            }
+        }
+    }
+}
+
+//A single ID or a range of them in hex, e.g. 7400 or 0000-00ff
+pub fn parse_range(value: &str) -> Result<RangeInclusive<u16>, String> {
+    let parse_id = |id: &str| {
+        u16::from_str_radix(id.trim().trim_start_matches("0x"), 16)
+            .map_err(|_| format!("'{}' is not a TLV ID in hex", id))
+    };
+    let range = match value.split_once('-') {
+        Some((first, last)) => parse_id(first)?..=parse_id(last)?,
+        None => parse_id(value)?..=parse_id(value)?,
+    };
+    if range.is_empty() {
+        return Err(format!("'{}' is an empty range", value));
+    }
+    Ok(*range.start()..=(*range.end()).min(LAST_ID))
+}
+
+//Queries the IDs a batch at a time. Batches the switch rejects or drops are split until the culprit is found
+pub fn scan(
+    client: &BlockingClient,
+    switch: &Switch,
+    range: RangeInclusive<u16>,
+    batch: usize,
+) -> Result<ProbeReport, Error> {
+    let mut report = ProbeReport {
+        model: switch.model.clone(),
+        mac: format_mac(&switch.mac_address),
+        range: format!("{:04x}-{:04x}", range.start(), range.end()),
+        ..ProbeReport::default()
+    };
+    let ids: Vec<u16> = range.collect();
+    for ids in ids.chunks(batch.max(1)) {
+        probe_ids(client, switch, ids, &mut report)?;
+    }
+
+    let firmware: [u8; 2] = Cmd::CMD_FW_Version.into();
+    report.firmware = report
+        .tlvs
+        .get(&to_hex(&firmware))
+        .and_then(|values| values.first())
+        .and_then(|value| from_hex(value).ok())
+        .map(|value| {
+            String::from_utf8_lossy(&value)
+                .trim_end_matches('\0')
+                .to_string()
+        })
+        .unwrap_or_default();
+    Ok(report)
+}
+
+fn probe_ids(
+    client: &BlockingClient,
+    switch: &Switch,
+    ids: &[u16],
+    report: &mut ProbeReport,
+) -> Result<(), Error> {
+    let tlvs = ids
+        .iter()
+        .map(|id| TypeLengthValue::from(id.to_be_bytes()))
+        .collect();
+    let err = match client.query_tlvs(switch, tlvs) {
+        Ok(resp) => {
+            for tlv in resp.get_cmds() {
+                report
+                    .tlvs
+                    .entry(to_hex(&tlv.cmd()))
+                    .or_default()
+                    .push(to_hex(tlv.value()));
+            }
+            return Ok(());
+        }
+        Err(err) => err,
+    };
+
+    match err.kind() {
+        ErrorKind::PermissionDenied | ErrorKind::TimedOut if ids.len() > 1 => {
+            if err.kind() == ErrorKind::TimedOut {
+                check_alive(client, switch)?;
+            }
+            let (first, second) = ids.split_at(ids.len() / 2);
+            probe_ids(client, switch, first, report)?;
+            probe_ids(client, switch, second, report)
+        }
+        ErrorKind::PermissionDenied => {
+            report.rejected.push(to_hex(&ids[0].to_be_bytes()));
+            Ok(())
+        }
+        ErrorKind::TimedOut => {
+            check_alive(client, switch)?;
+            report.silent.push(to_hex(&ids[0].to_be_bytes()));
+            Ok(())
+        }
+        _ => Err(err),
+    }
+}
+
+//Tells an ID the switch ignores apart from a switch that went away, which would otherwise time out on every ID
+fn check_alive(client: &BlockingClient, switch: &Switch) -> Result<(), Error> {
+    client
+        .query(switch, &[Cmd::CMD_Model])
+        .map(|_| ())
+        .map_err(|err| Error::new(err.kind(), format!("Switch stopped answering: {}", err)))
+}
+
+pub fn print_report(out: &mut dyn Write, report: &ProbeReport) -> Result<(), io::Error> {
+    for (id, values) in &report.tlvs {
+        writeln!(
+            out,
+            "  {} {:<32} {:>8}  {}",
+            id,
+            id_name(id),
+            report.cell(id),
+            values.first().map_or(String::new(), |value| shorten(value))
+        )?;
+    }
+    writeln!(
+        out,
+        "{} answered, {} rejected, {} silent in {}",
+        report.tlvs.len(),
+        report.rejected.len(),
+        report.silent.len(),
+        report.range
+    )
+}
+
+//One line per ID the reports disagree on, or every ID any of them knows with all set
+pub fn diff(out: &mut dyn Write, reports: &[ProbeReport], all: bool) -> Result<usize, io::Error> {
+    let ids: BTreeSet<&String> = reports
+        .iter()
+        .flat_map(|report| {
+            report
+                .tlvs
+                .keys()
+                .chain(&report.rejected)
+                .chain(&report.silent)
+        })
+        .collect();
+
+    let labels: Vec<String> = reports.iter().map(ProbeReport::label).collect();
+    let width = labels.iter().map(String::len).max().unwrap_or(0).max(8);
+    let mut header = format!("{:<37}", "TLV");
+    for label in &labels {
+        header += &format!(" {:<width$}", label);
+    }
+    writeln!(out, "{}", header.trim_end())?;
+
+    let mut differences = 0;
+    for id in ids {
+        let cells: Vec<String> = reports.iter().map(|report| report.cell(id)).collect();
+        let differs = cells.iter().any(|cell| *cell != cells[0]);
+        if differs {
+            differences += 1;
+        }
+        if !differs && !all {
+            continue;
+        }
+        let mut row = format!("{} {:<32}", id, id_name(id));
+        for cell in &cells {
+            row += &format!(" {:<width$}", cell);
+        }
+        writeln!(out, "{}", row.trim_end())?;
+    }
+    Ok(differences)
+}
+
+//Long values like port statistics would wrap the table
+fn shorten(hex: &str) -> String {
+    match hex.get(..32) {
+        Some(start) if hex.len() > 32 => format!("{}..", start),
+        _ => hex.to_string(),
+    }
+}
+
+fn id_name(id: &str) -> String {
+    Cmd::iter()
+        .find(|cmd| to_hex(&<[u8; 2]>::from(cmd)) == id)
+        .map_or(String::from("?unknown"), |cmd| cmd.name())
+}
@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cmds::{Cmd, TypeLengthValue};
+use crate::values::{format_mac, from_hex, to_hex};
+
+//
+//Captures of raw NSDP conversations. Every request and the reply it got are appended to a TOML file named after
+//the MAC of the replying switch, so captures from real switches can be replayed as golden test fixtures.
+//Passwords are blanked before anything is written
+//
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Capture {
+    #[serde(default, rename = "exchange")]
+    pub exchanges: Vec<Exchange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Exchange {
+    //Milliseconds since the Unix epoch when the reply arrived
+    pub time: u64,
+    pub mac: String,
+    pub request: String,
+    pub reply: String,
+}
+
+pub struct Recorder {
+    dir: PathBuf,
+    files: Mutex<()>,
+}
+
+impl Recorder {
+    pub fn create(dir: &Path) -> Result<Recorder, Error> {
+        fs::create_dir_all(dir).map_err(|err| {
+            Error::new(
+                err.kind(),
+                format!("Could not create {}: {}", dir.display(), err),
+            )
+        })?;
+        Ok(Recorder {
+            dir: dir.to_path_buf(),
+            files: Mutex::new(()),
+        })
+    }
+
+    pub fn record(&self, request: &[u8], reply: &[u8]) -> Result<(), Error> {
+        let mac: [u8; 6] = reply
+            .get(14..20)
+            .and_then(|mac| mac.try_into().ok())
+            .ok_or(Error::new(ErrorKind::InvalidData, "Reply too short"))?;
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+        let capture = Capture {
+            exchanges: vec![Exchange {
+                time,
+                mac: format_mac(&mac),
+                request: to_hex(&mask_passwords(request)),
+                reply: to_hex(reply),
+            }],
+        };
+        let entry = toml::to_string(&capture).map_err(Error::other)?;
+
+        //Appending a table keeps the file valid TOML, the lock keeps concurrent entries from interleaving
+        let _files = self.files.lock().unwrap_or_else(PoisonError::into_inner);
+        let path = self
+            .dir
+            .join(format!("{}.toml", format_mac(&mac).replace(':', "-")));
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", entry)
+    }
+}
+
+//Every capture file in the directory
+pub fn load_captures(dir: &Path) -> Result<Vec<Exchange>, Error> {
+    let mut exchanges = Vec::new();
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    paths.sort();
+    for path in paths
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+    {
+        let capture: Capture = toml::from_str(&fs::read_to_string(&path)?).map_err(|err| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), err),
+            )
+        })?;
+        exchanges.extend(capture.exchanges);
+    }
+    Ok(exchanges)
+}
+
+//Answers requests with the recorded replies. Requests are matched byte for byte apart from the host MAC, the
+//sequence number and passwords. A request recorded several times gets its replies in recorded order, the last
+//one repeating once they run out
+pub struct Replay {
+    recorded: BTreeMap<(Vec<u8>, [u8; 6]), Recorded>,
+}
+
+#[derive(Default)]
+struct Recorded {
+    replies: Vec<Vec<u8>>,
+    next: usize,
+}
+
+impl Replay {
+    pub fn new(exchanges: &[Exchange]) -> Result<Replay, String> {
+        let mut recorded: BTreeMap<(Vec<u8>, [u8; 6]), Recorded> = BTreeMap::new();
+        for exchange in exchanges {
+            let request = from_hex(&exchange.request)?;
+            let reply = from_hex(&exchange.reply)?;
+            if request.len() < 32 || reply.len() < 32 {
+                return Err(format!(
+                    "Exchange with {} at {} is shorter than a header",
+                    exchange.mac, exchange.time
+                ));
+            }
+            let mac: [u8; 6] = reply[14..20].try_into().unwrap();
+            recorded
+                .entry((match_key(&request), mac))
+                .or_default()
+                .replies
+                .push(reply);
+        }
+        Ok(Replay { recorded })
+    }
+
+    pub fn load(dir: &Path) -> Result<Replay, Error> {
+        Replay::new(&load_captures(dir)?).map_err(|err| Error::new(ErrorKind::InvalidData, err))
+    }
+
+    //One reply from every switch that answered the request when it was recorded
+    pub fn answer(&mut self, request: &[u8]) -> Vec<Vec<u8>> {
+        if request.len() < 32 {
+            return Vec::new();
+        }
+        let key = match_key(request);
+        self.recorded
+            .iter_mut()
+            .filter(|((request, _), _)| *request == key)
+            .map(|(_, recorded)| {
+                let last = recorded.replies.len() - 1;
+                let mut reply = recorded.replies[recorded.next.min(last)].clone();
+                recorded.next += 1;
+                reply[8..14].copy_from_slice(&request[8..14]);
+                reply[22..24].copy_from_slice(&request[22..24]);
+                reply
+            })
+            .collect()
+    }
+}
+
+fn match_key(request: &[u8]) -> Vec<u8> {
+    let mut key = mask_passwords(request);
+    key[8..14].fill(0);
+    key[22..24].fill(0);
+    key
+}
+
+//Blanks the value of password TLVs, the rest of the datagram is kept as is
+pub fn mask_passwords(datagram: &[u8]) -> Vec<u8> {
+    let mut masked = datagram.to_vec();
+    let secrets: [[u8; 2]; 2] = [Cmd::CMD_Password.into(), Cmd::CMD_New_Password.into()];
+    let mut index = 32;
+    while let Ok((tlv, len)) = TypeLengthValue::from_raw(&datagram[index.min(datagram.len())..]) {
+        if secrets.contains(&tlv.cmd()) {
+            masked[index + 4..index + len].fill(0);
+        }
+        index += len;
+    }
+    masked
+}
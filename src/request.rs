@@ -1,8 +1,7 @@
-use std::borrow::BorrowMut;
+use crate::cmds::{TLVReadingError, TypeLengthValue};
+use crate::packet::Packet;
 
-use crate::cmds::TypeLengthValue;
-
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Session {
     seq: [u8; 2],
     source_mac: [u8; 6],
@@ -26,9 +25,17 @@ impl Session {
         }
     }
 
+    pub fn get_host_mac(&self) -> [u8; 6] {
+        self.source_mac
+    }
+
     pub fn get_switch_mac(&self) -> [u8; 6] {
         self.dest_mac
     }
+
+    pub fn get_seq(&self) -> [u8; 2] {
+        self.seq
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -48,6 +55,23 @@ impl Request {
         RequestBuilder::new()
     }
 
+    //Inverse of format, anything but a query or transmit request is rejected
+    pub fn parse(datagram: &[u8]) -> Result<Request, TLVReadingError> {
+        Request::try_from(Packet::parse(datagram)?)
+    }
+
+    pub fn get_ctype(&self) -> [u8; 2] {
+        self.ctype
+    }
+
+    pub fn get_session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn get_cmds(&self) -> &Vec<TypeLengthValue> {
+        &self.cmds
+    }
+
     pub fn format(&self) -> Vec<u8> {
         let mut head: [u8; 32] = [0; 32];
         head[0..2].copy_from_slice(&self.ctype);
@@ -104,16 +128,41 @@ impl RequestBuilder {
         self
     }
 
-    pub fn build(mut self) -> Request {
+    pub fn build(self) -> Request {
         Request {
             cmds: self.cmds,
             ctype: self.ctype.expect("CType must be provided!"),
             session: self.session.expect("Session information must be provided!"),
-            nsdp: crate::cmds::ProtoConsts::NDSP.value()[0..4].try_into().unwrap(),
-            reserved: crate::cmds::ProtoConsts::Reserved.value()[0..2].try_into().unwrap(),
+            nsdp: crate::cmds::ProtoConsts::NDSP.value()[0..4]
+                .try_into()
+                .unwrap(),
+            reserved: crate::cmds::ProtoConsts::Reserved.value()[0..2]
+                .try_into()
+                .unwrap(),
             end_of_msg: crate::cmds::ProtoConsts::EndOfMessage.value()[0..4]
                 .try_into()
                 .unwrap(),
         }
     }
 }
+
+impl TryFrom<Packet> for Request {
+    type Error = TLVReadingError;
+
+    fn try_from(packet: Packet) -> Result<Self, Self::Error> {
+        let packet_type = packet.get_type();
+        if !packet_type.is_request() {
+            return Err(TLVReadingError::InvalidType(format!(
+                "A {} is not a request",
+                packet_type
+            )));
+        }
+        let mut builder = Request::builder()
+            .ctype(packet_type.into())
+            .session(packet.get_session().clone());
+        for cmd in packet.into_cmds() {
+            builder = builder.add_cmd(cmd);
+        }
+        Ok(builder.build())
+    }
+}
@@ -1,53 +1,54 @@
-use std::string::FromUtf8Error;
-
 use crate::cmds::{Cmd, TLVReadingError, TypeLengthValue};
+use crate::packet::read_tlvs;
 use crate::request::Session;
 
 #[derive(Debug)]
 pub struct Response {
     cmds: Vec<TypeLengthValue>,
     ctype: [u8; 2],
+    status: [u8; 2],
     session: Session, //Persistent details from switch. Source MAC is still PC's and Dest MAC is still Switches'
 }
 
 impl Response {
     pub fn build(msg: &[u8]) -> Result<Response, TLVReadingError> {
+        if msg.len() < 32 {
+            return Err(TLVReadingError::ArrTooShort(String::from(
+                "Raw array too short for header",
+            )));
+        }
+
         let ctype: [u8; 2] = msg[0..2].try_into().unwrap();
-        //2 to 8 is reserved
+        let status: [u8; 2] = msg[2..4].try_into().unwrap(); //Non zero when the switch rejected the request
+                                                             //4 to 8 is reserved
         let source_mac: [u8; 6] = msg[8..14].try_into().unwrap();
         let dest_mac: [u8; 6] = msg[14..20].try_into().unwrap();
         //20 to 22 is reserved
         let seq: [u8; 2] = msg[22..24].try_into().unwrap();
         let _nsdp: [u8; 4] = msg[24..28].try_into().unwrap();
 
-        let mut cmds: Vec<TypeLengthValue> = Vec::new();
-
-        let mut current_index: usize = 32;
-
-        loop {
-            let read_result = TypeLengthValue::from_raw(&msg[current_index..]);
-            match read_result {
-                Ok((tlv, len_index)) => {
-                    current_index += len_index;
-                    cmds.push(tlv);
-                }
-                Err(error) => match error {
-                    TLVReadingError::ArrTooShort(_) => {
-                        return Err(error);
-                    }
-                    TLVReadingError::EndOfMessage => break,
-                    _ => {},
-                },
-            }
-        }
+        let cmds = read_tlvs(&msg[32..])?;
 
         Ok(Response {
             cmds,
             ctype,
+            status,
             session: Session::new(source_mac, dest_mac, seq),
         })
     }
 
+    pub fn get_ctype(&self) -> [u8; 2] {
+        self.ctype
+    }
+
+    pub fn get_status(&self) -> [u8; 2] {
+        self.status
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status == [0x00, 0x00]
+    }
+
     pub fn get_session(&self) -> &Session {
         &self.session
     }
@@ -64,5 +65,4 @@ impl Response {
         }
         Err(String::from("Not found"))
     }
-
 }
@@ -0,0 +1,74 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use crate::cmds::Cmd;
+use crate::dispatch::PortPair;
+use crate::response::Response;
+use crate::values::format_mac;
+
+#[derive(Debug, Clone)]
+pub struct Switch {
+    pub name: String,
+    pub model: String,
+    pub location: String,
+    pub ipv4_address_reported: Ipv4Addr,
+
+    pub ipv4_address: String,
+    pub mac_address: [u8; 6],
+    pub port_pair: PortPair,
+}
+
+impl Switch {
+    //Builds the switch from a reply to the discovery query, src_addr is where the reply came from and
+    //port_pair the ports the query went out on
+    pub fn from_response(
+        response: &Response,
+        src_addr: SocketAddr,
+        port_pair: PortPair,
+    ) -> Option<Switch> {
+        let string = |cmd: &Cmd| -> Option<String> {
+            let value: String = response.get_cmd(cmd).ok()?.try_into().ok()?;
+            Some(value.trim_end_matches('\0').to_string())
+        };
+
+        Some(Switch {
+            name: string(&Cmd::CMD_Name)?,
+            model: string(&Cmd::CMD_Model)?,
+            location: string(&Cmd::CMD_Location)?,
+            ipv4_address_reported: response.get_cmd(&Cmd::CMD_IPv4).ok()?.try_into().ok()?,
+
+            ipv4_address: src_addr.to_string(),
+            mac_address: response.get_session().get_switch_mac(),
+            port_pair,
+        })
+    }
+
+    //Where unicast requests go, the address the switch replied from or else the one it reports
+    pub fn ip(&self) -> IpAddr {
+        self.ipv4_address
+            .parse::<SocketAddr>()
+            .map(|addr| addr.ip())
+            .unwrap_or(IpAddr::V4(self.ipv4_address_reported))
+    }
+}
+
+impl fmt::Display for Switch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}) {}",
+            self.name,
+            self.model,
+            format_mac(&self.mac_address)
+        )?;
+        write!(
+            f,
+            " at {} via {}",
+            self.ipv4_address_reported, self.ipv4_address
+        )?;
+        if !self.location.is_empty() {
+            write!(f, " [{}]", self.location)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+use std::io::{Error, ErrorKind};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+//
+//Minimal read only TFTP server (RFC 1350) used to hand a firmware image to a switch in upgrade mode, and the
+//client side the bootloader of an emulated switch fetches it with. Only octet transfers with the default
+//512 byte blocks are supported, which is what the bootloaders ask for
+//
+
+const OPCODE_RRQ: u16 = 1;
+const OPCODE_DATA: u16 = 3;
+const OPCODE_ACK: u16 = 4;
+const OPCODE_ERROR: u16 = 5;
+
+const BLOCK_SIZE: usize = 512;
+const RETRIES: u32 = 5;
+const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct TftpServer {
+    socket: UdpSocket,
+}
+
+impl TftpServer {
+    pub fn bind(addr: SocketAddr) -> Result<TftpServer, Error> {
+        let socket = UdpSocket::bind(addr).map_err(|err| {
+            Error::new(
+                err.kind(),
+                format!("Could not bind TFTP server to {}: {}", addr, err),
+            )
+        })?;
+        Ok(TftpServer { socket })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.socket.local_addr()
+    }
+
+    //Waits for a single read request and sends the image to whoever asked, whatever file name was requested.
+    //Returns who asked and for which file
+    pub fn serve_once(
+        &self,
+        image: &[u8],
+        timeout: Duration,
+    ) -> Result<(SocketAddr, String), Error> {
+        let started = Instant::now();
+        let mut buf = [0; 1024];
+
+        let (client, file) = loop {
+            let remaining = timeout.checked_sub(started.elapsed()).ok_or(Error::new(
+                ErrorKind::TimedOut,
+                "No TFTP read request received",
+            ))?;
+            self.socket
+                .set_read_timeout(Some(remaining.max(Duration::from_millis(1))))?;
+
+            let (len, client) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    continue
+                }
+                Err(err) => return Err(err),
+            };
+            if len >= 2 && u16::from_be_bytes([buf[0], buf[1]]) == OPCODE_RRQ {
+                let mut fields = buf[2..len].split(|byte| *byte == 0);
+                let file = String::from_utf8_lossy(fields.next().unwrap_or_default());
+                break (client, file.into_owned());
+            }
+        };
+
+        //Every transfer gets its own port (transfer ID) as the RFC asks for
+        let transfer = UdpSocket::bind(SocketAddr::new(self.socket.local_addr()?.ip(), 0))?;
+        transfer.connect(client)?;
+        transfer.set_read_timeout(Some(ACK_TIMEOUT))?;
+
+        //A final block shorter than 512 bytes ends the transfer, so an exact multiple needs an empty one
+        let block_count = image.len() / BLOCK_SIZE + 1;
+        for index in 0..block_count {
+            let block_number = ((index + 1) % 0x10000) as u16;
+            let start = index * BLOCK_SIZE;
+            let end = (start + BLOCK_SIZE).min(image.len());
+
+            let mut packet = Vec::with_capacity(4 + BLOCK_SIZE);
+            packet.extend_from_slice(&OPCODE_DATA.to_be_bytes());
+            packet.extend_from_slice(&block_number.to_be_bytes());
+            packet.extend_from_slice(&image[start..end]);
+
+            send_until_acked(&transfer, &packet, block_number)?;
+        }
+
+        Ok((client, file))
+    }
+}
+
+//Reads a file from a TFTP server, acknowledging every block until a short one ends the transfer
+pub fn fetch(server: SocketAddr, file: &str, timeout: Duration) -> Result<Vec<u8>, Error> {
+    let socket = UdpSocket::bind(SocketAddr::new(
+        match server {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        },
+        0,
+    ))?;
+    socket.set_read_timeout(Some(ACK_TIMEOUT.min(timeout)))?;
+
+    let mut request = OPCODE_RRQ.to_be_bytes().to_vec();
+    request.extend_from_slice(file.as_bytes());
+    request.push(0);
+    request.extend_from_slice(b"octet");
+    request.push(0);
+
+    let started = Instant::now();
+    let mut image = Vec::new();
+    let mut buf = [0; 4 + BLOCK_SIZE];
+    //The server answers from a port of its own, which the acknowledgements have to go to
+    let mut transfer: Option<SocketAddr> = None;
+    let mut last_block: u16 = 0;
+    let mut last_sent = request.clone();
+    socket.send_to(&request, server)?;
+
+    loop {
+        if started.elapsed() >= timeout {
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                format!("TFTP transfer of '{}' did not finish", file),
+            ));
+        }
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                socket.send_to(&last_sent, transfer.unwrap_or(server))?;
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        if len < 4 || transfer.is_some_and(|transfer| transfer != from) {
+            continue;
+        }
+
+        match u16::from_be_bytes([buf[0], buf[1]]) {
+            OPCODE_DATA => {
+                transfer = Some(from);
+                let block_number = u16::from_be_bytes([buf[2], buf[3]]);
+                //A repeated block means our acknowledgement got lost, it is acknowledged again below
+                if block_number == last_block.wrapping_add(1) {
+                    image.extend_from_slice(&buf[4..len]);
+                    last_block = block_number;
+                }
+                last_sent = OPCODE_ACK.to_be_bytes().to_vec();
+                last_sent.extend_from_slice(&block_number.to_be_bytes());
+                socket.send_to(&last_sent, from)?;
+                if block_number == last_block && len - 4 < BLOCK_SIZE {
+                    return Ok(image);
+                }
+            }
+            OPCODE_ERROR => {
+                return Err(Error::new(
+                    ErrorKind::ConnectionAborted,
+                    format!(
+                        "TFTP server refused '{}': {}",
+                        file,
+                        String::from_utf8_lossy(&buf[4..len]).trim_end_matches('\0')
+                    ),
+                ))
+            }
+            _ => {}
+        }
+    }
+}
+
+fn send_until_acked(transfer: &UdpSocket, packet: &[u8], block_number: u16) -> Result<(), Error> {
+    let mut buf = [0; 516];
+    for _ in 0..RETRIES {
+        transfer.send(packet)?;
+        loop {
+            let len = match transfer.recv(&mut buf) {
+                Ok(len) => len,
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    break
+                }
+                Err(err) => return Err(err),
+            };
+            if len < 4 {
+                continue;
+            }
+            match u16::from_be_bytes([buf[0], buf[1]]) {
+                OPCODE_ACK if u16::from_be_bytes([buf[2], buf[3]]) == block_number => return Ok(()),
+                OPCODE_ERROR => {
+                    return Err(Error::new(
+                        ErrorKind::ConnectionAborted,
+                        format!(
+                            "TFTP client aborted: {}",
+                            String::from_utf8_lossy(&buf[4..len]).trim_end_matches('\0')
+                        ),
+                    ))
+                }
+                _ => {} //Duplicate ACKs for older blocks are ignored
+            }
+        }
+    }
+
+    Err(Error::new(
+        ErrorKind::TimedOut,
+        format!("TFTP block {} was never acknowledged", block_number),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::thread;
+
+    //Reads the image like a bootloader would, acknowledging every block
+    fn fetch(server: SocketAddr) -> Vec<u8> {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut request = OPCODE_RRQ.to_be_bytes().to_vec();
+        request.extend_from_slice(b"GS105E.bin\0octet\0");
+        socket.send_to(&request, server).unwrap();
+
+        let mut image = Vec::new();
+        let mut buf = [0; 516];
+        loop {
+            let (len, transfer) = socket.recv_from(&mut buf).unwrap();
+            assert_ne!(transfer, server, "Data comes from a port of its own");
+            assert_eq!(u16::from_be_bytes([buf[0], buf[1]]), OPCODE_DATA);
+            image.extend_from_slice(&buf[4..len]);
+            let mut ack = OPCODE_ACK.to_be_bytes().to_vec();
+            ack.extend_from_slice(&buf[2..4]);
+            socket.send_to(&ack, transfer).unwrap();
+            if len < 4 + BLOCK_SIZE {
+                return image;
+            }
+        }
+    }
+
+    #[test]
+    fn serves_the_image_in_blocks_ending_with_a_short_one() {
+        //An exact multiple of the block size needs an empty block at the end
+        for size in [700, 2 * BLOCK_SIZE] {
+            let server = TftpServer::bind((Ipv4Addr::LOCALHOST, 0).into()).unwrap();
+            let addr = server.local_addr().unwrap();
+            let image: Vec<u8> = (0..size).map(|index| index as u8).collect();
+            let served = image.clone();
+            let serving = thread::spawn(move || server.serve_once(&served, Duration::from_secs(5)));
+
+            assert_eq!(fetch(addr), image);
+            assert!(serving.join().unwrap().is_ok());
+        }
+    }
+
+    #[test]
+    fn gives_up_without_a_request() {
+        let server = TftpServer::bind((Ipv4Addr::LOCALHOST, 0).into()).unwrap();
+        let err = server
+            .serve_once(&[0x00], Duration::from_millis(100))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,1197 @@
+use std::{fmt, str::FromStr};
+
+use crate::cmds::{Cmd, TLVReadingError, TypeLengthValue};
+
+//
+//Section for typed values carried inside TLVs, along with the conversions to and from their raw layout
+//
+
+pub const MAX_PORTS: u8 = 64;
+
+//Front panel port, numbered from 1 like on the switch itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Port(u8);
+
+impl Port {
+    pub fn new(number: u8) -> Option<Port> {
+        if number == 0 || number > MAX_PORTS {
+            return None;
+        }
+        Some(Port(number))
+    }
+
+    pub fn number(&self) -> u8 {
+        self.0
+    }
+}
+
+impl fmt::Display for Port {
+    //Keeps the width and alignment of the caller, the ports table relies on them
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl FromStr for Port {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number: u8 = s
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid port number '{}'", s))?;
+        Port::new(number).ok_or(format!("Port numbers range from 1 to {}", MAX_PORTS))
+    }
+}
+
+//Set of ports, stored with bit n - 1 representing port n
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub struct PortSet(u64);
+
+impl PortSet {
+    pub fn new() -> PortSet {
+        PortSet(0)
+    }
+
+    pub fn insert(&mut self, port: Port) {
+        self.0 |= 1 << (port.number() - 1);
+    }
+
+    pub fn contains(&self, port: Port) -> bool {
+        self.0 & (1 << (port.number() - 1)) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Port> + '_ {
+        (1..=MAX_PORTS)
+            .filter_map(Port::new)
+            .filter(|port| self.contains(*port))
+    }
+
+    //Switches send port bitmaps MSB first, so the top bit of the first byte is port 1
+    pub fn from_bitmap(bytes: &[u8]) -> PortSet {
+        let mut set = PortSet::new();
+        for (byte_index, byte) in bytes.iter().enumerate().take(8) {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    set.insert(Port((byte_index * 8 + bit + 1) as u8));
+                }
+            }
+        }
+        set
+    }
+
+    pub fn to_bitmap(self, port_count: u8) -> Vec<u8> {
+        let mut bytes = vec![0; bitmap_len(port_count)];
+        for port in self.iter() {
+            let index = (port.number() - 1) as usize;
+            if index / 8 < bytes.len() {
+                bytes[index / 8] |= 0x80 >> (index % 8);
+            }
+        }
+        bytes
+    }
+}
+
+impl FromIterator<Port> for PortSet {
+    fn from_iter<T: IntoIterator<Item = Port>>(iter: T) -> Self {
+        let mut set = PortSet::new();
+        for port in iter {
+            set.insert(port);
+        }
+        set
+    }
+}
+
+impl fmt::Display for PortSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "none");
+        }
+        let ports: Vec<String> = self.iter().map(|port| port.to_string()).collect();
+        write!(f, "{}", ports.join(","))
+    }
+}
+
+//Accepts lists like "1,2,3" as well as ranges like "1-4,8", "none" or an empty string give an empty set
+impl FromStr for PortSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut set = PortSet::new();
+        if s.trim().eq_ignore_ascii_case("none") {
+            return Ok(set);
+        }
+        for part in s.split(',').filter(|part| !part.trim().is_empty()) {
+            match part.split_once('-') {
+                Some((start, end)) => {
+                    let start: Port = start.parse()?;
+                    let end: Port = end.parse()?;
+                    if start > end {
+                        return Err(format!("'{}' is a reversed range", part.trim()));
+                    }
+                    for number in start.number()..=end.number() {
+                        set.insert(Port(number));
+                    }
+                }
+                None => set.insert(part.parse()?),
+            }
+        }
+        Ok(set)
+    }
+}
+
+pub fn format_mac(mac: &[u8; 6]) -> String {
+    let bytes: Vec<String> = mac.iter().map(|byte| format!("{:02x}", byte)).collect();
+    bytes.join(":")
+}
+
+//Accepts aa:bb:cc:dd:ee:ff as well as aa-bb-cc-dd-ee-ff
+pub fn parse_mac(str: &str) -> Option<[u8; 6]> {
+    let bytes: Vec<u8> = str
+        .split([':', '-'])
+        .map(|part| u8::from_str_radix(part, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    bytes.try_into().ok()
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+//Whitespace between bytes is allowed, e.g. "0102 0000" or "01 02 00 00"
+pub fn from_hex(str: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<char> = str.chars().filter(|char| !char.is_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("Odd number of hex digits in '{}'", str));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair: String = pair.iter().collect();
+            u8::from_str_radix(&pair, 16).map_err(|_| format!("Invalid hex byte '{}'", pair))
+        })
+        .collect()
+}
+
+pub fn bitmap_len(port_count: u8) -> usize {
+    (port_count as usize).div_ceil(8).max(1)
+}
+
+//Passwords go over the wire XORed with a fixed key, characters past its end are dropped
+pub const PASSWORD_KEY: &str = "NtgrSmartSwitchRock";
+
+pub fn encode_password(plain: &str) -> Vec<u8> {
+    let plainpass = plain.as_bytes();
+    let hashkey = PASSWORD_KEY.as_bytes();
+
+    let mut password: Vec<u8> = Vec::<u8>::new();
+
+    for (plain_char, hash_char) in plainpass.iter().zip(hashkey) {
+        let xor = plain_char ^ hash_char;
+        password.push(xor);
+    }
+    password
+}
+
+fn expect_cmd(tlv: &TypeLengthValue, cmd: &Cmd, min_len: usize) -> Result<(), TLVReadingError> {
+    if !tlv.cmd_equal_to(cmd) {
+        return Err(TLVReadingError::InvalidType(format!(
+            "Expected {:?} TLV",
+            cmd
+        )));
+    }
+    if tlv.value().len() < min_len {
+        return Err(TLVReadingError::ArrTooShort(format!(
+            "{:?} needs at least {} bytes",
+            cmd, min_len
+        )));
+    }
+    Ok(())
+}
+
+//
+//Port mirroring: destination port (0 when disabled), a reserved byte and then the source port bitmap
+//
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Mirror {
+    pub destination: Option<Port>,
+    pub sources: PortSet,
+}
+
+impl Mirror {
+    pub fn to_tlv(&self, port_count: u8) -> TypeLengthValue {
+        let mut value = vec![self.destination.map_or(0, |port| port.number()), 0x00];
+        if self.destination.is_some() {
+            value.append(&mut self.sources.to_bitmap(port_count));
+        } else {
+            value.append(&mut PortSet::new().to_bitmap(port_count));
+        }
+        TypeLengthValue::from((Cmd::CMD_Port_Mirroring, value))
+    }
+
+    //Switches drop bits past their last port instead of refusing them, so settings are checked before sending
+    pub fn validate(&self, port_count: u8) -> Result<(), String> {
+        let Some(destination) = self.destination else {
+            return Ok(());
+        };
+        if let Some(port) = self
+            .sources
+            .iter()
+            .chain([destination])
+            .find(|port| port.number() > port_count)
+        {
+            return Err(format!(
+                "Port {} does not exist, switch has {} ports",
+                port, port_count
+            ));
+        }
+        if self.sources.contains(destination) {
+            return Err(format!("Port {} cannot mirror to itself", destination));
+        }
+        if self.sources.is_empty() {
+            return Err(String::from("At least one source port is needed"));
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<TypeLengthValue> for Mirror {
+    type Error = TLVReadingError;
+
+    fn try_from(tlv: TypeLengthValue) -> Result<Self, Self::Error> {
+        expect_cmd(&tlv, &Cmd::CMD_Port_Mirroring, 3)?;
+        let value = tlv.value();
+        Ok(Mirror {
+            destination: Port::new(value[0]),
+            sources: PortSet::from_bitmap(&value[2..]),
+        })
+    }
+}
+
+impl fmt::Display for Mirror {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.destination {
+            Some(destination) => write!(f, "ports {} -> port {}", self.sources, destination),
+            None => write!(f, "disabled"),
+        }
+    }
+}
+
+//
+//IGMP snooping: spread over several TLVs, the snooping TLV holds the enabled flag and the VLAN it runs on
+//
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IgmpSnooping {
+    pub enabled: bool,
+    pub vlan: u16,
+    pub validate_ip_header: bool,
+    pub block_unknown_multicast: bool,
+    pub router_ports: PortSet,
+}
+
+impl IgmpSnooping {
+    pub const CMDS: [Cmd; 4] = [
+        Cmd::CMD_IGMP_Snooping,
+        Cmd::CMD_IGMP_Validate_IP_Header,
+        Cmd::CMD_IGMP_Block_Unknown_Multicast,
+        Cmd::CMD_IGMP_Router_Ports,
+    ];
+
+    pub fn from_tlvs(
+        snooping: TypeLengthValue,
+        validate_ip_header: TypeLengthValue,
+        block_unknown_multicast: TypeLengthValue,
+        router_ports: TypeLengthValue,
+    ) -> Result<IgmpSnooping, TLVReadingError> {
+        expect_cmd(&snooping, &Cmd::CMD_IGMP_Snooping, 4)?;
+        expect_cmd(&validate_ip_header, &Cmd::CMD_IGMP_Validate_IP_Header, 1)?;
+        expect_cmd(
+            &block_unknown_multicast,
+            &Cmd::CMD_IGMP_Block_Unknown_Multicast,
+            1,
+        )?;
+        expect_cmd(&router_ports, &Cmd::CMD_IGMP_Router_Ports, 1)?;
+
+        let value = snooping.value();
+        Ok(IgmpSnooping {
+            enabled: value[1] != 0,
+            vlan: u16::from_be_bytes([value[2], value[3]]),
+            validate_ip_header: validate_ip_header.try_into()?,
+            block_unknown_multicast: block_unknown_multicast.try_into()?,
+            router_ports: PortSet::from_bitmap(router_ports.value()),
+        })
+    }
+
+    pub fn to_tlvs(&self, port_count: u8) -> Vec<TypeLengthValue> {
+        let mut snooping = vec![0x00, self.enabled as u8];
+        snooping.extend_from_slice(&self.vlan.to_be_bytes());
+        vec![
+            TypeLengthValue::from((Cmd::CMD_IGMP_Snooping, snooping)),
+            TypeLengthValue::from((Cmd::CMD_IGMP_Validate_IP_Header, self.validate_ip_header)),
+            TypeLengthValue::from((
+                Cmd::CMD_IGMP_Block_Unknown_Multicast,
+                self.block_unknown_multicast,
+            )),
+            TypeLengthValue::from((
+                Cmd::CMD_IGMP_Router_Ports,
+                self.router_ports.to_bitmap(port_count),
+            )),
+        ]
+    }
+}
+
+impl fmt::Display for IgmpSnooping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "IGMP snooping:           {}", on_off(self.enabled))?;
+        writeln!(f, "VLAN:                    {}", self.vlan)?;
+        writeln!(
+            f,
+            "Validate IP header:      {}",
+            on_off(self.validate_ip_header)
+        )?;
+        writeln!(
+            f,
+            "Block unknown multicast: {}",
+            on_off(self.block_unknown_multicast)
+        )?;
+        write!(f, "Router ports:            {}", self.router_ports)
+    }
+}
+
+//
+//VLAN membership: one TLV per VLAN, the 2 byte VLAN ID and the member bitmap. 802.1Q VLANs add a bitmap of the
+//members that send tagged frames, port based VLANs have none
+//
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VlanMembership {
+    pub id: u16,
+    pub members: PortSet,
+    pub tagged: Option<PortSet>,
+}
+
+impl VlanMembership {
+    pub fn cmd(&self) -> Cmd {
+        match self.tagged {
+            Some(_) => Cmd::CMD_VLAN_8021Q_Members,
+            None => Cmd::CMD_VLAN_Port_Members,
+        }
+    }
+
+    pub fn to_tlv(&self, port_count: u8) -> TypeLengthValue {
+        let mut value = self.id.to_be_bytes().to_vec();
+        value.append(&mut self.members.to_bitmap(port_count));
+        if let Some(tagged) = self.tagged {
+            value.append(&mut tagged.to_bitmap(port_count));
+        }
+        TypeLengthValue::from((self.cmd(), value))
+    }
+
+    pub fn delete_tlv(id: u16) -> TypeLengthValue {
+        TypeLengthValue::from((Cmd::CMD_VLAN_Delete, id.to_be_bytes().to_vec()))
+    }
+
+    //The bitmaps are as long as the port count needs, so it is required to tell them apart
+    pub fn from_tlv(
+        tlv: &TypeLengthValue,
+        port_count: u8,
+    ) -> Result<VlanMembership, TLVReadingError> {
+        let len = bitmap_len(port_count);
+        let dot1q = tlv.cmd_equal_to(&Cmd::CMD_VLAN_8021Q_Members);
+        if dot1q {
+            expect_cmd(tlv, &Cmd::CMD_VLAN_8021Q_Members, 2 + 2 * len)?;
+        } else {
+            expect_cmd(tlv, &Cmd::CMD_VLAN_Port_Members, 2 + len)?;
+        }
+        let value = tlv.value();
+        Ok(VlanMembership {
+            id: u16::from_be_bytes([value[0], value[1]]),
+            members: PortSet::from_bitmap(&value[2..2 + len]),
+            tagged: dot1q.then(|| PortSet::from_bitmap(&value[2 + len..2 + 2 * len])),
+        })
+    }
+}
+
+pub fn on_off(flag: bool) -> &'static str {
+    if flag {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+//Accepts the usual spellings of a toggle on the command line
+pub fn parse_on_off(s: &str) -> Result<bool, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "on" | "enable" | "enabled" | "true" | "yes" | "1" => Ok(true),
+        "off" | "disable" | "disabled" | "false" | "no" | "0" => Ok(false),
+        _ => Err(format!("Expected on or off, got '{}'", s)),
+    }
+}
+
+//
+//Per port records: switches answer with one TLV per port, each value starting with the port number.
+//Some firmwares pack every record into a single TLV instead, so values are split into records of a fixed length
+//
+
+pub fn port_records(
+    tlvs: &[TypeLengthValue],
+    cmd: &Cmd,
+    record_len: usize,
+    port_count: u8,
+) -> Result<Vec<(Port, Vec<u8>)>, TLVReadingError> {
+    let mut records: Vec<(Port, Vec<u8>)> = Vec::new();
+    for tlv in tlvs {
+        if !tlv.cmd_equal_to(cmd) {
+            continue;
+        }
+        if tlv.value().is_empty() || tlv.value().len() % record_len != 0 {
+            return Err(TLVReadingError::ArrTooShort(format!(
+                "{:?} records must be {} bytes",
+                cmd, record_len
+            )));
+        }
+        for record in tlv.value().chunks(record_len) {
+            let port = Port::new(record[0])
+                .filter(|port| port.number() <= port_count)
+                .ok_or(TLVReadingError::InvalidType(format!(
+                    "{:?} record for unknown port {}",
+                    cmd, record[0]
+                )))?;
+            records.push((port, record.to_vec()));
+        }
+    }
+    records.sort_by_key(|(port, _)| *port);
+    Ok(records)
+}
+
+//
+//Port speed: the negotiated link reported by the switch and the setting it is configured with
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkSpeed {
+    Down,
+    Half10,
+    Full10,
+    Half100,
+    Full100,
+    Full1000,
+    Unknown(u8),
+}
+
+impl From<u8> for LinkSpeed {
+    fn from(raw: u8) -> Self {
+        match raw {
+            0x00 => LinkSpeed::Down,
+            0x01 => LinkSpeed::Half10,
+            0x02 => LinkSpeed::Full10,
+            0x03 => LinkSpeed::Half100,
+            0x04 => LinkSpeed::Full100,
+            0x05 => LinkSpeed::Full1000,
+            other => LinkSpeed::Unknown(other),
+        }
+    }
+}
+
+impl fmt::Display for LinkSpeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkSpeed::Down => write!(f, "down"),
+            LinkSpeed::Half10 => write!(f, "10M half"),
+            LinkSpeed::Full10 => write!(f, "10M full"),
+            LinkSpeed::Half100 => write!(f, "100M half"),
+            LinkSpeed::Full100 => write!(f, "100M full"),
+            LinkSpeed::Full1000 => write!(f, "1000M full"),
+            LinkSpeed::Unknown(raw) => write!(f, "unknown ({:02x})", raw),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpeedSetting {
+    Disabled,
+    Auto,
+    Half10,
+    Full10,
+    Half100,
+    Full100,
+}
+
+impl SpeedSetting {
+    pub fn to_tlv(self, port: Port) -> TypeLengthValue {
+        TypeLengthValue::from((Cmd::CMD_Port_Speed_Config, vec![port.number(), self as u8]))
+    }
+}
+
+impl TryFrom<u8> for SpeedSetting {
+    type Error = TLVReadingError;
+
+    fn try_from(raw: u8) -> Result<Self, Self::Error> {
+        match raw {
+            0x00 => Ok(SpeedSetting::Disabled),
+            0x01 => Ok(SpeedSetting::Auto),
+            0x02 => Ok(SpeedSetting::Half10),
+            0x03 => Ok(SpeedSetting::Full10),
+            0x04 => Ok(SpeedSetting::Half100),
+            0x05 => Ok(SpeedSetting::Full100),
+            other => Err(TLVReadingError::InvalidType(format!(
+                "Unknown speed setting {:02x}",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for SpeedSetting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpeedSetting::Disabled => write!(f, "disable"),
+            SpeedSetting::Auto => write!(f, "auto"),
+            SpeedSetting::Half10 => write!(f, "10h"),
+            SpeedSetting::Full10 => write!(f, "10f"),
+            SpeedSetting::Half100 => write!(f, "100h"),
+            SpeedSetting::Full100 => write!(f, "100f"),
+        }
+    }
+}
+
+impl FromStr for SpeedSetting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "disable" | "disabled" | "off" => Ok(SpeedSetting::Disabled),
+            "auto" => Ok(SpeedSetting::Auto),
+            "10h" => Ok(SpeedSetting::Half10),
+            "10f" => Ok(SpeedSetting::Full10),
+            "100h" => Ok(SpeedSetting::Half100),
+            "100f" => Ok(SpeedSetting::Full100),
+            _ => Err(format!(
+                "Unknown speed '{}', expected auto, 10h, 10f, 100h, 100f or disable",
+                s
+            )),
+        }
+    }
+}
+
+pub fn flow_control_tlv(port: Port, enabled: bool) -> TypeLengthValue {
+    TypeLengthValue::from((
+        Cmd::CMD_Port_Flow_Control,
+        vec![port.number(), enabled as u8],
+    ))
+}
+
+//One row of the ports table, built from the per port TLVs
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortInfo {
+    pub port: Port,
+    pub link: LinkSpeed,
+    pub speed: Option<SpeedSetting>,
+    pub flow_control: Option<bool>,
+}
+
+impl PortInfo {
+    pub const CMDS: [Cmd; 3] = [
+        Cmd::CMD_Port_Status,
+        Cmd::CMD_Port_Speed_Config,
+        Cmd::CMD_Port_Flow_Control,
+    ];
+
+    pub fn from_tlvs(
+        tlvs: &[TypeLengthValue],
+        port_count: u8,
+    ) -> Result<Vec<PortInfo>, TLVReadingError> {
+        let mut ports: Vec<PortInfo> = (1..=port_count)
+            .filter_map(Port::new)
+            .map(|port| PortInfo {
+                port,
+                link: LinkSpeed::Down,
+                speed: None,
+                flow_control: None,
+            })
+            .collect();
+
+        for (port, record) in port_records(tlvs, &Cmd::CMD_Port_Status, 3, port_count)? {
+            ports[port.number() as usize - 1].link = LinkSpeed::from(record[1]);
+        }
+        for (port, record) in port_records(tlvs, &Cmd::CMD_Port_Speed_Config, 2, port_count)? {
+            ports[port.number() as usize - 1].speed = Some(SpeedSetting::try_from(record[1])?);
+        }
+        for (port, record) in port_records(tlvs, &Cmd::CMD_Port_Flow_Control, 2, port_count)? {
+            ports[port.number() as usize - 1].flow_control = Some(record[1] != 0);
+        }
+        Ok(ports)
+    }
+}
+
+//
+//Cable test: the result holds a status and the distance to the fault in metres for each wire pair. Older
+//firmware answers with a single record that covers the whole cable
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CableStatus {
+    Ok,
+    NoCable,
+    Open,
+    Short,
+    Unknown(u32),
+}
+
+impl From<u32> for CableStatus {
+    fn from(raw: u32) -> Self {
+        match raw {
+            0 => CableStatus::Ok,
+            1 => CableStatus::NoCable,
+            2 => CableStatus::Open,
+            3 => CableStatus::Short,
+            other => CableStatus::Unknown(other),
+        }
+    }
+}
+
+impl fmt::Display for CableStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CableStatus::Ok => write!(f, "OK"),
+            CableStatus::NoCable => write!(f, "no cable"),
+            CableStatus::Open => write!(f, "open"),
+            CableStatus::Short => write!(f, "short"),
+            CableStatus::Unknown(raw) => write!(f, "unknown ({:08x})", raw),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PairResult {
+    pub status: CableStatus,
+    pub fault_distance: u32,
+}
+
+impl fmt::Display for PairResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.status {
+            CableStatus::Ok | CableStatus::NoCable => write!(f, "{}", self.status),
+            _ => write!(f, "{}, fault at {} m", self.status, self.fault_distance),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CableTestResult {
+    pub port: Port,
+    pub pairs: Vec<PairResult>,
+}
+
+impl CableTestResult {
+    pub fn request_tlv(port: Port) -> TypeLengthValue {
+        TypeLengthValue::from((Cmd::CMD_Cable_Test_Request, vec![port.number(), 0x01]))
+    }
+
+    pub fn query_tlv(port: Port) -> TypeLengthValue {
+        TypeLengthValue::from((Cmd::CMD_Cable_Test_Result, vec![port.number()]))
+    }
+}
+
+impl TryFrom<TypeLengthValue> for CableTestResult {
+    type Error = TLVReadingError;
+
+    fn try_from(tlv: TypeLengthValue) -> Result<Self, Self::Error> {
+        expect_cmd(&tlv, &Cmd::CMD_Cable_Test_Result, 9)?;
+        let value = tlv.value();
+        if !(value.len() - 1).is_multiple_of(8) {
+            return Err(TLVReadingError::InvalidType(format!(
+                "Cable test result of {} bytes does not hold whole pair records",
+                value.len()
+            )));
+        }
+        Ok(CableTestResult {
+            port: Port::new(value[0]).ok_or(TLVReadingError::InvalidType(String::from(
+                "Cable test result for port 0",
+            )))?,
+            pairs: value[1..]
+                .chunks_exact(8)
+                .map(|record| PairResult {
+                    status: CableStatus::from(u32::from_be_bytes(record[0..4].try_into().unwrap())),
+                    fault_distance: u32::from_be_bytes(record[4..8].try_into().unwrap()),
+                })
+                .collect(),
+        })
+    }
+}
+
+impl fmt::Display for CableTestResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Port {}:", self.port)?;
+        if let [cable] = self.pairs.as_slice() {
+            return write!(f, " {}", cable);
+        }
+        //Pairs are named A to D like on the switch's web interface
+        for (pair, result) in (b'A'..).zip(&self.pairs) {
+            let separator = if pair == b'A' { "" } else { "," };
+            write!(f, "{} pair {} {}", separator, pair as char, result)?;
+        }
+        Ok(())
+    }
+}
+
+//
+//Firmware: dual image switches report a version for each image and which one is running
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirmwareBank {
+    Image1 = 1,
+    Image2 = 2,
+}
+
+impl FirmwareBank {
+    pub fn index(self) -> usize {
+        self as usize - 1
+    }
+
+    pub fn to_tlv(self) -> TypeLengthValue {
+        TypeLengthValue::from((Cmd::CMD_FW_Active, vec![self as u8]))
+    }
+}
+
+impl TryFrom<u8> for FirmwareBank {
+    type Error = TLVReadingError;
+
+    fn try_from(raw: u8) -> Result<Self, Self::Error> {
+        match raw {
+            1 => Ok(FirmwareBank::Image1),
+            2 => Ok(FirmwareBank::Image2),
+            other => Err(TLVReadingError::InvalidType(format!(
+                "Unknown firmware image {:02x}",
+                other
+            ))),
+        }
+    }
+}
+
+impl TryFrom<TypeLengthValue> for FirmwareBank {
+    type Error = TLVReadingError;
+
+    fn try_from(tlv: TypeLengthValue) -> Result<Self, Self::Error> {
+        expect_cmd(&tlv, &Cmd::CMD_FW_Active, 1)?;
+        FirmwareBank::try_from(tlv.value()[0])
+    }
+}
+
+impl FromStr for FirmwareBank {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "1" => Ok(FirmwareBank::Image1),
+            "2" => Ok(FirmwareBank::Image2),
+            _ => Err(format!("Expected firmware image 1 or 2, got '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for FirmwareBank {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", *self as u8)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FirmwareInfo {
+    pub images: [Option<String>; 2],
+    pub active: FirmwareBank,
+}
+
+impl FirmwareInfo {
+    pub const CMDS: [Cmd; 3] = [
+        Cmd::CMD_FW_Version,
+        Cmd::CMD_FW_Version_2,
+        Cmd::CMD_FW_Active,
+    ];
+
+    //Single image switches leave out the second version and the active flag
+    pub fn from_tlvs(
+        version: TypeLengthValue,
+        version_2: Option<TypeLengthValue>,
+        active: Option<TypeLengthValue>,
+    ) -> Result<FirmwareInfo, TLVReadingError> {
+        expect_cmd(&version, &Cmd::CMD_FW_Version, 0)?;
+        let active = match active {
+            Some(tlv) if !tlv.value().is_empty() => FirmwareBank::try_from(tlv)?,
+            _ => FirmwareBank::Image1,
+        };
+        Ok(FirmwareInfo {
+            images: [version_string(version), version_2.and_then(version_string)],
+            active,
+        })
+    }
+
+    pub fn version(&self, bank: FirmwareBank) -> Option<&String> {
+        self.images[bank.index()].as_ref()
+    }
+
+    pub fn active_version(&self) -> Option<&String> {
+        self.version(self.active)
+    }
+}
+
+fn version_string(tlv: TypeLengthValue) -> Option<String> {
+    let version: String = tlv.try_into().ok()?;
+    let version = version.trim_end_matches('\0').trim().to_string();
+    if version.is_empty() {
+        return None;
+    }
+    Some(version)
+}
+
+impl fmt::Display for FirmwareInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for bank in [FirmwareBank::Image1, FirmwareBank::Image2] {
+            if bank != FirmwareBank::Image1 {
+                writeln!(f)?;
+            }
+            write!(
+                f,
+                "Image {}: {}",
+                bank,
+                self.version(bank).map_or("-", |version| version.as_str())
+            )?;
+            if bank == self.active {
+                write!(f, " (active)")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ports(list: &str) -> PortSet {
+        list.parse().unwrap()
+    }
+
+    #[test]
+    fn mirror_decodes_destination_and_sources() {
+        let tlv = TypeLengthValue::from((Cmd::CMD_Port_Mirroring, vec![0x05, 0x00, 0xc1]));
+        let mirror = Mirror::try_from(tlv).unwrap();
+        assert_eq!(mirror.destination, Port::new(5));
+        assert_eq!(mirror.sources, ports("1,2,8"));
+        assert_eq!(mirror.to_string(), "ports 1,2,8 -> port 5");
+    }
+
+    #[test]
+    fn mirror_decodes_disabled_and_rejects_short_values() {
+        let tlv = TypeLengthValue::from((Cmd::CMD_Port_Mirroring, vec![0x00, 0x00, 0x00]));
+        let mirror = Mirror::try_from(tlv).unwrap();
+        assert_eq!(mirror, Mirror::default());
+        assert_eq!(mirror.to_string(), "disabled");
+
+        let short = TypeLengthValue::from((Cmd::CMD_Port_Mirroring, vec![0x05, 0x00]));
+        assert!(Mirror::try_from(short).is_err());
+        let wrong_cmd = TypeLengthValue::from((Cmd::CMD_Name, vec![0x05, 0x00, 0xc1]));
+        assert!(Mirror::try_from(wrong_cmd).is_err());
+    }
+
+    #[test]
+    fn mirror_encodes_a_bitmap_sized_for_the_switch() {
+        let mirror = Mirror {
+            destination: Port::new(24),
+            sources: ports("1,9-10,17"),
+        };
+        let tlv = mirror.to_tlv(24);
+        assert_eq!(tlv.value(), &[24, 0x00, 0x80, 0xc0, 0x80]);
+        assert_eq!(Mirror::try_from(tlv).unwrap(), mirror);
+
+        let eight_ports = Mirror {
+            destination: Port::new(8),
+            sources: ports("1-3"),
+        };
+        assert_eq!(eight_ports.to_tlv(8).value(), &[8, 0x00, 0xe0]);
+    }
+
+    #[test]
+    fn disabled_mirror_sends_an_empty_bitmap() {
+        //Sources left over from a previous session are not sent once mirroring is off
+        let mirror = Mirror {
+            destination: None,
+            sources: ports("1,2"),
+        };
+        assert_eq!(mirror.to_tlv(16).value(), &[0x00, 0x00, 0x00, 0x00]);
+    }
+
+    fn igmp_tlvs(
+        snooping: Vec<u8>,
+        validate: u8,
+        block: u8,
+        router: Vec<u8>,
+    ) -> [TypeLengthValue; 4] {
+        [
+            TypeLengthValue::from((Cmd::CMD_IGMP_Snooping, snooping)),
+            TypeLengthValue::from((Cmd::CMD_IGMP_Validate_IP_Header, vec![validate])),
+            TypeLengthValue::from((Cmd::CMD_IGMP_Block_Unknown_Multicast, vec![block])),
+            TypeLengthValue::from((Cmd::CMD_IGMP_Router_Ports, router)),
+        ]
+    }
+
+    #[test]
+    fn igmp_decodes_from_its_four_tlvs_and_encodes_back() {
+        let [snooping, validate, block, router] =
+            igmp_tlvs(vec![0x00, 0x01, 0x00, 0x0a], 0x01, 0x00, vec![0x90]);
+        let igmp = IgmpSnooping::from_tlvs(snooping, validate, block, router).unwrap();
+        assert_eq!(
+            igmp,
+            IgmpSnooping {
+                enabled: true,
+                vlan: 10,
+                validate_ip_header: true,
+                block_unknown_multicast: false,
+                router_ports: ports("1,4"),
+            }
+        );
+        assert_eq!(
+            igmp.to_tlvs(8).to_vec(),
+            igmp_tlvs(vec![0x00, 0x01, 0x00, 0x0a], 0x01, 0x00, vec![0x90]).to_vec()
+        );
+    }
+
+    #[test]
+    fn igmp_rejects_tlvs_in_the_wrong_order_or_too_short() {
+        let [snooping, validate, block, router] =
+            igmp_tlvs(vec![0x00, 0x01, 0x00, 0x0a], 0x01, 0x00, vec![0x90]);
+        assert!(IgmpSnooping::from_tlvs(
+            validate.clone(),
+            snooping.clone(),
+            block.clone(),
+            router.clone()
+        )
+        .is_err());
+
+        let [short, ..] = igmp_tlvs(vec![0x00, 0x01], 0x00, 0x00, vec![0x00]);
+        assert!(IgmpSnooping::from_tlvs(short, validate, block, router).is_err());
+    }
+
+    #[test]
+    fn toggles_accept_the_usual_spellings() {
+        for on in ["on", "Enable", "TRUE", "yes", "1"] {
+            assert_eq!(parse_on_off(on), Ok(true));
+        }
+        for off in ["off", "disabled", "false", "No", "0"] {
+            assert_eq!(parse_on_off(off), Ok(false));
+        }
+        assert!(parse_on_off("maybe").is_err());
+        assert_eq!(on_off(true), "on");
+    }
+
+    #[test]
+    fn speed_settings_round_trip_through_text_and_tlvs() {
+        for raw in 0x00..=0x05 {
+            let speed = SpeedSetting::try_from(raw).unwrap();
+            assert_eq!(speed.to_string().parse::<SpeedSetting>(), Ok(speed));
+            assert_eq!(speed.to_tlv(Port::new(3).unwrap()).value(), &[0x03, raw]);
+        }
+        assert_eq!("disabled".parse(), Ok(SpeedSetting::Disabled));
+        assert_eq!("100F".parse(), Ok(SpeedSetting::Full100));
+        assert!("1000f".parse::<SpeedSetting>().is_err());
+        assert!(SpeedSetting::try_from(0x06).is_err());
+    }
+
+    #[test]
+    fn port_info_reads_one_tlv_per_port_or_records_packed_into_one() {
+        let per_port = vec![
+            TypeLengthValue::from((Cmd::CMD_Port_Status, vec![0x02, 0x05, 0x01])),
+            TypeLengthValue::from((Cmd::CMD_Port_Status, vec![0x01, 0x00, 0x01])),
+            TypeLengthValue::from((Cmd::CMD_Port_Speed_Config, vec![0x02, 0x00])),
+        ];
+        let packed = vec![
+            TypeLengthValue::from((
+                Cmd::CMD_Port_Status,
+                vec![0x01, 0x00, 0x01, 0x02, 0x05, 0x01],
+            )),
+            TypeLengthValue::from((Cmd::CMD_Port_Speed_Config, vec![0x02, 0x00])),
+        ];
+        for tlvs in [per_port, packed] {
+            let ports = PortInfo::from_tlvs(&tlvs, 3).unwrap();
+            assert_eq!(ports.len(), 3);
+            assert_eq!(ports[0].link, LinkSpeed::Down);
+            assert_eq!(ports[1].link, LinkSpeed::Full1000);
+            assert_eq!(ports[1].speed, Some(SpeedSetting::Disabled));
+            //Ports the switch left out are shown as down with no setting
+            assert_eq!(ports[2].speed, None);
+        }
+    }
+
+    #[test]
+    fn port_records_reject_unknown_ports_and_ragged_values() {
+        let beyond = [TypeLengthValue::from((
+            Cmd::CMD_Port_Status,
+            vec![0x09, 0x05, 0x01],
+        ))];
+        assert!(port_records(&beyond, &Cmd::CMD_Port_Status, 3, 8).is_err());
+        let ragged = [TypeLengthValue::from((
+            Cmd::CMD_Port_Status,
+            vec![0x01, 0x05],
+        ))];
+        assert!(port_records(&ragged, &Cmd::CMD_Port_Status, 3, 8).is_err());
+        assert_eq!(LinkSpeed::from(0x07).to_string(), "unknown (07)");
+    }
+
+    #[test]
+    fn flow_control_is_read_and_written_per_port() {
+        let port = Port::new(2).unwrap();
+        assert_eq!(flow_control_tlv(port, true).value(), &[0x02, 0x01]);
+        assert_eq!(flow_control_tlv(port, false).value(), &[0x02, 0x00]);
+
+        let tlvs = [
+            TypeLengthValue::from((Cmd::CMD_Port_Flow_Control, vec![0x01, 0x00])),
+            TypeLengthValue::from((Cmd::CMD_Port_Flow_Control, vec![0x02, 0x01])),
+        ];
+        let ports = PortInfo::from_tlvs(&tlvs, 3).unwrap();
+        assert_eq!(ports[0].flow_control, Some(false));
+        assert_eq!(ports[1].flow_control, Some(true));
+        assert_eq!(ports[2].flow_control, None);
+    }
+
+    #[test]
+    fn cable_test_results_decode_status_and_fault_distance() {
+        let port = Port::new(3).unwrap();
+        assert_eq!(CableTestResult::request_tlv(port).value(), &[0x03, 0x01]);
+        assert_eq!(CableTestResult::query_tlv(port).value(), &[0x03]);
+
+        let open = TypeLengthValue::from((
+            Cmd::CMD_Cable_Test_Result,
+            vec![0x03, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x0c],
+        ));
+        let result = CableTestResult::try_from(open).unwrap();
+        assert_eq!(
+            result.pairs,
+            [PairResult {
+                status: CableStatus::Open,
+                fault_distance: 12,
+            }]
+        );
+        assert_eq!(result.to_string(), "Port 3: open, fault at 12 m");
+
+        let ok = TypeLengthValue::from((
+            Cmd::CMD_Cable_Test_Result,
+            vec![0x01, 0, 0, 0, 0, 0, 0, 0, 0],
+        ));
+        assert_eq!(
+            CableTestResult::try_from(ok).unwrap().to_string(),
+            "Port 1: OK"
+        );
+    }
+
+    #[test]
+    fn cable_test_results_need_a_port_and_both_fields() {
+        let no_port = TypeLengthValue::from((Cmd::CMD_Cable_Test_Result, vec![0x00; 9]));
+        assert!(CableTestResult::try_from(no_port).is_err());
+        //Queries for a port the test hasn't finished on come back with only the port
+        let pending = TypeLengthValue::from((Cmd::CMD_Cable_Test_Result, vec![0x03]));
+        assert!(CableTestResult::try_from(pending).is_err());
+        assert_eq!(CableStatus::from(9).to_string(), "unknown (00000009)");
+        let torn = TypeLengthValue::from((Cmd::CMD_Cable_Test_Result, vec![0x03; 13]));
+        assert!(CableTestResult::try_from(torn).is_err());
+    }
+
+    #[test]
+    fn cable_test_results_name_each_pair() {
+        let mut value = vec![0x02];
+        for (status, distance) in [(0u32, 0u32), (2, 3), (0, 0), (1, 7)] {
+            value.extend(status.to_be_bytes());
+            value.extend(distance.to_be_bytes());
+        }
+        let tlv = TypeLengthValue::from((Cmd::CMD_Cable_Test_Result, value));
+        let result = CableTestResult::try_from(tlv).unwrap();
+        assert_eq!(result.pairs.len(), 4);
+        assert_eq!(
+            result.to_string(),
+            "Port 2: pair A OK, pair B open, fault at 3 m, pair C OK, pair D no cable"
+        );
+    }
+
+    fn firmware_tlvs(
+        version: &str,
+        version_2: &str,
+        active: Vec<u8>,
+    ) -> (
+        TypeLengthValue,
+        Option<TypeLengthValue>,
+        Option<TypeLengthValue>,
+    ) {
+        (
+            TypeLengthValue::from((Cmd::CMD_FW_Version, version.as_bytes().to_vec())),
+            Some(TypeLengthValue::from((
+                Cmd::CMD_FW_Version_2,
+                version_2.as_bytes().to_vec(),
+            ))),
+            Some(TypeLengthValue::from((Cmd::CMD_FW_Active, active))),
+        )
+    }
+
+    #[test]
+    fn firmware_info_reports_both_images_and_the_running_one() {
+        let (version, version_2, active) = firmware_tlvs("1.00.10\0\0", "1.00.11", vec![0x02]);
+        let firmware = FirmwareInfo::from_tlvs(version, version_2, active).unwrap();
+        assert_eq!(
+            firmware.images,
+            [Some(String::from("1.00.10")), Some(String::from("1.00.11"))]
+        );
+        assert_eq!(
+            firmware.active_version().map(String::as_str),
+            Some("1.00.11")
+        );
+        assert_eq!(
+            firmware.to_string(),
+            "Image 1: 1.00.10\nImage 2: 1.00.11 (active)"
+        );
+    }
+
+    #[test]
+    fn single_image_firmware_runs_the_first_image() {
+        let (version, _, _) = firmware_tlvs("V2.06.03", "", Vec::new());
+        let firmware = FirmwareInfo::from_tlvs(version, None, None).unwrap();
+        assert_eq!(firmware.images[1], None);
+        assert_eq!(
+            firmware.active_version().map(String::as_str),
+            Some("V2.06.03")
+        );
+        assert_eq!(
+            firmware.to_string(),
+            "Image 1: V2.06.03 (active)\nImage 2: -"
+        );
+    }
+
+    #[test]
+    fn firmware_banks_are_numbered_from_one() {
+        assert_eq!("2".parse::<FirmwareBank>(), Ok(FirmwareBank::Image2));
+        assert!("3".parse::<FirmwareBank>().is_err());
+        assert_eq!(FirmwareBank::Image1.index(), 0);
+        assert_eq!(FirmwareBank::Image2.to_string(), "2");
+
+        let tlv = FirmwareBank::Image2.to_tlv();
+        assert_eq!(tlv.value(), &[0x02]);
+        assert_eq!(FirmwareBank::try_from(tlv).ok(), Some(FirmwareBank::Image2));
+        assert!(FirmwareBank::try_from(0x00).is_err());
+    }
+
+    #[test]
+    fn passwords_are_xored_with_the_key_as_before() {
+        assert_eq!(
+            encode_password("password"),
+            vec![0x3e, 0x15, 0x14, 0x01, 0x24, 0x02, 0x13, 0x16]
+        );
+        //Characters past the end of the key are not sent, as in the original client
+        let long = "a".repeat(25);
+        assert_eq!(encode_password(&long).len(), PASSWORD_KEY.len());
+    }
+}
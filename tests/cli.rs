@@ -0,0 +1,1034 @@
+//The tool always talks to port 63322, so every test serves its switches on a loopback address of its own.
+//Linux routes all of 127.0.0.0/8 to the loopback interface, other platforms only have 127.0.0.1
+#![cfg(target_os = "linux")]
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use pputl::client::BlockingClient;
+use pputl::cmds::Cmd;
+use pputl::dispatch::{BindMode, PortPair};
+use pputl::emulator::{EmulatedSwitch, Emulator, STATUS_INVALID_VALUE};
+
+const MAC_1: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x01, 0x01];
+const MAC_2: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x01, 0x02];
+
+fn emulator(ip: Ipv4Addr, switches: Vec<EmulatedSwitch>) -> Emulator {
+    Emulator::bind(SocketAddr::new(ip.into(), PortPair::V1.switch), switches).unwrap()
+}
+
+fn pputl(ip: Ipv4Addr, password: &str, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_pputl"))
+        .args(["--ephemeral-port", "--probe", &ip.to_string()])
+        .args(args)
+        .env("PPUTL_PASSWORD", password)
+        .output()
+        .unwrap()
+}
+
+//Commands for a single switch take it from the environment instead of discovering it
+fn pputl_on_mac_1(ip: Ipv4Addr, args: &[&str]) -> Output {
+    pputl_on_model(ip, "GS105E", args)
+}
+
+fn pputl_on_model(ip: Ipv4Addr, model: &str, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_pputl"))
+        .args(["--ephemeral-port", "--probe", &ip.to_string()])
+        .args(args)
+        .env("PPUTL_PASSWORD", "password")
+        .env("TESTSWITCH_NAME", "desk")
+        .env("TESTSWITCH_MODEL", model)
+        .env("TESTSWITCH_LOCATION", "")
+        .env(
+            "TESTSWITCH_IPV4_REPORTED",
+            ip.octets().map(|octet| octet.to_string()).join(","),
+        )
+        .env("TESTSWITCH_IPV4", format!("{}:63322", ip))
+        .env(
+            "TESTSWITCH_MAC",
+            MAC_1.map(|byte| byte.to_string()).join(","),
+        )
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn info_on_every_switch() {
+    let ip = Ipv4Addr::new(127, 0, 0, 11);
+    let _emulator = emulator(
+        ip,
+        vec![
+            EmulatedSwitch::new("GS105E", MAC_1, 5).with_name("desk"),
+            EmulatedSwitch::new("GS108Ev3", MAC_2, 8).with_name("rack"),
+        ],
+    );
+
+    let output = pputl(ip, "password", &["--all", "info"]);
+    let stdout = stdout(&output);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("desk (GS105E) 02:00:00:00:01:01"));
+    assert!(stdout.contains("Ports: 8"));
+    assert!(stdout.contains("Summary: 2 succeeded, 0 failed"));
+}
+
+#[test]
+fn filter_limits_the_switches_changed() {
+    let ip = Ipv4Addr::new(127, 0, 0, 12);
+    let emulator = emulator(
+        ip,
+        vec![
+            EmulatedSwitch::new("GS105E", MAC_1, 5).with_name("desk"),
+            EmulatedSwitch::new("GS108Ev3", MAC_2, 8).with_name("rack"),
+        ],
+    );
+
+    let output = pputl(
+        ip,
+        "password",
+        &["--filter", "model=GS108*", "loop-detection", "on"],
+    );
+    assert!(output.status.success(), "{}", stdout(&output));
+
+    let switches = emulator.switches();
+    assert_eq!(switches[0].get(&Cmd::CMD_Loop_Detection).unwrap()[0], [0]);
+    assert_eq!(switches[1].get(&Cmd::CMD_Loop_Detection).unwrap()[0], [1]);
+}
+
+#[test]
+fn port_settings_round_trip() {
+    let ip = Ipv4Addr::new(127, 0, 0, 13);
+    let _emulator = emulator(ip, vec![EmulatedSwitch::new("GS105E", MAC_1, 5)]);
+
+    let output = pputl(
+        ip,
+        "password",
+        &[
+            "--all",
+            "port",
+            "set",
+            "2",
+            "--speed",
+            "100f",
+            "--flow-control",
+            "on",
+        ],
+    );
+    assert!(output.status.success(), "{}", stdout(&output));
+
+    let output = pputl(ip, "password", &["--all", "ports"]);
+    let stdout = stdout(&output);
+    assert!(output.status.success(), "{}", stdout);
+    let port_2 = stdout
+        .lines()
+        .find(|line| line.starts_with("2 "))
+        .expect(&stdout);
+    assert!(port_2.contains("100f"), "{}", port_2);
+    assert!(port_2.contains("on"), "{}", port_2);
+}
+
+#[test]
+fn wrong_password_fails_with_a_summary() {
+    let ip = Ipv4Addr::new(127, 0, 0, 14);
+    let emulator = emulator(
+        ip,
+        vec![EmulatedSwitch::new("GS105E", MAC_1, 5).with_password("secret")],
+    );
+
+    let output = pputl(ip, "password", &["--all", "loop-detection", "on"]);
+    let stdout = stdout(&output);
+    assert!(!output.status.success());
+    assert!(
+        stdout.contains("Summary: 0 succeeded, 1 failed"),
+        "{}",
+        stdout
+    );
+    assert!(emulator.switches()[0].writes().is_empty());
+}
+
+#[test]
+fn emulator_binary_serves_a_fixture_fleet() {
+    let ip = Ipv4Addr::new(127, 0, 0, 15);
+    let fixture = std::env::temp_dir().join(format!("pputl-fleet-{}.toml", std::process::id()));
+    std::fs::write(
+        &fixture,
+        r#"
+        [[switch]]
+        name = "edge-{n}"
+        count = 20
+        mac = "02:00:00:00:20:01"
+        model = "GS105Ev2"
+        ports = 5
+        "#,
+    )
+    .unwrap();
+    let mut emulator = Command::new(env!("CARGO_BIN_EXE_pputl-emulator"))
+        .arg(&fixture)
+        .args([
+            "--listen",
+            &SocketAddr::new(ip.into(), PortPair::V1.switch).to_string(),
+        ])
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(500));
+
+    let output = pputl(ip, "password", &["--filter", "name=edge-*", "info"]);
+    emulator.kill().unwrap();
+    emulator.wait().unwrap();
+    std::fs::remove_file(&fixture).unwrap();
+
+    let stdout = stdout(&output);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(
+        stdout.contains("edge-20 (GS105Ev2) 02:00:00:00:20:14"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("Summary: 20 succeeded, 0 failed"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn recorded_run_replays_with_the_same_output() {
+    let live_ip = Ipv4Addr::new(127, 0, 0, 16);
+    let replay_ip = Ipv4Addr::new(127, 0, 0, 17);
+    let dir = std::env::temp_dir().join(format!("pputl-record-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let recorded = {
+        let _emulator = emulator(
+            live_ip,
+            vec![EmulatedSwitch::new("GS105E", MAC_1, 5).with_name("desk")],
+        );
+        pputl(
+            live_ip,
+            "password",
+            &["--record", dir.to_str().unwrap(), "--all", "ports"],
+        )
+    };
+    assert!(recorded.status.success(), "{}", stdout(&recorded));
+
+    let mut replay = Command::new(env!("CARGO_BIN_EXE_pputl-emulator"))
+        .args(["--replay", dir.to_str().unwrap()])
+        .args([
+            "--listen",
+            &SocketAddr::new(replay_ip.into(), PortPair::V1.switch).to_string(),
+        ])
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(500));
+    let replayed = pputl(replay_ip, "password", &["--all", "ports"]);
+    replay.kill().unwrap();
+    replay.wait().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(replayed.status.success(), "{}", stdout(&replayed));
+    //The replayed switch answers from another address, everything else matches
+    assert_eq!(
+        stdout(&replayed).replace(&replay_ip.to_string(), &live_ip.to_string()),
+        stdout(&recorded)
+    );
+}
+
+#[test]
+fn probe_scans_compare_models() {
+    let ip = Ipv4Addr::new(127, 0, 0, 18);
+    let dir = std::env::temp_dir().join(format!("pputl-probe-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut rack = EmulatedSwitch::new("GS108Ev3", MAC_2, 8).with_name("rack");
+    rack.set(Cmd::CMD_FW_Version, vec![b"2.06.10".to_vec()]);
+    rack.set(Cmd::CMD_7800, vec![vec![0x01]]);
+    let _emulator = emulator(
+        ip,
+        vec![
+            EmulatedSwitch::new("GS105E", MAC_1, 5)
+                .with_name("desk")
+                .with_failure(Cmd::CMD_7400, STATUS_INVALID_VALUE),
+            rack,
+        ],
+    );
+
+    let output = pputl(
+        ip,
+        "password",
+        &[
+            "--all",
+            "probe",
+            "scan",
+            "--range",
+            "0000-7fff",
+            "--batch",
+            "64",
+            "-o",
+            dir.to_str().unwrap(),
+        ],
+    );
+    let scanned = stdout(&output);
+    assert!(output.status.success(), "{}", scanned);
+    assert!(
+        scanned.contains("1 rejected, 0 silent in 0000-7fff"),
+        "{}",
+        scanned
+    );
+
+    let desk = dir.join("02-00-00-00-01-01.toml");
+    let rack = dir.join("02-00-00-00-01-02.toml");
+    let output = Command::new(env!("CARGO_BIN_EXE_pputl"))
+        .args([
+            "probe",
+            "diff",
+            desk.to_str().unwrap(),
+            rack.to_str().unwrap(),
+        ])
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let diff = stdout(&output);
+    assert!(!output.status.success(), "{}", diff);
+    let row = |id: &str| {
+        diff.lines()
+            .find(|line| line.starts_with(id))
+            .unwrap_or_else(|| panic!("no {} in {}", id, diff))
+            .split_whitespace()
+            .skip(2)
+            .collect::<Vec<&str>>()
+            .join(" ")
+    };
+    assert!(diff.contains("GS105E 1.00.10"), "{}", diff);
+    assert!(diff.contains("GS108Ev3 2.06.10"), "{}", diff);
+    assert_eq!(row("7400"), "rejected -");
+    assert_eq!(row("7800"), "- 1");
+    assert_eq!(row("0c00"), "3 x5 3 x8");
+    assert!(!diff.contains("6000 "), "{}", diff);
+}
+
+#[test]
+fn queries_leave_out_tlvs_the_model_lacks() {
+    let ip = Ipv4Addr::new(127, 0, 0, 19);
+    //Both reject the second firmware image, only the unknown model gets asked for it
+    let _emulator = emulator(
+        ip,
+        vec![
+            EmulatedSwitch::new("GS105E", MAC_1, 5)
+                .with_name("desk")
+                .with_failure(Cmd::CMD_FW_Version_2, STATUS_INVALID_VALUE),
+            EmulatedSwitch::new("XS000", MAC_2, 5)
+                .with_name("lab")
+                .with_failure(Cmd::CMD_FW_Version_2, STATUS_INVALID_VALUE),
+        ],
+    );
+
+    let output = pputl(ip, "password", &["--all", "info"]);
+    let stdout = stdout(&output);
+    assert!(!output.status.success());
+    assert!(
+        stdout.contains("Summary: 1 succeeded, 1 failed"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("desk (GS105E)"), "{}", stdout);
+    assert!(stdout.contains("error   lab (XS000)"), "{}", stdout);
+}
+
+#[test]
+fn hashed_password_models_are_read_only() {
+    let ip = Ipv4Addr::new(127, 0, 0, 20);
+    let emulator = emulator(ip, vec![EmulatedSwitch::new("GS308E", MAC_1, 8)]);
+
+    let output = pputl(ip, "password", &["--all", "ports"]);
+    assert!(output.status.success(), "{}", stdout(&output));
+
+    let output = pputl(ip, "password", &["--all", "loop-detection", "on"]);
+    let stdout = stdout(&output);
+    assert!(!output.status.success());
+    assert!(stdout.contains("expects a hashed password"), "{}", stdout);
+    assert!(emulator.switches()[0].writes().is_empty());
+}
+
+#[test]
+fn igmp_changes_with_snooping_off_skip_the_vlan_check() {
+    let ip = Ipv4Addr::new(127, 0, 0, 21);
+    let mut switch = EmulatedSwitch::new("GS105E", MAC_1, 5);
+    switch.set(Cmd::CMD_IGMP_Snooping, vec![vec![0x00, 0x00, 0x00, 0x00]]);
+    let emulator = emulator(ip, vec![switch]);
+
+    let output = pputl(
+        ip,
+        "password",
+        &["--all", "igmp", "set", "--block-unknown-multicast", "on"],
+    );
+    assert!(output.status.success(), "{}", stdout(&output));
+    assert_eq!(
+        emulator.switches()[0]
+            .get(&Cmd::CMD_IGMP_Block_Unknown_Multicast)
+            .unwrap()[0],
+        [1]
+    );
+
+    let output = pputl(ip, "password", &["--all", "igmp", "set", "--enabled", "on"]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains("VLAN 0 is out of range"));
+}
+
+#[test]
+fn loop_detection_check_fails_for_unchecked_switches() {
+    let ip = Ipv4Addr::new(127, 0, 0, 22);
+    let mut desk = EmulatedSwitch::new("GS105E", MAC_1, 5).with_name("desk");
+    desk.set(Cmd::CMD_Loop_Detection, vec![vec![0x01]]);
+    let _emulator = emulator(
+        ip,
+        vec![
+            desk,
+            EmulatedSwitch::new("GS108Ev3", MAC_2, 8)
+                .with_name("rack")
+                .with_failure(Cmd::CMD_Loop_Detection, STATUS_INVALID_VALUE),
+        ],
+    );
+
+    let output = pputl(ip, "password", &["loop-detection", "status", "--all"]);
+    let stdout = stdout(&output);
+    assert!(!output.status.success(), "{}", stdout);
+    assert!(stdout.contains("on      desk (GS105E)"), "{}", stdout);
+    assert!(stdout.contains("error   rack (GS108Ev3)"), "{}", stdout);
+
+    //Nothing answers on this address
+    let output = pputl(
+        Ipv4Addr::new(127, 0, 0, 23),
+        "password",
+        &["loop-detection", "status", "--all"],
+    );
+    assert!(!output.status.success());
+}
+
+#[test]
+fn cable_test_reports_pairs_and_keeps_going() {
+    let ip = Ipv4Addr::new(127, 0, 0, 24);
+    let mut switch = EmulatedSwitch::new("GS105E", MAC_1, 5);
+    //Port 2 answers with a broken record, port 3 has pair B open 12 m away
+    let mut open = vec![3];
+    for pair in 0..4u32 {
+        let (status, distance) = if pair == 1 { (2u32, 12u32) } else { (0, 0) };
+        open.extend(status.to_be_bytes());
+        open.extend(distance.to_be_bytes());
+    }
+    switch.set(Cmd::CMD_Cable_Test_Result, vec![vec![2; 12], open]);
+    let _emulator = emulator(ip, vec![switch]);
+
+    let output = pputl_on_mac_1(ip, &["cable-test", "--all"]);
+    let stdout = stdout(&output);
+    assert!(!output.status.success(), "{}", stdout);
+    assert!(stdout.contains("Port 2: error"), "{}", stdout);
+    assert!(
+        stdout.contains("Port 3: pair A OK, pair B open, fault at 12 m, pair C OK, pair D OK"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("Port 5: pair A OK"), "{}", stdout);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Cable test failed on 1 of 5 ports"));
+}
+
+#[test]
+fn reboot_waits_for_a_slow_switch_to_go_down_and_return() {
+    let ip = Ipv4Addr::new(127, 0, 0, 25);
+    let emulator = emulator(
+        ip,
+        vec![EmulatedSwitch::new("GS105E", MAC_1, 5)
+            .with_name("desk")
+            .with_restart(Duration::from_millis(1500), Duration::from_secs(2))],
+    );
+
+    let started = Instant::now();
+    let output = pputl(ip, "password", &["reboot", "desk"]);
+    let stdout = stdout(&output);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("desk is back after"), "{}", stdout);
+    //Still answering during the delay doesn't count as being back
+    assert!(started.elapsed() >= Duration::from_millis(3500));
+    assert!(emulator.switches()[0]
+        .writes()
+        .iter()
+        .any(|tlv| tlv.cmd_equal_to(&Cmd::CMD_Reboot)));
+}
+
+#[test]
+fn reboot_notices_a_restart_between_two_polls() {
+    let ip = Ipv4Addr::new(127, 0, 0, 32);
+    let mut switch = EmulatedSwitch::new("GS105E", MAC_1, 5)
+        .with_name("desk")
+        .with_restart(Duration::from_millis(200), Duration::from_millis(300));
+    //Bytes received on port 1, cleared by the restart
+    let mut statistics = vec![0x01];
+    statistics.resize(49, 0x00);
+    statistics[8] = 0x40;
+    switch.set(Cmd::CMD_Port_Statistics, vec![statistics]);
+    let emulator = emulator(ip, vec![switch]);
+
+    let started = Instant::now();
+    let output = pputl(ip, "password", &["reboot", "desk"]);
+    let stdout = stdout(&output);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(
+        stdout.contains("desk restarted between polls"),
+        "{}",
+        stdout
+    );
+    assert!(started.elapsed() < Duration::from_secs(10));
+    assert_eq!(
+        emulator.switches()[0]
+            .get(&Cmd::CMD_Port_Statistics)
+            .unwrap()[0][8],
+        0x00
+    );
+}
+
+#[test]
+fn firmware_upgrade_serves_the_image_and_checks_the_new_version() {
+    let ip = Ipv4Addr::new(127, 0, 0, 26);
+    let tftp = SocketAddr::new(ip.into(), 6969);
+    let emulator = emulator(
+        ip,
+        vec![EmulatedSwitch::new("GS105E", MAC_1, 5)
+            .with_name("desk")
+            .with_firmware_upgrade(tftp, "1.00.11")],
+    );
+    let image: Vec<u8> = (0..1500).map(|index| index as u8).collect();
+    let path = std::env::temp_dir().join(format!("pputl-upgrade-{}.bin", std::process::id()));
+    std::fs::write(&path, &image).unwrap();
+
+    let output = pputl_on_mac_1(
+        ip,
+        &[
+            "firmware",
+            "upgrade",
+            path.to_str().unwrap(),
+            "--tftp-listen",
+            &tftp.to_string(),
+        ],
+    );
+    let _ = std::fs::remove_file(&path);
+    let stdout = stdout(&output);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(
+        stdout.contains("which asked for 'GS105E.bin'"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("Upgraded from 1.00.10 to 1.00.11"),
+        "{}",
+        stdout
+    );
+    assert_eq!(emulator.switches()[0].firmware_image(), Some(&image));
+}
+
+#[test]
+fn plan_and_apply_compare_values_not_spellings() {
+    let ip = Ipv4Addr::new(127, 0, 0, 27);
+    let emulator = emulator(
+        ip,
+        vec![EmulatedSwitch::new("GS105E", MAC_1, 5).with_name("desk")],
+    );
+    let dir = std::env::temp_dir().join(format!("pputl-plan-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let write_state = |name: &str, sources: &str, router_ports: &str, speed: &str| {
+        let file = dir.join(name);
+        std::fs::write(
+            &file,
+            format!(
+                r#"
+                [switches.desk]
+                mac = "02:00:00:00:01:01"
+                mirror = {{ destination = 5, sources = "{}" }}
+                igmp = {{ enabled = true, vlan = 1, router_ports = "{}" }}
+                ports = [{{ port = 3, speed = "{}" }}]
+                "#,
+                sources, router_ports, speed
+            ),
+        )
+        .unwrap();
+        file.to_string_lossy().into_owned()
+    };
+    let canonical = write_state("canonical.toml", "1,2,3", "1,2", "disable");
+    let aliases = write_state("aliases.toml", "1-3", "1-2", "disabled");
+
+    let output = pputl(ip, "password", &["apply", &canonical]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    assert!(stdout(&output).contains("desk: changed"));
+
+    for command in ["plan", "apply"] {
+        let output = pputl(ip, "password", &[command, &aliases]);
+        assert!(output.status.success(), "{}", stdout(&output));
+        assert!(
+            stdout(&output).contains("desk: in sync"),
+            "{}",
+            stdout(&output)
+        );
+    }
+
+    //A partial IGMP table keeps the snooping state of the switch
+    let partial = dir.join("partial.toml");
+    std::fs::write(&partial, "[igmp]\nblock_unknown_multicast = true\n").unwrap();
+    let output = pputl_on_mac_1(ip, &["restore", "--input", &partial.to_string_lossy()]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    assert_eq!(
+        emulator.switches()[0]
+            .get(&Cmd::CMD_IGMP_Block_Unknown_Multicast)
+            .unwrap()[0],
+        [1]
+    );
+    assert_eq!(
+        emulator.switches()[0].get(&Cmd::CMD_IGMP_Snooping).unwrap()[0],
+        [0x00, 0x01, 0x00, 0x01]
+    );
+
+    let output = pputl(ip, "password", &["plan", &aliases]);
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success(), "{}", stdout(&output));
+}
+
+#[test]
+fn taken_host_ports_need_share_port_or_ephemeral_port() {
+    let ip = Ipv4Addr::new(127, 0, 0, 28);
+    let _emulator = emulator(
+        ip,
+        vec![EmulatedSwitch::new("GS105E", MAC_1, 5).with_name("desk")],
+    );
+    //Another client sharing the host ports. Bound to an address of its own so replies to pputl don't reach it
+    let host_ports: Vec<SocketAddr> = [PortPair::V1, PortPair::V2]
+        .iter()
+        .map(|port_pair| SocketAddr::new(Ipv4Addr::new(127, 0, 0, 39).into(), port_pair.host))
+        .collect();
+    let _other = BlockingClient::bind_with(&host_ports, BindMode::Shared).unwrap();
+    let info = |flags: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_pputl"))
+            .args(flags)
+            .args(["--probe", &ip.to_string(), "--all", "info"])
+            .env("PPUTL_PASSWORD", "password")
+            .output()
+            .unwrap()
+    };
+
+    let output = info(&[]);
+    assert!(!output.status.success(), "{}", stdout(&output));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--share-port"), "{}", stderr);
+    assert!(stderr.contains("--ephemeral-port"), "{}", stderr);
+
+    for flag in ["--share-port", "--ephemeral-port"] {
+        let output = info(&[flag]);
+        assert!(output.status.success(), "{}", stdout(&output));
+        assert!(stdout(&output).contains("desk"), "{}", stdout(&output));
+    }
+}
+
+#[test]
+fn oversized_probe_ranges_are_refused_up_front() {
+    let started = Instant::now();
+    let output = pputl(
+        Ipv4Addr::new(10, 0, 0, 0),
+        "password",
+        &["--probe", "10.0.0.0/8", "--all", "info"],
+    );
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Probing 16777215 hosts on 2 port pairs")
+    );
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn vlans_listed_twice_count_once_against_the_model_limit() {
+    let ip = Ipv4Addr::new(127, 0, 0, 29);
+    //The switch reports its 32 VLANs both as port based and as 802.1Q VLANs
+    let mut switch = EmulatedSwitch::new("GS105E", MAC_1, 5);
+    switch.set(
+        Cmd::CMD_VLAN_Port_Members,
+        (1..=32).map(|vlan| vec![0x00, vlan, 0xf8]).collect(),
+    );
+    switch.set(
+        Cmd::CMD_VLAN_8021Q_Members,
+        (1..=32).map(|vlan| vec![0x00, vlan, 0xf8, 0x00]).collect(),
+    );
+    let _emulator = emulator(ip, vec![switch]);
+    let memberships: Vec<String> = (1..=32)
+        .map(|vlan| {
+            format!(
+                "[[vlans.memberships]]\nid = {}\nmembers = \"1-5\"\ntagged = \"\"\n",
+                vlan
+            )
+        })
+        .collect();
+    let file = std::env::temp_dir().join(format!("pputl-vlans-{}.toml", std::process::id()));
+    std::fs::write(&file, memberships.concat()).unwrap();
+
+    let output = pputl_on_mac_1(ip, &["restore", "--input", &file.to_string_lossy()]);
+    std::fs::remove_file(&file).unwrap();
+    assert!(output.status.success(), "{}", stdout(&output));
+    assert!(stdout(&output).contains("Restored 32 settings"));
+}
+
+#[test]
+fn restore_and_plan_check_the_mirror_against_the_switch() {
+    let ip = Ipv4Addr::new(127, 0, 0, 30);
+    let emulator = emulator(
+        ip,
+        vec![EmulatedSwitch::new("GS105E", MAC_1, 5).with_name("desk")],
+    );
+    let dir = std::env::temp_dir().join(format!("pputl-mirror-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    //A backup taken from a larger model, restored onto five ports
+    let backup = dir.join("backup.toml");
+    std::fs::write(&backup, "[mirror]\ndestination = 5\nsources = \"1,8\"\n").unwrap();
+    let output = pputl_on_mac_1(ip, &["restore", "--input", &backup.to_string_lossy()]);
+    assert!(!output.status.success(), "{}", stdout(&output));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Port 8 does not exist"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let state = dir.join("state.toml");
+    std::fs::write(
+        &state,
+        "[switches.desk]\nmac = \"02:00:00:00:01:01\"\nmirror = { destination = 3, sources = \"1-3\" }\n",
+    )
+    .unwrap();
+    let output = pputl(ip, "password", &["plan", &state.to_string_lossy()]);
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(!output.status.success(), "{}", stdout(&output));
+    assert!(format!(
+        "{}{}",
+        stdout(&output),
+        String::from_utf8_lossy(&output.stderr)
+    )
+    .contains("Port 3 cannot mirror to itself"));
+
+    assert_eq!(
+        emulator.switches()[0]
+            .get(&Cmd::CMD_Port_Mirroring)
+            .unwrap()[0],
+        [0x00, 0x00, 0x00]
+    );
+}
+
+#[test]
+fn apply_counts_the_vlans_already_on_the_switch() {
+    let ip = Ipv4Addr::new(127, 0, 0, 31);
+    let vlan = |id: u8| vec![0x00, id, 0xf8, 0x00];
+    let mut switch = EmulatedSwitch::new("GS105E", MAC_1, 5).with_name("desk");
+    switch.set(Cmd::CMD_VLAN_8021Q_Members, (1..=30).map(vlan).collect());
+    let emulator = emulator(ip, vec![switch]);
+
+    let dir = std::env::temp_dir().join(format!("pputl-vlan-apply-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let write_state = |name: &str, last: u8| {
+        let memberships: Vec<String> = (1..=last)
+            .map(|id| format!("{{ id = {}, members = \"1-5\", tagged = \"\" }}", id))
+            .collect();
+        let file = dir.join(name);
+        std::fs::write(
+            &file,
+            format!(
+                "[switches.desk]\nmac = \"02:00:00:00:01:01\"\nvlans = {{ memberships = [{}] }}\n",
+                memberships.join(", ")
+            ),
+        )
+        .unwrap();
+        file.to_string_lossy().into_owned()
+    };
+    let too_many = write_state("too_many.toml", 33);
+    let at_limit = write_state("at_limit.toml", 32);
+
+    //Only the three new VLANs differ, but together with the 30 on the switch they are over the limit
+    let output = pputl(ip, "password", &["apply", &too_many]);
+    assert!(!output.status.success(), "{}", stdout(&output));
+    assert!(
+        stdout(&output).contains("Switch would hold 33 VLANs, GS105E holds at most 32"),
+        "{}",
+        stdout(&output)
+    );
+    assert_eq!(
+        emulator.switches()[0]
+            .get(&Cmd::CMD_VLAN_8021Q_Members)
+            .unwrap()
+            .len(),
+        30
+    );
+
+    let output = pputl(ip, "password", &["apply", &at_limit]);
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success(), "{}", stdout(&output));
+    assert_eq!(
+        emulator.switches()[0]
+            .get(&Cmd::CMD_VLAN_8021Q_Members)
+            .unwrap()
+            .len(),
+        32
+    );
+}
+
+#[test]
+fn passwords_longer_than_the_key_are_refused_before_sending() {
+    let ip = Ipv4Addr::new(127, 0, 0, 33);
+    let emulator = emulator(ip, vec![EmulatedSwitch::new("GS105E", MAC_1, 5)]);
+    let too_long = "a".repeat(20);
+
+    let output = pputl(ip, &too_long, &["--all", "loop-detection", "on"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("PPUTL_PASSWORD is longer"), "{}", stderr);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_pputl"))
+        .args([
+            "--ephemeral-port",
+            "--probe",
+            &ip.to_string(),
+            "--all",
+            "password",
+        ])
+        .env("PPUTL_PASSWORD", "password")
+        .env("PPUTL_NEW_PASSWORD", &too_long)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("PPUTL_NEW_PASSWORD is longer"),
+        "{}",
+        stderr
+    );
+
+    assert!(emulator.switches()[0].writes().is_empty());
+}
+
+#[test]
+fn factory_reset_needs_yes() {
+    let ip = Ipv4Addr::new(127, 0, 0, 34);
+    let emulator = emulator(
+        ip,
+        vec![EmulatedSwitch::new("GS105E", MAC_1, 5).with_name("desk")],
+    );
+
+    let output = pputl(ip, "password", &["factory-reset", "desk"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("pass --yes to confirm"), "{}", stderr);
+    assert!(emulator.switches()[0].writes().is_empty());
+}
+
+#[test]
+fn reboot_fails_when_the_restart_can_not_be_verified() {
+    let ip = Ipv4Addr::new(127, 0, 0, 35);
+    //Takes the reboot but keeps answering past the grace period, with idle counters that show nothing either way
+    let emulator = emulator(
+        ip,
+        vec![EmulatedSwitch::new("GS105E", MAC_1, 5)
+            .with_name("desk")
+            .with_restart(Duration::from_secs(60), Duration::from_secs(1))],
+    );
+
+    let output = pputl(ip, "password", &["reboot", "desk"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("could not verify that it restarted"),
+        "{}",
+        stderr
+    );
+    assert!(emulator.switches()[0]
+        .writes()
+        .iter()
+        .any(|tlv| tlv.cmd_equal_to(&Cmd::CMD_Reboot)));
+}
+
+#[test]
+fn restores_replace_the_vlans_on_the_switch() {
+    let ip = Ipv4Addr::new(127, 0, 0, 36);
+    let mut switch = EmulatedSwitch::new("GS105E", MAC_1, 5);
+    switch.set(Cmd::CMD_VLAN_Mode, vec![vec![0x04]]);
+    switch.set(
+        Cmd::CMD_VLAN_8021Q_Members,
+        (1..=3).map(|vlan| vec![0x00, vlan, 0xf8, 0x80]).collect(),
+    );
+    switch.set(
+        Cmd::CMD_VLAN_PVID,
+        (1..=5).map(|port| vec![port, 0x00, 0x01]).collect(),
+    );
+    switch.set(Cmd::CMD_QoS_Mode, vec![vec![0x01]]);
+    switch.set(Cmd::CMD_Storm_Control, vec![vec![0x00]]);
+    switch.set(
+        Cmd::CMD_Ingress_Rate_Limit,
+        vec![vec![0x02, 0x00, 0x00, 0x00, 0x05]],
+    );
+    let emulator = emulator(ip, vec![switch]);
+    let dir = std::env::temp_dir().join(format!("pputl-vlan-restore-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let backup = dir.join("backup.toml");
+    let output = pputl_on_mac_1(ip, &["backup", "--output", &backup.to_string_lossy()]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    let text = std::fs::read_to_string(&backup).unwrap();
+    assert!(text.contains("[[vlans.memberships]]"), "{}", text);
+    assert!(text.contains("[[rate_limits.ports]]"), "{}", text);
+    assert!(!text.contains("[tlvs]"), "{}", text);
+
+    let vlans = |emulator: &Emulator| -> Vec<u8> {
+        emulator.switches()[0]
+            .get(&Cmd::CMD_VLAN_8021Q_Members)
+            .unwrap()
+            .iter()
+            .map(|record| record[1])
+            .collect()
+    };
+    let fewer = dir.join("fewer.toml");
+    std::fs::write(
+        &fewer,
+        "[[vlans.memberships]]\nid = 1\nmembers = \"1-5\"\ntagged = \"1\"\n\n[[vlans.memberships]]\nid = 10\nmembers = \"4,5\"\ntagged = \"\"\n",
+    )
+    .unwrap();
+    let output = pputl_on_mac_1(ip, &["restore", "--input", &fewer.to_string_lossy()]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(vlans(&emulator), [1, 10]);
+
+    let output = pputl_on_mac_1(ip, &["restore", "--input", &backup.to_string_lossy()]);
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(vlans(&emulator), [1, 2, 3]);
+}
+
+#[test]
+fn restores_count_the_vlans_left_after_the_deletes() {
+    let ip = Ipv4Addr::new(127, 0, 0, 37);
+    let mut switch = EmulatedSwitch::new("GS105E", MAC_1, 5);
+    switch.set(
+        Cmd::CMD_VLAN_8021Q_Members,
+        (1..=30).map(|vlan| vec![0x00, vlan, 0xf8, 0x00]).collect(),
+    );
+    let emulator = emulator(ip, vec![switch]);
+    //32 other VLANs, the 30 on the switch go so it never holds more than 32
+    let memberships: Vec<String> = (101..=132)
+        .map(|vlan| {
+            format!(
+                "[[vlans.memberships]]\nid = {}\nmembers = \"1-5\"\ntagged = \"\"\n",
+                vlan
+            )
+        })
+        .collect();
+    let file = std::env::temp_dir().join(format!("pputl-vlans-after-{}.toml", std::process::id()));
+    std::fs::write(&file, memberships.concat()).unwrap();
+
+    let output = pputl_on_mac_1(ip, &["restore", "--input", &file.to_string_lossy()]);
+    std::fs::remove_file(&file).unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let vlans: Vec<u8> = emulator.switches()[0]
+        .get(&Cmd::CMD_VLAN_8021Q_Members)
+        .unwrap()
+        .iter()
+        .map(|record| record[1])
+        .collect();
+    assert_eq!(vlans, (101..=132).collect::<Vec<u8>>());
+}
+
+#[test]
+fn applying_the_same_state_twice_changes_nothing_the_second_time() {
+    let ip = Ipv4Addr::new(127, 0, 0, 38);
+    let mut switch = EmulatedSwitch::new("GS105E", MAC_1, 5).with_name("desk");
+    switch.set(Cmd::CMD_VLAN_Mode, vec![vec![0x04]]);
+    switch.set(
+        Cmd::CMD_VLAN_8021Q_Members,
+        (1..=3).map(|vlan| vec![0x00, vlan, 0xf8, 0x00]).collect(),
+    );
+    let emulator = emulator(ip, vec![switch]);
+    let file = std::env::temp_dir().join(format!("pputl-apply-twice-{}.toml", std::process::id()));
+    std::fs::write(
+        &file,
+        r#"
+        [switches.desk]
+        mac = "02:00:00:00:01:01"
+        vlans = { memberships = [{ id = 1, members = "1-5", tagged = "" }, { id = 20, members = "4-5", tagged = "5" }], ports = [{ port = 4, pvid = 20 }] }
+        qos = { mode = 1, ports = [{ port = 1, priority = 1 }] }
+        rate_limits = { storm_control = true, ports = [{ port = 2, ingress = 5, egress = 3 }] }
+        "#,
+    )
+    .unwrap();
+    let file = file.to_string_lossy().into_owned();
+
+    let output = pputl(ip, "password", &["apply", &file]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    assert!(
+        stdout(&output).contains("- vlans.memberships.2.members"),
+        "{}",
+        stdout(&output)
+    );
+    let vlans: Vec<u8> = emulator.switches()[0]
+        .get(&Cmd::CMD_VLAN_8021Q_Members)
+        .unwrap()
+        .iter()
+        .map(|record| record[1])
+        .collect();
+    assert_eq!(vlans, [1, 20]);
+
+    let writes = emulator.switches()[0].writes().len();
+    let output = pputl(ip, "password", &["apply", &file]);
+    std::fs::remove_file(&file).unwrap();
+    assert!(output.status.success(), "{}", stdout(&output));
+    assert!(
+        stdout(&output).contains("desk: in sync"),
+        "{}",
+        stdout(&output)
+    );
+    assert_eq!(emulator.switches()[0].writes().len(), writes);
+}
+
+#[test]
+fn firmware_activate_reboots_into_the_other_image() {
+    let ip = Ipv4Addr::new(127, 0, 0, 40);
+    let mut switch = EmulatedSwitch::new("GS105Ev2", MAC_1, 5)
+        .with_name("desk")
+        .with_restart(Duration::from_millis(500), Duration::from_secs(3));
+    switch.set(Cmd::CMD_FW_Version_2, vec![b"1.00.12".to_vec()]);
+    let emulator = emulator(ip, vec![switch]);
+
+    let output = pputl_on_model(ip, "GS105Ev2", &["firmware", "activate", "2"]);
+    let stdout = stdout(&output);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("boots image 2 from now on"), "{}", stdout);
+    assert!(stdout.contains("desk is back after"), "{}", stdout);
+    assert!(stdout.contains("Image 2: 1.00.12 (active)"), "{}", stdout);
+    let switch = &emulator.switches()[0];
+    assert_eq!(switch.get(&Cmd::CMD_FW_Active).unwrap()[0], [0x02]);
+    assert!(switch
+        .writes()
+        .iter()
+        .any(|tlv| tlv.cmd_equal_to(&Cmd::CMD_Reboot)));
+}
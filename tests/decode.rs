@@ -0,0 +1,256 @@
+use std::fs;
+use std::io::Write;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use pputl::cmds::{Cmd, ProtoConsts, TypeLengthValue};
+use pputl::dispatch::PortPair;
+use pputl::emulator::EmulatedSwitch;
+use pputl::packet::Packet;
+use pputl::request::{Request, Session};
+use pputl::values::to_hex;
+
+const HOST_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x03, 0x00];
+const SWITCH_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x03, 0x01];
+const HOST_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 0, 10);
+const SWITCH_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 0, 239);
+
+fn capture_path(test: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("pputl-{}-{}", test, std::process::id()))
+}
+
+//A query for the model, the port count and a TLV pputl doesn't know, and the emulator's reply to it
+fn conversation() -> (Vec<u8>, Vec<u8>) {
+    let request = Request::builder()
+        .ctype(ProtoConsts::QueryRequest.value().try_into().unwrap())
+        .session(Session::new(HOST_MAC, SWITCH_MAC, [0x00, 0x2a]))
+        .add_cmd(TypeLengthValue::from(Cmd::CMD_Model))
+        .add_cmd(TypeLengthValue::from(Cmd::CMD_Port_Count))
+        .add_cmd(TypeLengthValue::from([0x7f, 0x00]))
+        .build()
+        .format();
+    let reply = EmulatedSwitch::new("GS108Ev3", SWITCH_MAC, 8)
+        .handle(&Packet::parse(&request).unwrap())
+        .unwrap()
+        .format();
+    (request, reply)
+}
+
+//Ethernet, IPv4 and UDP headers around the payload, checksums are left zero like with offloading
+fn ethernet_frame(src: (Ipv4Addr, u16), dst: (Ipv4Addr, u16), payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::new();
+    frame.extend([0xff; 6]);
+    frame.extend(HOST_MAC);
+    frame.extend([0x08, 0x00]);
+
+    frame.extend([0x45, 0x00]);
+    frame.extend((20 + 8 + payload.len() as u16).to_be_bytes());
+    frame.extend([0x00, 0x00, 0x40, 0x00, 0x40, 17, 0x00, 0x00]);
+    frame.extend(src.0.octets());
+    frame.extend(dst.0.octets());
+
+    frame.extend(src.1.to_be_bytes());
+    frame.extend(dst.1.to_be_bytes());
+    frame.extend((8 + payload.len() as u16).to_be_bytes());
+    frame.extend([0x00, 0x00]);
+    frame.extend(payload);
+    frame
+}
+
+fn frames() -> Vec<Vec<u8>> {
+    let (request, reply) = conversation();
+    let host = (HOST_IP, PortPair::V1.host);
+    let switch = (SWITCH_IP, PortPair::V1.switch);
+    vec![
+        ethernet_frame(host, switch, &request),
+        ethernet_frame((HOST_IP, 5353), (SWITCH_IP, 5353), b"not nsdp"),
+        ethernet_frame(switch, host, &reply),
+    ]
+}
+
+//Classic pcap, microsecond timestamps, 1.5 ms apart
+fn pcap(frames: &[Vec<u8>]) -> Vec<u8> {
+    let mut capture = Vec::new();
+    capture.extend(0xa1b2c3d4u32.to_le_bytes());
+    capture.extend(2u16.to_le_bytes());
+    capture.extend(4u16.to_le_bytes());
+    capture.extend([0; 8]);
+    capture.extend(65535u32.to_le_bytes());
+    capture.extend(1u32.to_le_bytes());
+    for (index, frame) in frames.iter().enumerate() {
+        capture.extend(1_700_000_000u32.to_le_bytes());
+        capture.extend((index as u32 * 1500).to_le_bytes());
+        capture.extend((frame.len() as u32).to_le_bytes());
+        capture.extend((frame.len() as u32).to_le_bytes());
+        capture.extend(frame);
+    }
+    capture
+}
+
+//Big endian pcapng with nanosecond timestamps, 1.5 ms apart
+fn pcapng(frames: &[Vec<u8>]) -> Vec<u8> {
+    fn block(capture: &mut Vec<u8>, block_type: u32, mut body: Vec<u8>) {
+        body.resize(body.len().div_ceil(4) * 4, 0);
+        let len = body.len() as u32 + 12;
+        capture.extend(block_type.to_be_bytes());
+        capture.extend(len.to_be_bytes());
+        capture.extend(body);
+        capture.extend(len.to_be_bytes());
+    }
+
+    let mut capture = Vec::new();
+    let mut section = 0x1a2b3c4du32.to_be_bytes().to_vec();
+    section.extend([0x00, 0x01, 0x00, 0x00]);
+    section.extend([0xff; 8]);
+    block(&mut capture, 0x0a0d0d0a, section);
+
+    let mut interface = vec![0x00, 0x01, 0x00, 0x00];
+    interface.extend(65535u32.to_be_bytes());
+    interface.extend([0x00, 0x09, 0x00, 0x01, 9, 0x00, 0x00, 0x00]);
+    interface.extend([0x00; 4]);
+    block(&mut capture, 1, interface);
+
+    for (index, frame) in frames.iter().enumerate() {
+        let timestamp = 1_700_000_000_000_000_000u64 + index as u64 * 1_500_000;
+        let mut packet = 0u32.to_be_bytes().to_vec();
+        packet.extend(((timestamp >> 32) as u32).to_be_bytes());
+        packet.extend((timestamp as u32).to_be_bytes());
+        packet.extend((frame.len() as u32).to_be_bytes());
+        packet.extend((frame.len() as u32).to_be_bytes());
+        packet.extend(frame);
+        block(&mut capture, 6, packet);
+    }
+    capture
+}
+
+fn decode(path: &Path) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_pputl"))
+        .args(["decode", path.to_str().unwrap()])
+        .output()
+        .unwrap();
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    (output.status.success(), text)
+}
+
+fn assert_decoded(stdout: &str) {
+    assert!(
+        stdout.contains("#1 +0.000000s 192.168.0.10:63321 -> 192.168.0.239:63322"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("#2 +0.003000s 192.168.0.239:63322 -> 192.168.0.10:63321"));
+    assert!(stdout
+        .contains("query request, seq 002a, host 02:00:00:00:03:00, switch 02:00:00:00:03:01"));
+    assert!(stdout.contains("query response, seq 002a"));
+    assert!(stdout.contains("= \"GS108Ev3\""));
+    assert!(stdout.contains("= 8"));
+    assert!(stdout.contains("?unknown"));
+    assert!(stdout.contains("2 NSDP packets in 3 UDP datagrams"));
+}
+
+#[test]
+fn decodes_a_pcap_capture() {
+    let path = capture_path("decode.pcap");
+    fs::write(&path, pcap(&frames())).unwrap();
+    let (success, stdout) = decode(&path);
+    let _ = fs::remove_file(&path);
+    assert!(success, "{}", stdout);
+    assert_decoded(&stdout);
+}
+
+#[test]
+fn decodes_a_pcapng_capture() {
+    let path = capture_path("decode.pcapng");
+    fs::write(&path, pcapng(&frames())).unwrap();
+    let (success, stdout) = decode(&path);
+    let _ = fs::remove_file(&path);
+    assert!(success, "{}", stdout);
+    assert_decoded(&stdout);
+}
+
+#[test]
+fn rejects_files_that_are_no_capture() {
+    let path = capture_path("decode.txt");
+    fs::write(&path, "hello, not a capture").unwrap();
+    let (success, stdout) = decode(&path);
+    let _ = fs::remove_file(&path);
+    assert!(!success);
+    assert!(stdout.contains("Not a pcap or pcapng file"), "{}", stdout);
+}
+
+fn decode_hex(args: &[&str], stdin: &str) -> (bool, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pputl"))
+        .arg("decode")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    (output.status.success(), text)
+}
+
+#[test]
+fn decodes_a_request_given_as_hex() {
+    let (request, _) = conversation();
+    let (success, stdout) = decode_hex(&["--hex", &to_hex(&request)], "");
+    assert!(success, "{}", stdout);
+    assert!(
+        stdout.contains("Type:       query request (0101)"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("Status:     0000 (success)"));
+    assert!(stdout.contains("Host MAC:   02:00:00:00:03:00"));
+    assert!(stdout.contains("Switch MAC: 02:00:00:00:03:01"));
+    assert!(stdout.contains("Sequence:   002a"));
+    assert!(stdout.contains("Signature:  NSDP"));
+    assert!(stdout.contains("TLVs:       3"));
+    assert!(stdout.contains("?unknown"));
+}
+
+#[test]
+fn decodes_a_response_from_stdin() {
+    let (_, reply) = conversation();
+    //Wireshark style, colon separated and wrapped over several lines
+    let dump: Vec<String> = reply
+        .chunks(16)
+        .map(|line| {
+            let bytes: Vec<String> = line.iter().map(|byte| format!("{:02x}", byte)).collect();
+            bytes.join(":")
+        })
+        .collect();
+    let (success, stdout) = decode_hex(&[], &dump.join("\n"));
+    assert!(success, "{}", stdout);
+    assert!(
+        stdout.contains("Type:       query response (0102)"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("TLVs:       2"));
+    assert!(stdout.contains("= \"GS108Ev3\""));
+}
+
+#[test]
+fn rejects_hex_that_is_no_packet() {
+    let (success, stdout) = decode_hex(&["--hex", "0102 0000"], "");
+    assert!(!success);
+    assert!(stdout.contains("Message too short"), "{}", stdout);
+}
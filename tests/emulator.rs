@@ -0,0 +1,374 @@
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use pputl::cmds::{Cmd, TypeLengthValue};
+use pputl::dispatch::{BindMode, PortPair};
+use pputl::emulator::{EmulatedSwitch, Emulator, STATUS_INVALID_VALUE};
+use pputl::fixture::Fixture;
+use pputl::values::encode_password;
+use pputl::{BlockingClient, Client, Switch};
+
+const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+const WAIT: Duration = Duration::from_millis(300);
+
+const MAC_1: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+const MAC_2: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x02];
+
+fn emulator(switches: Vec<EmulatedSwitch>) -> Emulator {
+    Emulator::bind(SocketAddr::new(LOCALHOST, 0), switches).unwrap()
+}
+
+//Unicast client on an ephemeral port talking to the emulator only
+fn client(emulator: &Emulator) -> BlockingClient {
+    let client =
+        BlockingClient::bind_with(&[SocketAddr::new(LOCALHOST, 0)], BindMode::Exclusive).unwrap();
+    let host = client.local_addrs().unwrap()[0].port();
+    client
+        .with_port_pairs(vec![PortPair {
+            host,
+            switch: emulator.local_addr().port(),
+        }])
+        .with_probe_hosts(vec![LOCALHOST])
+        .with_unicast(true)
+        .with_reply_timeout(WAIT)
+}
+
+fn discover_one(client: &BlockingClient) -> Switch {
+    let mut switches = client.discover(WAIT).unwrap();
+    assert_eq!(switches.len(), 1);
+    switches.remove(0)
+}
+
+fn login(plain: &str) -> TypeLengthValue {
+    TypeLengthValue::from((Cmd::CMD_Password, encode_password(plain)))
+}
+
+#[test]
+fn discovers_every_emulated_switch() {
+    let emulator = emulator(vec![
+        EmulatedSwitch::new("GS105E", MAC_1, 5).with_name("desk"),
+        EmulatedSwitch::new("GS108Ev3", MAC_2, 8).with_name("rack"),
+    ]);
+    let client = client(&emulator);
+
+    let mut switches = client.discover(WAIT).unwrap();
+    switches.sort_by_key(|switch| switch.mac_address);
+    let found: Vec<(&str, &str, [u8; 6])> = switches
+        .iter()
+        .map(|switch| {
+            (
+                switch.name.as_str(),
+                switch.model.as_str(),
+                switch.mac_address,
+            )
+        })
+        .collect();
+    assert_eq!(
+        found,
+        vec![("desk", "GS105E", MAC_1), ("rack", "GS108Ev3", MAC_2)]
+    );
+    assert_eq!(switches[0].ip(), LOCALHOST);
+}
+
+#[test]
+fn query_returns_stored_records() {
+    let emulator = emulator(vec![EmulatedSwitch::new("GS105E", MAC_1, 5)]);
+    let client = client(&emulator);
+    let switch = discover_one(&client);
+
+    let resp = client
+        .query(&switch, &[Cmd::CMD_Port_Count, Cmd::CMD_Port_Speed_Config])
+        .unwrap();
+    assert_eq!(resp.get_session().get_switch_mac(), MAC_1);
+    assert_eq!(resp.get_cmd(&Cmd::CMD_Port_Count).unwrap().value(), [5]);
+    let speeds: Vec<&[u8]> = resp
+        .get_cmds()
+        .iter()
+        .filter(|tlv| tlv.cmd_equal_to(&Cmd::CMD_Port_Speed_Config))
+        .map(TypeLengthValue::value)
+        .collect();
+    assert_eq!(speeds.len(), 5);
+    assert_eq!(speeds[4], [5, 0x01]);
+}
+
+#[test]
+fn transmit_needs_the_password() {
+    let emulator = emulator(vec![
+        EmulatedSwitch::new("GS105E", MAC_1, 5).with_password("secret")
+    ]);
+    let client = client(&emulator);
+    let switch = discover_one(&client);
+    let loop_detection = TypeLengthValue::from((Cmd::CMD_Loop_Detection, true));
+
+    let err = client
+        .transmit(&switch, &login("password"), vec![loop_detection.clone()])
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    assert!(emulator.switches()[0].writes().is_empty());
+
+    client
+        .transmit(&switch, &login("secret"), vec![loop_detection])
+        .unwrap();
+    let resp = client.query(&switch, &[Cmd::CMD_Loop_Detection]).unwrap();
+    assert_eq!(resp.get_cmd(&Cmd::CMD_Loop_Detection).unwrap().value(), [1]);
+}
+
+#[test]
+fn per_port_writes_replace_only_that_port() {
+    let emulator = emulator(vec![EmulatedSwitch::new("GS105E", MAC_1, 5)]);
+    let client = client(&emulator);
+    let switch = discover_one(&client);
+
+    client
+        .transmit(
+            &switch,
+            &login("password"),
+            vec![TypeLengthValue::from((
+                Cmd::CMD_Port_Flow_Control,
+                vec![3, 0x01],
+            ))],
+        )
+        .unwrap();
+
+    let switches = emulator.switches();
+    let flow_control = switches[0].get(&Cmd::CMD_Port_Flow_Control).unwrap();
+    assert_eq!(flow_control.len(), 5);
+    assert_eq!(flow_control[2], [3, 0x01]);
+    assert_eq!(flow_control[3], [4, 0x00]);
+}
+
+#[test]
+fn new_password_replaces_the_old_one() {
+    let emulator = emulator(vec![EmulatedSwitch::new("GS105E", MAC_1, 5)]);
+    let client = client(&emulator);
+    let switch = discover_one(&client);
+
+    client
+        .transmit(
+            &switch,
+            &login("password"),
+            vec![TypeLengthValue::from((
+                Cmd::CMD_New_Password,
+                encode_password("changed"),
+            ))],
+        )
+        .unwrap();
+
+    assert!(client
+        .transmit(&switch, &login("password"), vec![])
+        .is_err());
+    client.transmit(&switch, &login("changed"), vec![]).unwrap();
+}
+
+#[test]
+fn injected_failures_reject_the_request() {
+    let emulator = emulator(vec![EmulatedSwitch::new("GS105E", MAC_1, 5)
+        .with_failure(Cmd::CMD_Port_Mirroring, STATUS_INVALID_VALUE)]);
+    let client = client(&emulator);
+    let switch = discover_one(&client);
+
+    let err = client
+        .query(&switch, &[Cmd::CMD_Port_Mirroring])
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    assert!(err.to_string().contains("05"));
+
+    let err = client
+        .transmit(
+            &switch,
+            &login("password"),
+            vec![TypeLengthValue::from((Cmd::CMD_Port_Count, vec![8]))],
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    assert_eq!(
+        emulator.switches()[0].get(&Cmd::CMD_Port_Count).unwrap()[0],
+        [5]
+    );
+}
+
+#[test]
+fn lost_requests_time_out() {
+    let emulator = emulator(vec![EmulatedSwitch::new("GS105E", MAC_1, 5)]);
+    let client = client(&emulator);
+    let switch = discover_one(&client);
+
+    emulator.switches()[0] = EmulatedSwitch::new("GS105E", MAC_1, 5).with_loss(1.0);
+    let err = client.query(&switch, &[Cmd::CMD_Model]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+}
+
+#[tokio::test]
+async fn concurrent_queries_get_their_own_replies() {
+    let emulator = emulator(vec![
+        EmulatedSwitch::new("GS105E", MAC_1, 5),
+        EmulatedSwitch::new("GS116Ev2", MAC_2, 16),
+    ]);
+    let client = Client::bind(SocketAddr::new(LOCALHOST, 0)).await.unwrap();
+    let host = client.local_addrs().unwrap()[0].port();
+    let client = client
+        .with_port_pairs(vec![PortPair {
+            host,
+            switch: emulator.local_addr().port(),
+        }])
+        .with_probe_hosts(vec![LOCALHOST])
+        .with_unicast(true);
+    let switches = client.discover(WAIT).await.unwrap();
+    assert_eq!(switches.len(), 2);
+
+    let client = Arc::new(client);
+    let tasks: Vec<_> = switches
+        .iter()
+        .cycle()
+        .take(20)
+        .cloned()
+        .map(|switch| {
+            let client = client.clone();
+            tokio::spawn(async move {
+                let resp = client.query(&switch, &[Cmd::CMD_Port_Count]).await;
+                (switch, resp)
+            })
+        })
+        .collect();
+    for task in tasks {
+        let (switch, resp) = task.await.unwrap();
+        let resp = resp.unwrap();
+        let expected = if switch.mac_address == MAC_1 { 5 } else { 16 };
+        assert_eq!(resp.get_session().get_switch_mac(), switch.mac_address);
+        assert_eq!(
+            resp.get_cmd(&Cmd::CMD_Port_Count).unwrap().value(),
+            [expected]
+        );
+    }
+}
+
+//The switch speaks only the second port pair and sits behind a relay noting the port every request came from
+#[test]
+fn switches_on_the_second_port_pair_are_reached_through_its_listener() {
+    let emulator = emulator(vec![
+        EmulatedSwitch::new("GS110EMX", MAC_1, 10).with_name("v2")
+    ]);
+    let relay = UdpSocket::bind(SocketAddr::new(LOCALHOST, 0)).unwrap();
+    let relay_port = relay.local_addr().unwrap().port();
+    let sources = Arc::new(Mutex::new(Vec::new()));
+    let seen = sources.clone();
+    let upstream = UdpSocket::bind(SocketAddr::new(LOCALHOST, 0)).unwrap();
+    upstream.connect(emulator.local_addr()).unwrap();
+    upstream.set_read_timeout(Some(WAIT)).unwrap();
+    thread::spawn(move || {
+        let mut buf = [0; 2048];
+        while let Ok((len, src_addr)) = relay.recv_from(&mut buf) {
+            seen.lock().unwrap().push(src_addr.port());
+            upstream.send(&buf[..len]).unwrap();
+            if let Ok(len) = upstream.recv(&mut buf) {
+                relay.send_to(&buf[..len], src_addr).unwrap();
+            }
+        }
+    });
+    //Nothing answers on the first pair
+    let silent = UdpSocket::bind(SocketAddr::new(LOCALHOST, 0)).unwrap();
+
+    let client = BlockingClient::bind_with(
+        &[SocketAddr::new(LOCALHOST, 0), SocketAddr::new(LOCALHOST, 0)],
+        BindMode::Exclusive,
+    )
+    .unwrap();
+    let hosts: Vec<u16> = client
+        .local_addrs()
+        .unwrap()
+        .iter()
+        .map(SocketAddr::port)
+        .collect();
+    let v1 = PortPair {
+        host: hosts[0],
+        switch: silent.local_addr().unwrap().port(),
+    };
+    let v2 = PortPair {
+        host: hosts[1],
+        switch: relay_port,
+    };
+    let client = client
+        .with_port_pairs(vec![v1, v2])
+        .with_probe_hosts(vec![LOCALHOST])
+        .with_unicast(true)
+        .with_reply_timeout(WAIT);
+
+    let switch = discover_one(&client);
+    assert_eq!(switch.name, "v2");
+    assert_eq!(switch.port_pair, v2);
+
+    let resp = client.query(&switch, &[Cmd::CMD_Port_Count]).unwrap();
+    assert_eq!(resp.get_cmd(&Cmd::CMD_Port_Count).unwrap().value(), [10]);
+    assert_eq!(*sources.lock().unwrap(), [v2.host, v2.host]);
+}
+
+#[test]
+fn fixture_expands_counted_entries() {
+    let fixture = Fixture::from_toml(
+        r#"
+        [[switch]]
+        name = "edge-{n}"
+        count = 3
+        mac = "02:00:00:00:00:ff"
+        model = "GS105Ev2"
+        ports = 5
+        ip = "10.0.0.254"
+
+        [[switch.vlans]]
+        id = 10
+        members = "1-3"
+        tagged = "1"
+
+        [[switch.stats]]
+        port = 2
+        received = 1000
+        "#,
+    )
+    .unwrap();
+    let switches = fixture.switches().unwrap();
+    assert_eq!(switches.len(), 3);
+
+    let last = &switches[2];
+    assert_eq!(last.mac(), [0x02, 0, 0, 0, 0x01, 0x01]);
+    assert_eq!(last.get(&Cmd::CMD_Name).unwrap()[0], b"edge-3");
+    assert_eq!(last.get(&Cmd::CMD_IPv4).unwrap()[0], [10, 0, 1, 0]);
+    assert_eq!(
+        last.get(&Cmd::CMD_VLAN_8021Q_Members).unwrap()[0],
+        [0x00, 10, 0b1110_0000, 0b1000_0000]
+    );
+    let pvids = last.get(&Cmd::CMD_VLAN_PVID).unwrap();
+    assert_eq!(pvids[0], [1, 0x00, 1]);
+    assert_eq!(pvids[1], [2, 0x00, 10]);
+    let stats = &last.get(&Cmd::CMD_Port_Statistics).unwrap()[1];
+    assert_eq!(stats.len(), 49);
+    assert_eq!(stats[1..9], 1000u64.to_be_bytes());
+}
+
+#[test]
+fn fixture_rejects_unknown_tlvs() {
+    let fixture = Fixture::from_toml(
+        r#"
+        [[switch]]
+        name = "desk"
+        mac = "02:00:00:00:00:01"
+        model = "GS105E"
+        ports = 5
+
+        [switch.tlvs]
+        CMD_Nope = ["01"]
+        "#,
+    )
+    .unwrap();
+    assert!(fixture.switches().unwrap_err().contains("CMD_Nope"));
+}
+
+#[test]
+fn shipped_fleet_fixture_loads() {
+    let toml = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/fleet.toml"))
+        .unwrap();
+    let switches = Fixture::from_toml(&toml).unwrap().switches().unwrap();
+    assert_eq!(switches.len(), 51);
+}
@@ -0,0 +1,49 @@
+use pputl::cmds::Cmd;
+use pputl::models::{readable_cmds, AuthScheme, ModelCapabilities, MODELS};
+
+#[test]
+fn models_are_looked_up_by_the_reported_string() {
+    let gs108 = ModelCapabilities::lookup("GS108Ev3\0\0").unwrap();
+    assert_eq!(gs108.ports, 8);
+    assert_eq!(gs108.auth, AuthScheme::XorPassword);
+    assert_eq!(ModelCapabilities::lookup("gs116ev2").unwrap().ports, 16);
+    assert_eq!(ModelCapabilities::lookup("JGS524PE").unwrap().ports, 24);
+    //A revision missing from the table is not mistaken for an earlier one
+    assert!(ModelCapabilities::lookup("GS105Ev3").is_none());
+}
+
+#[test]
+fn every_model_supports_the_basics() {
+    for capabilities in MODELS {
+        for cmd in [
+            Cmd::CMD_Model,
+            Cmd::CMD_Password,
+            Cmd::CMD_Port_Count,
+            Cmd::CMD_FW_Version,
+        ] {
+            assert!(
+                capabilities.supports(&cmd),
+                "{} {:?}",
+                capabilities.model,
+                cmd
+            );
+        }
+        assert!(capabilities.max_vlans > 0);
+    }
+}
+
+#[test]
+fn queries_only_ask_for_supported_tlvs() {
+    let gs105 = readable_cmds("GS105E");
+    assert!(gs105.contains(&Cmd::CMD_Port_Statistics));
+    assert!(!gs105.contains(&Cmd::CMD_FW_Version_2));
+    assert!(!gs105.contains(&Cmd::CMD_7400));
+    assert!(!gs105.contains(&Cmd::CMD_Password));
+
+    assert!(readable_cmds("GS108Ev3").contains(&Cmd::CMD_FW_Active));
+
+    //Unknown models are asked for everything readable
+    let unknown = readable_cmds("XS000");
+    assert!(unknown.contains(&Cmd::CMD_0002));
+    assert!(unknown.contains(&Cmd::CMD_7400));
+}
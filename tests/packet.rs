@@ -0,0 +1,130 @@
+use pputl::cmds::{Cmd, ProtoConsts, TLVReadingError, TypeLengthValue};
+use pputl::emulator::{EmulatedSwitch, STATUS_DENIED};
+use pputl::packet::{Packet, PacketType};
+use pputl::request::{Request, Session};
+use pputl::values::encode_password;
+
+const HOST_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x04, 0x00];
+const SWITCH_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x04, 0x01];
+
+fn request(ctype: ProtoConsts, cmds: Vec<TypeLengthValue>) -> Request {
+    cmds.into_iter()
+        .fold(
+            Request::builder()
+                .ctype(ctype.value().try_into().unwrap())
+                .session(Session::new(HOST_MAC, SWITCH_MAC, [0x00, 0x07])),
+            |builder, cmd| builder.add_cmd(cmd),
+        )
+        .build()
+}
+
+#[test]
+fn requests_round_trip() {
+    let requests = [
+        request(
+            ProtoConsts::QueryRequest,
+            vec![Cmd::CMD_Model.into(), Cmd::CMD_Port_Statistics.into()],
+        ),
+        request(
+            ProtoConsts::TransmitRequest,
+            vec![
+                TypeLengthValue::from((Cmd::CMD_Password, encode_password("password"))),
+                TypeLengthValue::from((Cmd::CMD_Name, b"desk".to_vec())),
+            ],
+        ),
+        request(ProtoConsts::QueryRequest, Vec::new()),
+    ];
+    for request in requests {
+        let datagram = request.format();
+        let parsed = Request::parse(&datagram).unwrap();
+        assert_eq!(parsed, request);
+        assert_eq!(parsed.format(), datagram);
+        assert_eq!(Packet::parse(&datagram).unwrap().format(), datagram);
+    }
+}
+
+#[test]
+fn every_packet_type_is_parsed() {
+    let types = [
+        (PacketType::QueryRequest, [0x01, 0x01], true),
+        (PacketType::QueryResponse, [0x01, 0x02], false),
+        (PacketType::TransmitRequest, [0x01, 0x03], true),
+        (PacketType::TransmitResponse, [0x01, 0x04], false),
+    ];
+    for (packet_type, ctype, is_request) in types {
+        let datagram = Packet::new(
+            packet_type,
+            Session::new(HOST_MAC, SWITCH_MAC, [0x00, 0x07]),
+        )
+        .with_cmd(Cmd::CMD_Model.into())
+        .format();
+        assert_eq!(datagram[0..2], ctype);
+        let packet = Packet::parse(&datagram).unwrap();
+        assert_eq!(packet.get_type(), packet_type);
+        assert_eq!(packet.get_type().is_request(), is_request);
+        assert_eq!(Request::parse(&datagram).is_ok(), is_request);
+    }
+}
+
+#[test]
+fn responses_are_no_requests() {
+    let mut datagram = request(ProtoConsts::QueryRequest, vec![Cmd::CMD_Model.into()]).format();
+    datagram[0..2].copy_from_slice(&[0x01, 0x02]);
+    assert!(matches!(
+        Request::parse(&datagram),
+        Err(TLVReadingError::InvalidType(_))
+    ));
+}
+
+#[test]
+fn malformed_packets_are_rejected() {
+    let datagram = request(ProtoConsts::QueryRequest, vec![Cmd::CMD_Model.into()]).format();
+
+    let mut unknown_type = datagram.clone();
+    unknown_type[0..2].copy_from_slice(&[0x01, 0x09]);
+    assert!(Packet::parse(&unknown_type).is_err());
+
+    let mut signature = datagram.clone();
+    signature[24..28].copy_from_slice(b"NSDQ");
+    assert!(Packet::parse(&signature).is_err());
+
+    let truncated = &datagram[..datagram.len() - 4];
+    assert!(matches!(
+        Packet::parse(truncated),
+        Err(TLVReadingError::ArrTooShort(_))
+    ));
+    assert!(Packet::parse(&datagram[..20]).is_err());
+}
+
+#[test]
+fn status_and_failed_tlv_survive_a_round_trip() {
+    let mut switch = EmulatedSwitch::new("GS105E", SWITCH_MAC, 5);
+    let transmit = request(
+        ProtoConsts::TransmitRequest,
+        vec![
+            TypeLengthValue::from((Cmd::CMD_Password, encode_password("wrong"))),
+            TypeLengthValue::from((Cmd::CMD_Name, b"desk".to_vec())),
+        ],
+    );
+    let reply = switch
+        .handle(&Packet::parse(&transmit.format()).unwrap())
+        .unwrap();
+    assert_eq!(reply.get_type(), PacketType::TransmitResponse);
+
+    let parsed = Packet::parse(&reply.format()).unwrap();
+    assert_eq!(parsed, reply);
+    assert!(!parsed.is_success());
+    assert_eq!(parsed.get_status(), STATUS_DENIED);
+    assert_eq!(parsed.get_failed_cmd(), <[u8; 2]>::from(Cmd::CMD_Password));
+}
+
+#[test]
+fn emulated_switches_ignore_responses() {
+    let mut switch = EmulatedSwitch::new("GS105E", SWITCH_MAC, 5);
+    let response = Packet::new(
+        PacketType::QueryResponse,
+        Session::new(HOST_MAC, SWITCH_MAC, [0x00, 0x07]),
+    )
+    .with_cmd(Cmd::CMD_Model.into());
+    assert!(switch.handle(&response).is_none());
+}
@@ -0,0 +1,138 @@
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use pputl::cmds::{Cmd, TypeLengthValue};
+use pputl::dispatch::{BindMode, PortPair};
+use pputl::emulator::{EmulatedSwitch, Emulator};
+use pputl::record::{load_captures, mask_passwords, Recorder, Replay};
+use pputl::values::{encode_password, from_hex};
+use pputl::BlockingClient;
+
+const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+const WAIT: Duration = Duration::from_millis(300);
+
+const MAC_1: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x02, 0x01];
+const MAC_2: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x02, 0x02];
+
+fn capture_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("pputl-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn client(emulator: &Emulator) -> BlockingClient {
+    let client =
+        BlockingClient::bind_with(&[SocketAddr::new(LOCALHOST, 0)], BindMode::Exclusive).unwrap();
+    let host = client.local_addrs().unwrap()[0].port();
+    client
+        .with_port_pairs(vec![PortPair {
+            host,
+            switch: emulator.local_addr().port(),
+        }])
+        .with_probe_hosts(vec![LOCALHOST])
+        .with_unicast(true)
+        .with_reply_timeout(WAIT)
+}
+
+//Discovers both switches, reads the ports of each and turns on loop detection on the first
+fn workflow(client: &BlockingClient) -> Vec<Vec<TypeLengthValue>> {
+    let mut switches = client.discover(WAIT).unwrap();
+    switches.sort_by_key(|switch| switch.mac_address);
+    assert_eq!(switches.len(), 2);
+
+    let mut answers = Vec::new();
+    for switch in &switches {
+        let resp = client
+            .query(switch, &[Cmd::CMD_Model, Cmd::CMD_Port_Status])
+            .unwrap();
+        answers.push(resp.get_cmds().clone());
+    }
+    let login = TypeLengthValue::from((Cmd::CMD_Password, encode_password("password")));
+    client
+        .transmit(
+            &switches[0],
+            &login,
+            vec![TypeLengthValue::from((Cmd::CMD_Loop_Detection, true))],
+        )
+        .unwrap();
+    let resp = client
+        .query(&switches[0], &[Cmd::CMD_Loop_Detection])
+        .unwrap();
+    answers.push(resp.get_cmds().clone());
+    answers
+}
+
+#[test]
+fn recorded_conversations_replay_byte_for_byte() {
+    let dir = capture_dir("replay");
+    let live = {
+        let emulator = Emulator::bind(
+            SocketAddr::new(LOCALHOST, 0),
+            vec![
+                EmulatedSwitch::new("GS105E", MAC_1, 5),
+                EmulatedSwitch::new("GS108Ev3", MAC_2, 8),
+            ],
+        )
+        .unwrap();
+        let client = client(&emulator).with_recorder(Recorder::create(&dir).unwrap());
+        workflow(&client)
+    };
+    assert_eq!(live[2][0].value(), [1]);
+
+    let mut files: Vec<String> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        vec!["02-00-00-00-02-01.toml", "02-00-00-00-02-02.toml"]
+    );
+    //Discovery, two queries and a transmit for the first switch
+    let exchanges = load_captures(&dir).unwrap();
+    assert_eq!(
+        exchanges
+            .iter()
+            .filter(|exchange| exchange.mac == "02:00:00:00:02:01")
+            .count(),
+        4
+    );
+
+    let replay =
+        Emulator::replay(SocketAddr::new(LOCALHOST, 0), Replay::load(&dir).unwrap()).unwrap();
+    let replayed = workflow(&client(&replay));
+    assert_eq!(replayed, live);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn passwords_are_blanked_in_captures() {
+    let dir = capture_dir("passwords");
+    let emulator = Emulator::bind(
+        SocketAddr::new(LOCALHOST, 0),
+        vec![EmulatedSwitch::new("GS105E", MAC_1, 5)],
+    )
+    .unwrap();
+    let client = client(&emulator).with_recorder(Recorder::create(&dir).unwrap());
+    let switch = client.discover(WAIT).unwrap().remove(0);
+    let password = encode_password("password");
+    client
+        .transmit(
+            &switch,
+            &TypeLengthValue::from((Cmd::CMD_Password, password.clone())),
+            vec![],
+        )
+        .unwrap();
+
+    let exchanges = load_captures(&dir).unwrap();
+    let transmit = from_hex(&exchanges.last().unwrap().request).unwrap();
+    assert!(!transmit
+        .windows(password.len())
+        .any(|window| window == password));
+    assert_eq!(transmit[32..36], [0x00, 0x0a, 0x00, 0x08]);
+    assert_eq!(transmit[36..44], [0; 8]);
+    assert_eq!(mask_passwords(&transmit), transmit);
+    fs::remove_dir_all(&dir).unwrap();
+}
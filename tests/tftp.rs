@@ -0,0 +1,63 @@
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+use pputl::tftp::{self, TftpServer};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn image(len: usize) -> Vec<u8> {
+    (0..len).map(|index| index as u8).collect()
+}
+
+fn server() -> (TftpServer, SocketAddr) {
+    let server = TftpServer::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)).unwrap();
+    let addr = server.local_addr().unwrap();
+    (server, addr)
+}
+
+#[test]
+fn images_are_fetched_whole() {
+    for len in [0, 1, 511, 513, 1500] {
+        let (server, addr) = server();
+        let sent = image(len);
+        let serving = thread::spawn(move || server.serve_once(&sent, TIMEOUT));
+
+        let fetched = tftp::fetch(addr, "GS105E.bin", TIMEOUT).unwrap();
+        let (_, file) = serving.join().unwrap().unwrap();
+        assert_eq!(fetched, image(len));
+        assert_eq!(file, "GS105E.bin");
+    }
+}
+
+//A last block of exactly 512 bytes doesn't end the transfer, an empty one has to follow
+#[test]
+fn images_of_whole_blocks_end_with_an_empty_block() {
+    let (server, addr) = server();
+    let serving = thread::spawn(move || server.serve_once(&image(1024), TIMEOUT));
+
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    socket.set_read_timeout(Some(TIMEOUT)).unwrap();
+    socket
+        .send_to(b"\x00\x01image.bin\x00octet\x00", addr)
+        .unwrap();
+
+    let mut blocks = Vec::new();
+    let mut buf = [0; 516];
+    loop {
+        let (len, transfer) = socket.recv_from(&mut buf).unwrap();
+        assert_eq!(buf[0..2], [0x00, 0x03]);
+        blocks.push((u16::from_be_bytes([buf[2], buf[3]]), len - 4));
+        socket
+            .send_to(&[0x00, 0x04, buf[2], buf[3]], transfer)
+            .unwrap();
+        if len - 4 < 512 {
+            break;
+        }
+    }
+
+    let (client, file) = serving.join().unwrap().unwrap();
+    assert_eq!(blocks, vec![(1, 512), (2, 512), (3, 0)]);
+    assert_eq!(client, socket.local_addr().unwrap());
+    assert_eq!(file, "image.bin");
+}